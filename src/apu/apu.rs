@@ -0,0 +1,466 @@
+#![allow(clippy::upper_case_acronyms)]
+use crate::apu::buffer::SampleBuffer;
+use crate::apu::dmc::Dmc;
+use crate::apu::noise::Noise;
+use crate::apu::pulse::Pulse;
+use crate::apu::triangle::Triangle;
+use crate::cpu::device::Device;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// NTSC CPU clock rate in Hz, the APU is clocked alongside the CPU
+pub static CPU_CLOCK_RATE: u64 = 1_789_773;
+
+// Cycles the CPU is suspended for while the DMC fetches a sample byte. The
+// hardware takes 4 in most cases, 3 if the fetch lands on a CPU write cycle
+// and 2 if it overlaps an OAM DMA. The CPU runs whole instructions here, so it
+// cannot tell which cycle the fetch lands on and always takes 4.
+//
+// Reference: https://www.nesdev.org/wiki/APU_DMC#Memory_reader
+pub static DMC_STALL_CYCLES: u16 = 4;
+
+// Registers
+pub const STATUS: u16 = 0x4015;
+pub const FRAME_COUNTER: u16 = 0x4017;
+
+// Frame counter step points in CPU cycles
+static QUARTER_FRAME_1: u32 = 7457;
+static HALF_FRAME_1: u32 = 14913;
+static QUARTER_FRAME_3: u32 = 22371;
+static FOUR_STEP_IRQ_START: u32 = 29828;
+static FOUR_STEP_HALF_FRAME_2: u32 = 29829;
+static FOUR_STEP_LENGTH: u32 = 29830;
+static FIVE_STEP_HALF_FRAME_2: u32 = 37281;
+static FIVE_STEP_LENGTH: u32 = 37282;
+
+// Audio processing unit (2A03), clocked once per CPU cycle.
+//
+// The pulse timers run at half the CPU rate, everything else at the CPU
+// rate. Quarter frames clock the envelopes and the triangle's linear
+// counter, half frames additionally clock the length counters and sweeps.
+//
+// Reference: https://www.nesdev.org/wiki/APU
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+
+    // Frame counter
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    odd_cycle: bool,
+
+    // Mixer lookup tables
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+
+    // Resampling to the output rate, averaging the mixer output between
+    // output samples
+    sample_rate: u64,
+    sample_clock: u64,
+    sample_sum: f32,
+    sample_count: u32,
+    samples: SampleBuffer,
+}
+
+impl APU {
+    // Samples are produced at sample_rate Hz (e.g. 44100 or 48000), one
+    // second of them is kept before the oldest are overwritten
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate > 0, "Sample rate must be non zero");
+
+        // Nonlinear mixer approximations
+        let mut pulse_table: [f32; 31] = [0.0; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table: [f32; 203] = [0.0; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+
+        Self {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            pulse_table,
+            tnd_table,
+            sample_rate: sample_rate as u64,
+            sample_clock: 0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            samples: SampleBuffer::new(sample_rate as usize),
+        }
+    }
+
+    // Reset silences all channels, as if $00 was written to $4015
+    pub fn reset(&mut self) {
+        self.write(STATUS, 0x00);
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+        self.samples.clear();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate as u32;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        assert!(sample_rate > 0, "Sample rate must be non zero");
+        self.sample_rate = sample_rate as u64;
+        self.sample_clock = 0;
+        self.samples = SampleBuffer::new(sample_rate as usize);
+    }
+
+    // Output samples in the range 0.0 -> 1.0, oldest first
+    pub fn samples(&mut self) -> &mut SampleBuffer {
+        return &mut self.samples;
+    }
+
+    // IRQ line level, from the frame counter or the end of a DMC sample
+    pub fn irq(&self) -> bool {
+        return self.frame_irq || self.dmc.irq;
+    }
+
+    // Advance the APU by a single CPU cycle
+    pub fn clock(&mut self) {
+        self.clock_frame_counter();
+
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        self.odd_cycle = !self.odd_cycle;
+
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= CPU_CLOCK_RATE {
+            self.sample_clock -= CPU_CLOCK_RATE;
+            self.samples
+                .push(self.sample_sum / self.sample_count as f32);
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let cycle: u32 = self.frame_cycle;
+
+        if cycle == QUARTER_FRAME_1 || cycle == QUARTER_FRAME_3 {
+            self.quarter_frame();
+        } else if cycle == HALF_FRAME_1 {
+            self.quarter_frame();
+            self.half_frame();
+        }
+
+        if self.five_step {
+            if cycle == FIVE_STEP_HALF_FRAME_2 {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            if cycle >= FIVE_STEP_LENGTH {
+                self.frame_cycle = 0;
+            }
+            return;
+        }
+
+        if cycle == FOUR_STEP_HALF_FRAME_2 {
+            self.quarter_frame();
+            self.half_frame();
+        }
+        if (FOUR_STEP_IRQ_START..=FOUR_STEP_LENGTH).contains(&cycle) && !self.irq_inhibit {
+            self.frame_irq = true;
+        }
+        if cycle >= FOUR_STEP_LENGTH {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    // Combine the channels through the nonlinear mixer
+    fn mix(&self) -> f32 {
+        let pulse: usize = (self.pulse1.output() + self.pulse2.output()) as usize;
+        let tnd: usize = 3 * self.triangle.output() as usize
+            + 2 * self.noise.output() as usize
+            + self.dmc.output() as usize;
+        return self.pulse_table[pulse] + self.tnd_table[tnd];
+    }
+
+    fn status(&self) -> u8 {
+        return (self.pulse1.length.active() as u8)
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7;
+    }
+}
+
+// Registers at $4000 -> $4013, $4015 and $4017 (writes only, $4017 reads
+// belong to the second controller port)
+impl Device for APU {
+    fn read(&mut self, address: u16) -> u8 {
+        if address != STATUS {
+            return 0x00;
+        }
+        // Reading the status acknowledges the frame interrupt
+        let status: u8 = self.status();
+        self.frame_irq = false;
+        return status;
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write(address - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write(address - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write(address - 0x4008, data),
+            0x400C..=0x400F => self.noise.write(address - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write(address - 0x4010, data),
+            STATUS => {
+                self.pulse1.length.set_enabled(data & 0x01 != 0);
+                self.pulse2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            FRAME_COUNTER => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // The 5 step sequence clocks everything straight away
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return ternary!(address == STATUS, Some(self.status()), None);
+    }
+
+    // Bit 5 of the status isn't driven, the other registers are write only
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        return ternary!(address == STATUS, 0x20, 0xFF);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.load(state);
+    }
+}
+
+impl Snapshot for APU {
+    fn save(&self, state: &mut StateWriter) {
+        self.pulse1.save(state);
+        self.pulse2.save(state);
+        self.triangle.save(state);
+        self.noise.save(state);
+        self.dmc.save(state);
+        state.bool(self.five_step);
+        state.bool(self.irq_inhibit);
+        state.bool(self.frame_irq);
+        state.u32(self.frame_cycle);
+        state.bool(self.odd_cycle);
+        state.u64(self.sample_clock);
+        state.f32(self.sample_sum);
+        state.u32(self.sample_count);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load(state)?;
+        self.pulse2.load(state)?;
+        self.triangle.load(state)?;
+        self.noise.load(state)?;
+        self.dmc.load(state)?;
+        self.five_step = state.bool()?;
+        self.irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.frame_cycle = state.u32()?;
+        self.odd_cycle = state.bool()?;
+        self.sample_clock = state.u64()?;
+        self.sample_sum = state.f32()?;
+        self.sample_count = state.u32()?;
+        // Samples already produced belong to the host, not the machine
+        self.samples.clear();
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cpu::bus::{self, Bus};
+    use crate::cpu::cpu::CPU;
+
+    // Pulse 1 length counter loaded with 2, silenced after two half frames
+    static LENGTH_2: u8 = 0x18;
+
+    // APU on a bus of its own, so reads go through the open bus handling
+    fn setup() -> (Bus, Rc<RefCell<APU>>) {
+        let apu: Rc<RefCell<APU>> = Rc::new(RefCell::new(APU::new(44100)));
+        let mut bus: Bus = Bus::new();
+        bus.attach(bus::APU_IO_START, bus::APU_IO_END, Box::new(apu.clone()));
+        return (bus, apu);
+    }
+
+    fn clock(apu: &Rc<RefCell<APU>>, cycles: u32) {
+        for _ in 0..cycles {
+            apu.borrow_mut().clock();
+        }
+    }
+
+    #[test]
+    fn status_bit_5_and_write_only_registers_are_open_bus() {
+        let (mut bus, _apu) = setup();
+
+        // Writes to the unmapped test registers only leave a value on the bus
+        bus.write(0x4018, 0xFF);
+        assert_eq!(bus.read(0x4015), 0x20);
+        bus.write(0x4018, 0xDF);
+        assert_eq!(bus.read(0x4015), 0x00);
+        bus.write(0x4018, 0x20);
+        assert_eq!(bus.peek(0x4015), Some(0x20));
+
+        // Channel status is driven over the open bus
+        bus.write(0x4015, 0x01);
+        bus.write(0x4003, LENGTH_2);
+        bus.write(0x4018, 0xDE);
+        assert_eq!(bus.read(0x4015), 0x01);
+
+        bus.write(0x4018, 0xA5);
+        assert_eq!(bus.read(0x4000), 0xA5);
+        assert_eq!(bus.read(0x4013), 0xA5);
+        assert_eq!(bus.peek(0x4000), None);
+    }
+
+    #[test]
+    fn frame_irq_is_acknowledged_by_status_reads() {
+        let (mut bus, apu) = setup();
+
+        // Raised at the end of the 4 step sequence
+        clock(&apu, 29827);
+        assert!(!apu.borrow().irq());
+        clock(&apu, 1);
+        assert!(apu.borrow().irq());
+        assert_eq!(bus.peek(0x4015), Some(0x40));
+        assert!(apu.borrow().irq());
+
+        // Raised again on the last two cycles, until the status is read
+        clock(&apu, 2);
+        assert_eq!(bus.read(0x4015), 0x40);
+        assert!(!apu.borrow().irq());
+        assert_eq!(bus.read(0x4015), 0x00);
+        clock(&apu, 29827);
+        assert!(!apu.borrow().irq());
+        clock(&apu, 1);
+        assert!(apu.borrow().irq());
+
+        // Setting the inhibit flag clears it and keeps it from being raised
+        bus.write(0x4017, 0x40);
+        assert!(!apu.borrow().irq());
+        clock(&apu, 2 * 29830);
+        assert!(!apu.borrow().irq());
+
+        // The 5 step sequence never raises it
+        bus.write(0x4017, 0x80);
+        clock(&apu, 3 * 37282);
+        assert!(!apu.borrow().irq());
+    }
+
+    #[test]
+    fn four_and_five_step_half_frame_timing() {
+        let (mut bus, apu) = setup();
+        bus.write(0x4015, 0x01);
+
+        // Half frames 14913 and 29829 cycles after the $4017 write
+        bus.write(0x4017, 0x00);
+        bus.write(0x4003, LENGTH_2);
+        clock(&apu, 14912);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x01);
+        clock(&apu, 14916);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x01);
+        clock(&apu, 1);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x00);
+
+        // The 5 step sequence has one on the write, then at 14913 and 37281
+        bus.write(0x4003, LENGTH_2);
+        bus.write(0x4017, 0x80);
+        clock(&apu, 14912);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x01);
+        clock(&apu, 1);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x00);
+
+        bus.write(0x4017, 0x80);
+        bus.write(0x4003, LENGTH_2);
+        clock(&apu, 14913 + 14916);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x01);
+        clock(&apu, 37280 - 29829);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x01);
+        clock(&apu, 1);
+        assert_eq!(bus.read(0x4015) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn dmc_fetch_stalls_the_cpu() {
+        let (mut bus, apu) = setup();
+        let mut cpu: CPU = CPU::new();
+
+        // A single byte sample at $C000
+        bus.write(0x4012, 0x00);
+        bus.write(0x4013, 0x00);
+        bus.write(0x4015, 0x10);
+
+        // Always 4 cycles, the 3 and 2 cycle cases (a fetch on a write cycle
+        // or during OAM DMA) are not told apart
+        cpu.service_dmc(&mut apu.borrow_mut());
+        assert_eq!(cpu.stall_cycles, 4);
+
+        // Nothing more to fetch until the sample buffer is emptied
+        cpu.service_dmc(&mut apu.borrow_mut());
+        assert_eq!(cpu.stall_cycles, 4);
+    }
+}
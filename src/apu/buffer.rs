@@ -0,0 +1,64 @@
+// Fixed capacity ring buffer of audio samples. When the consumer falls
+// behind the oldest samples are overwritten.
+pub struct SampleBuffer {
+    samples: Vec<f32>,
+    read: usize,
+    len: usize,
+}
+
+impl SampleBuffer {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Sample buffer capacity must be non zero");
+        Self {
+            samples: vec![0.0; capacity],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.samples.len();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let write: usize = (self.read + self.len) % self.capacity();
+        self.samples[write] = sample;
+        if self.len == self.capacity() {
+            self.read = (self.read + 1) % self.capacity();
+        } else {
+            self.len += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<f32> {
+        if self.len == 0 {
+            return None;
+        }
+        let sample: f32 = self.samples[self.read];
+        self.read = (self.read + 1) % self.capacity();
+        self.len -= 1;
+        return Some(sample);
+    }
+
+    // Move up to out.len() samples into out, returning how many were written
+    pub fn drain_into(&mut self, out: &mut [f32]) -> usize {
+        let count: usize = out.len().min(self.len);
+        for sample in out.iter_mut().take(count) {
+            *sample = self.pop().unwrap_or(0.0);
+        }
+        return count;
+    }
+
+    pub fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+}
@@ -0,0 +1,200 @@
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Timer periods in CPU cycles (NTSC)
+static DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// Delta modulation channel ($4010 -> $4013). Plays 1 bit delta encoded
+// samples fetched from $8000 -> $FFFF by DMA, which stalls the CPU.
+pub struct Dmc {
+    pub irq_enabled: bool,
+    pub irq: bool,
+    looping: bool,
+    timer: u16,
+    period: u16,
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    level: u8,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer: 0,
+            period: DMC_PERIODS[0],
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            level: 0,
+        }
+    }
+
+    // Register write, address is the offset from $4010
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.period = DMC_PERIODS[(data & 0x0F) as usize];
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            _ => self.sample_length = ((data as u16) << 4) | 0x0001,
+        }
+    }
+
+    // Enable or disable playback through $4015. Enabling restarts the
+    // sample only once the previous one has finished.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        return self.bytes_remaining > 0;
+    }
+
+    // Address of the next sample byte when the buffer needs refilling
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            return Some(self.current_address);
+        }
+        return None;
+    }
+
+    // Complete a DMA started from dma_request
+    pub fn dma_fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = ternary!(
+            self.current_address == 0xFFFF,
+            0x8000,
+            self.current_address + 1
+        );
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return self.level;
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.irq_enabled);
+        state.bool(self.irq);
+        state.bool(self.looping);
+        state.u16(self.timer);
+        state.u16(self.period);
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.current_address);
+        state.u16(self.bytes_remaining);
+        state.bool(self.sample_buffer.is_some());
+        state.u8(self.sample_buffer.unwrap_or(0x00));
+        state.u8(self.shift);
+        state.u8(self.bits_remaining);
+        state.bool(self.silence);
+        state.u8(self.level);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.bool()?;
+        self.irq = state.bool()?;
+        self.looping = state.bool()?;
+        self.timer = state.u16()?;
+        self.period = state.u16()?;
+        // The timer is reloaded with period - 1
+        if self.period == 0 {
+            return Err(StateError::Invalid("DMC period"));
+        }
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.current_address = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let buffered: bool = state.bool()?;
+        let sample: u8 = state.u8()?;
+        self.sample_buffer = ternary!(buffered, Some(sample), None);
+        self.shift = state.u8()?;
+        self.bits_remaining = state.u8()?;
+        self.silence = state.bool()?;
+        self.level = state.u8()?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Volume envelope shared by the pulse and noise channels. Produces either a
+// constant volume or a decaying saw, clocked on quarter frames.
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool, // doubles as the length counter halt flag
+    pub constant: bool,
+    pub volume: u8, // constant volume, or the divider period
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    // Set from the low 6 bits of $4000/$4004/$400C
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return ternary!(self.constant, self.volume, self.decay);
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.start);
+        state.bool(self.looping);
+        state.bool(self.constant);
+        state.u8(self.volume);
+        state.u8(self.divider);
+        state.u8(self.decay);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.bool()?;
+        self.looping = state.bool()?;
+        self.constant = state.bool()?;
+        self.volume = state.u8()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        return Ok(());
+    }
+}
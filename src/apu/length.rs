@@ -0,0 +1,71 @@
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// Length counter values indexed by the 5 bit value written to a channel's
+// length register
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel after a set duration, clocked on half frames
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    // Load the counter from the table, only while the channel is enabled
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    // Enable or disable the channel through $4015, disabling clears the count
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        return self.counter > 0;
+    }
+}
+
+impl Default for LengthCounter {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.halt);
+        state.u8(self.counter);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.halt = state.bool()?;
+        self.counter = state.u8()?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,9 @@
+#[allow(clippy::module_inception)]
+pub mod apu;
+pub mod buffer;
+pub mod dmc;
+pub mod envelope;
+pub mod length;
+pub mod noise;
+pub mod pulse;
+pub mod triangle;
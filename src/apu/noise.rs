@@ -0,0 +1,102 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Timer periods in CPU cycles (NTSC)
+static NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// Pseudo random noise channel ($400C -> $400F)
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+
+    mode: bool, // short mode, feedback from bit 6 instead of bit 1
+    shift: u16, // 15 bit linear feedback shift register
+    timer: u16,
+    period: u16,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            mode: false,
+            shift: 0x0001,
+            timer: 0,
+            period: NOISE_PERIODS[0],
+        }
+    }
+
+    // Register write, address is the offset from $400C
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.envelope.write(data);
+                self.length.halt = data & 0x20 != 0;
+            }
+            1 => {}
+            2 => {
+                self.mode = data & 0x80 != 0;
+                self.period = NOISE_PERIODS[(data & 0x0F) as usize];
+            }
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        let tap: u16 = ternary!(self.mode, 6, 1);
+        let feedback: u16 = (self.shift ^ (self.shift >> tap)) & 0x0001;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 0x0001 != 0 {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for Noise {
+    fn save(&self, state: &mut StateWriter) {
+        self.envelope.save(state);
+        self.length.save(state);
+        state.bool(self.mode);
+        state.u16(self.shift);
+        state.u16(self.timer);
+        state.u16(self.period);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load(state)?;
+        Snapshot::load(&mut self.length, state)?;
+        self.mode = state.bool()?;
+        self.shift = state.u16()?;
+        self.timer = state.u16()?;
+        self.period = state.u16()?;
+        // The timer is reloaded with period - 1
+        if self.period == 0 {
+            return Err(StateError::Invalid("noise period"));
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Waveforms for the 4 duty cycles (12.5%, 25%, 50% and 25% negated)
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Square wave channel ($4000 -> $4003 and $4004 -> $4007)
+pub struct Pulse {
+    channel: u8, // 1 or 2, the sweep units differ in how they negate
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+
+    duty: u8,
+    sequence: u8,
+    timer: u16,
+    period: u16,
+
+    // Sweep unit
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: u8) -> Self {
+        Self {
+            channel,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            duty: 0,
+            sequence: 0,
+            timer: 0,
+            period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // Register write, address is the offset from the channel's first register
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+                self.length.halt = data & 0x20 != 0;
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((data & 0x07) as u16) << 8);
+                self.length.load(data >> 3);
+                self.sequence = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // Clocked every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequence = (self.sequence + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Period the sweep unit is moving towards. Pulse 1 negates with the
+    // ones' complement, so it subtracts one more than pulse 2.
+    fn sweep_target(&self) -> u16 {
+        let change: u16 = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            return self.period + change;
+        }
+        let change: u16 = change + ternary!(self.channel == 1, 1, 0);
+        return self.period.saturating_sub(change);
+    }
+
+    // Periods below 8 or targets past $7FF silence the channel, whether or
+    // not the sweep is enabled
+    fn sweep_muted(&self) -> bool {
+        return self.period < 8 || self.sweep_target() > 0x07FF;
+    }
+
+    // Clocked on half frames
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift > 0
+            && !self.sweep_muted()
+        {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.sweep_muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+        {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        self.envelope.save(state);
+        self.length.save(state);
+        state.u8(self.duty);
+        state.u8(self.sequence);
+        state.u16(self.timer);
+        state.u16(self.period);
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_period);
+        state.bool(self.sweep_negate);
+        state.u8(self.sweep_shift);
+        state.u8(self.sweep_divider);
+        state.bool(self.sweep_reload);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load(state)?;
+        Snapshot::load(&mut self.length, state)?;
+        self.duty = state.u8()?;
+        if self.duty as usize >= DUTY_TABLE.len() {
+            return Err(StateError::Invalid("pulse duty"));
+        }
+        self.sequence = state.u8()?;
+        if self.sequence as usize >= DUTY_TABLE[0].len() {
+            return Err(StateError::Invalid("pulse sequence step"));
+        }
+        self.timer = state.u16()?;
+        self.period = state.u16()?;
+        self.sweep_enabled = state.bool()?;
+        self.sweep_period = state.u8()?;
+        self.sweep_negate = state.bool()?;
+        self.sweep_shift = state.u8()?;
+        self.sweep_divider = state.u8()?;
+        self.sweep_reload = state.bool()?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::apu::length::LengthCounter;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// 32 step sequence, 15 down to 0 and back up
+static TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// Triangle wave channel ($4008 -> $400B)
+pub struct Triangle {
+    pub length: LengthCounter,
+
+    sequence: u8,
+    timer: u16,
+    period: u16,
+
+    // Linear counter, a finer grained second length counter
+    control: bool, // doubles as the length counter halt flag
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self {
+            length: LengthCounter::new(),
+            sequence: 0,
+            timer: 0,
+            period: 0,
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+        }
+    }
+
+    // Register write, address is the offset from $4008
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((data & 0x07) as u16) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle. The sequencer only moves while both counters
+    // are non zero, holding the last level otherwise.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.sequence = (self.sequence + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked on quarter frames
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return TRIANGLE_SEQUENCE[self.sequence as usize];
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, state: &mut StateWriter) {
+        self.length.save(state);
+        state.u8(self.sequence);
+        state.u16(self.timer);
+        state.u16(self.period);
+        state.bool(self.control);
+        state.u8(self.linear_reload_value);
+        state.u8(self.linear_counter);
+        state.bool(self.linear_reload);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        Snapshot::load(&mut self.length, state)?;
+        self.sequence = state.u8()?;
+        if self.sequence as usize >= TRIANGLE_SEQUENCE.len() {
+            return Err(StateError::Invalid("triangle sequence step"));
+        }
+        self.timer = state.u16()?;
+        self.period = state.u16()?;
+        self.control = state.bool()?;
+        self.linear_reload_value = state.u8()?;
+        self.linear_counter = state.u8()?;
+        self.linear_reload = state.bool()?;
+        return Ok(());
+    }
+}
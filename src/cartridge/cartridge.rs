@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cartridge::ines::{INesHeader, Mirroring, RomError, HEADER_SIZE, TRAINER_SIZE};
+use crate::cartridge::mappers::{self, Mapper, PrgMapping};
+use crate::cpu::device::Device;
+use crate::savestate::savestate::{self, Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+static TRAINER_OFFSET: usize = 0x1000; // trainer is loaded at $7000
+
+// Game cartridge built from an iNES / NES 2.0 ROM file
+pub struct Cartridge {
+    pub header: INesHeader,
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>, // CHR ROM, or CHR RAM when the board has no CHR ROM
+    pub prg_ram: Vec<u8>,
+    pub chr_is_ram: bool,
+    trainer: Option<Vec<u8>>, // loaded into PRG RAM at $7000
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    // Load a ROM file from disk
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RomError> {
+        let data: Vec<u8> = fs::read(path)?;
+        return Self::from_bytes(&data);
+    }
+
+    // Build a cartridge from the raw contents of a ROM file
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RomError> {
+        let header: INesHeader = INesHeader::parse(data)?;
+        let file_size: usize = header.file_size()?;
+        if data.len() < file_size {
+            return Err(RomError::Truncated {
+                expected: file_size,
+                actual: data.len(),
+            });
+        }
+
+        let mut offset: usize = HEADER_SIZE;
+        let mut prg_ram: Vec<u8> = vec![0x00; header.prg_ram_size + header.prg_nvram_size];
+
+        // The trainer is copied into PRG RAM at $7000 before the game starts
+        let mut trainer: Option<Vec<u8>> = None;
+        if header.trainer {
+            if prg_ram.len() < TRAINER_OFFSET + TRAINER_SIZE {
+                prg_ram.resize(TRAINER_OFFSET + TRAINER_SIZE, 0x00);
+            }
+            trainer = Some(data[offset..offset + TRAINER_SIZE].to_vec());
+            offset += TRAINER_SIZE;
+        }
+
+        let prg_rom: Vec<u8> = data[offset..offset + header.prg_rom_size].to_vec();
+        offset += header.prg_rom_size;
+
+        let chr_is_ram: bool = header.chr_rom_size == 0;
+        let chr: Vec<u8> = if chr_is_ram {
+            vec![0x00; header.chr_ram_size + header.chr_nvram_size]
+        } else {
+            data[offset..offset + header.chr_rom_size].to_vec()
+        };
+
+        let mapper: Box<dyn Mapper> = mappers::create(&header, prg_rom.len(), chr.len())?;
+
+        let mut cartridge: Self = Self {
+            header,
+            prg_rom,
+            chr,
+            prg_ram,
+            chr_is_ram,
+            trainer,
+            mapper,
+        };
+        cartridge.load_trainer();
+        return Ok(cartridge);
+    }
+
+    fn load_trainer(&mut self) {
+        if let Some(trainer) = &self.trainer {
+            self.prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE].copy_from_slice(trainer);
+        }
+    }
+
+    // Back to the state of a freshly loaded cartridge: RAM cleared, battery
+    // backed or not, and the mapper registers at their power up values
+    pub fn erase(&mut self) {
+        self.prg_ram.fill(0x00);
+        self.load_trainer();
+        if self.chr_is_ram {
+            self.chr.fill(0x00);
+        }
+        self.power_on();
+    }
+
+    // Mapper registers back to their power up values. PRG RAM is kept, it
+    // is battery backed on the boards with it.
+    pub fn power_on(&mut self) {
+        self.mapper = mappers::create(&self.header, self.prg_rom.len(), self.chr.len())
+            .expect("Mapper was created when the cartridge was loaded");
+    }
+
+    // Identifies the ROM, save states only load into the cartridge they
+    // were saved from
+    pub fn hash(&self) -> u64 {
+        let mut rom: Vec<u8> = self.prg_rom.clone();
+        if !self.chr_is_ram {
+            rom.extend_from_slice(&self.chr);
+        }
+        return savestate::hash(&rom);
+    }
+
+    pub fn mapper(&self) -> u16 {
+        return self.header.mapper;
+    }
+
+    // Current nametable mirroring, mappers may override the header
+    pub fn mirroring(&self) -> Mirroring {
+        return self.mapper.mirroring().unwrap_or(self.header.mirroring);
+    }
+
+    // Whether the mapper is asserting the CPU IRQ line
+    pub fn irq(&self) -> bool {
+        return self.mapper.irq();
+    }
+
+    // Read from the pattern tables ($0000 -> $1FFF) on the PPU bus
+    pub fn ppu_read(&mut self, address: u16) -> u8 {
+        self.mapper.notify_ppu_address(address);
+        return self.ppu_peek(address);
+    }
+
+    // Pattern table read without notifying the mapper
+    pub fn ppu_peek(&self, address: u16) -> u8 {
+        return self
+            .mapper
+            .ppu_map_read(address)
+            .and_then(|offset| self.chr.get(offset))
+            .copied()
+            .unwrap_or(0x00);
+    }
+
+    // Write to the pattern tables, only CHR RAM is writable
+    pub fn ppu_write(&mut self, address: u16, data: u8) {
+        self.mapper.notify_ppu_address(address);
+        if !self.chr_is_ram {
+            return;
+        }
+        if let Some(offset) = self.mapper.ppu_map_write(address) {
+            if let Some(cell) = self.chr.get_mut(offset) {
+                *cell = data;
+            }
+        }
+    }
+
+    // Byte at a resolved PRG location, None when nothing is there
+    fn prg_cell(&self, mapping: PrgMapping) -> Option<u8> {
+        return match mapping {
+            PrgMapping::Rom(offset) => self.prg_rom.get(offset).copied(),
+            PrgMapping::Ram(offset) if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[offset % self.prg_ram.len()])
+            }
+            PrgMapping::Ram(_) => None,
+        };
+    }
+}
+
+// Cartridge space $4020 -> $FFFF as seen by the CPU
+impl Device for Cartridge {
+    fn read(&mut self, address: u16) -> u8 {
+        return self.peek(address).unwrap_or(0x00);
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if let Some(PrgMapping::Ram(offset)) = self.mapper.cpu_map_write(address, data) {
+            if !self.prg_ram.is_empty() {
+                let length: usize = self.prg_ram.len();
+                self.prg_ram[offset % length] = data;
+            }
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return self
+            .mapper
+            .cpu_map_read(address)
+            .and_then(|mapping| self.prg_cell(mapping));
+    }
+
+    // Nothing answers where no memory is mapped, the bus keeps its value
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        return ternary!(self.peek(address).is_none(), 0xFF, 0x00);
+    }
+
+    fn cpu_clock(&mut self) {
+        self.mapper.cpu_clock();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.load(state);
+    }
+}
+
+// Cartridge RAM and the mapper's registers, the ROM is identified by hash()
+impl Snapshot for Cartridge {
+    fn save(&self, state: &mut StateWriter) {
+        state.block(&self.prg_ram);
+        state.block(ternary!(self.chr_is_ram, &self.chr[..], &[]));
+        state.section(|mapper| self.mapper.save(mapper));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.block(&mut self.prg_ram, "PRG RAM size")?;
+        let chr: &mut [u8] = ternary!(self.chr_is_ram, &mut self.chr[..], &mut []);
+        state.block(chr, "CHR RAM size")?;
+        let mapper: &mut Box<dyn Mapper> = &mut self.mapper;
+        return state.section(|state| mapper.load(state));
+    }
+}
@@ -0,0 +1,214 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::ternary;
+
+// iNES / NES 2.0 file header
+// Reference: https://www.nesdev.org/wiki/INES and https://www.nesdev.org/wiki/NES_2.0
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_BANK_SIZE: usize = 0x4000; // 16KB
+pub const CHR_ROM_BANK_SIZE: usize = 0x2000; // 8KB
+pub const PRG_RAM_BANK_SIZE: usize = 0x2000; // 8KB
+
+static MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+
+// Flags 6
+static FLAG_VERTICAL_MIRRORING: u8 = 1 << 0;
+static FLAG_BATTERY: u8 = 1 << 1;
+static FLAG_TRAINER: u8 = 1 << 2;
+static FLAG_FOUR_SCREEN: u8 = 1 << 3;
+
+// Flags 7, bits 2 and 3 identify the header format
+static FORMAT_MASK: u8 = 0x0C;
+static FORMAT_NES2: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+}
+
+// Nametable arrangement wired up by the cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower, // only selectable by mappers
+    SingleScreenUpper,
+}
+
+// Errors raised while parsing a ROM file
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    InvalidMagic,
+    Truncated { expected: usize, actual: usize },
+    InvalidSize(&'static str),
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RomError::Io(e) => write!(f, "Unable to read ROM file: {}", e),
+            RomError::InvalidMagic => write!(f, "Not an iNES file, missing NES<EOF> magic"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "ROM file is truncated, expected {} bytes but found {}",
+                expected, actual
+            ),
+            RomError::InvalidSize(field) => write!(f, "Header declares an invalid {} size", field),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+        };
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        return match self {
+            RomError::Io(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> Self {
+        return RomError::Io(e);
+    }
+}
+
+// Decoded header, all sizes are in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct INesHeader {
+    pub format: RomFormat,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+}
+
+impl INesHeader {
+    // Parse the 16 byte header at the start of a ROM file
+    pub fn parse(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+        if data[0..4] != MAGIC {
+            return Err(RomError::InvalidMagic);
+        }
+
+        let flags6: u8 = data[6];
+        let flags7: u8 = data[7];
+        let format: RomFormat = ternary!(
+            flags7 & FORMAT_MASK == FORMAT_NES2,
+            RomFormat::Nes2,
+            RomFormat::INes
+        );
+
+        let mirroring: Mirroring = if flags6 & FLAG_FOUR_SCREEN != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & FLAG_VERTICAL_MIRRORING != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut header = Self {
+            format,
+            prg_rom_size: data[4] as usize * PRG_ROM_BANK_SIZE,
+            chr_rom_size: data[5] as usize * CHR_ROM_BANK_SIZE,
+            mapper: ((flags7 & 0xF0) | (flags6 >> 4)) as u16,
+            submapper: 0,
+            mirroring,
+            battery: flags6 & FLAG_BATTERY != 0,
+            trainer: flags6 & FLAG_TRAINER != 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+        };
+
+        match format {
+            RomFormat::Nes2 => {
+                header.mapper |= ((data[8] & 0x0F) as u16) << 8;
+                header.submapper = data[8] >> 4;
+                header.prg_rom_size =
+                    Self::nes2_rom_size(data[4], data[9] & 0x0F, PRG_ROM_BANK_SIZE, "PRG ROM")?;
+                header.chr_rom_size =
+                    Self::nes2_rom_size(data[5], data[9] >> 4, CHR_ROM_BANK_SIZE, "CHR ROM")?;
+                header.prg_ram_size = Self::nes2_ram_size(data[10] & 0x0F);
+                header.prg_nvram_size = Self::nes2_ram_size(data[10] >> 4);
+                header.chr_ram_size = Self::nes2_ram_size(data[11] & 0x0F);
+                header.chr_nvram_size = Self::nes2_ram_size(data[11] >> 4);
+            }
+            RomFormat::INes => {
+                // Old dumping tools wrote their name into bytes 12-15 (e.g.
+                // "DiskDude!"), in which case the upper mapper nibble is junk
+                if flags7 & FORMAT_MASK == 0 && data[12..16].iter().any(|&b| b != 0) {
+                    header.mapper &= 0x000F;
+                }
+                // A PRG RAM size of 0 infers 8KB for compatibility
+                let prg_ram_size: usize = data[8].max(1) as usize * PRG_RAM_BANK_SIZE;
+                if header.battery {
+                    header.prg_nvram_size = prg_ram_size;
+                } else {
+                    header.prg_ram_size = prg_ram_size;
+                }
+                // Boards without CHR ROM have 8KB of CHR RAM instead
+                if header.chr_rom_size == 0 {
+                    header.chr_ram_size = CHR_ROM_BANK_SIZE;
+                }
+            }
+        }
+
+        return Ok(header);
+    }
+
+    // NES 2.0 ROM size from the LSB in bytes 4/5 and the MSB nibble in
+    // byte 9. An MSB of 0xF switches to exponent-multiplier notation,
+    // where the LSB is EEEEEEMM and the size is 2^E * (MM * 2 + 1).
+    fn nes2_rom_size(
+        lsb: u8,
+        msb: u8,
+        unit: usize,
+        field: &'static str,
+    ) -> Result<usize, RomError> {
+        if msb != 0x0F {
+            return Ok((((msb as usize) << 8) | lsb as usize) * unit);
+        }
+        let exponent: u32 = (lsb >> 2) as u32;
+        let multiplier: usize = (lsb & 0x03) as usize * 2 + 1;
+        return 1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or(RomError::InvalidSize(field));
+    }
+
+    // NES 2.0 RAM sizes are stored as a shift count, 64 << shift (0 = none)
+    fn nes2_ram_size(shift: u8) -> usize {
+        return ternary!(shift == 0, 0, 64usize << shift);
+    }
+
+    // Total number of bytes the file must contain. Exponent notation can
+    // declare sizes no file could have, which overflow the sum.
+    pub fn file_size(&self) -> Result<usize, RomError> {
+        return (HEADER_SIZE + ternary!(self.trainer, TRAINER_SIZE, 0))
+            .checked_add(self.prg_rom_size)
+            .and_then(|size| size.checked_add(self.chr_rom_size))
+            .ok_or(RomError::InvalidSize("ROM"));
+    }
+}
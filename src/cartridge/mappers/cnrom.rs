@@ -0,0 +1,58 @@
+use crate::cartridge::mappers::{self, Mapper, PrgMapping, PRG_ROM_START};
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+static CHR_BANK_SIZE: usize = 0x2000;
+
+// Mapper 3 (CNROM): fixed 16KB or 32KB of PRG ROM and a switchable 8KB
+// CHR bank
+pub struct CnRom {
+    prg_mask: u16,
+    chr_banks: usize,
+    chr_bank: usize,
+}
+
+impl CnRom {
+    pub fn new(prg_rom_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_mask: ternary!(prg_rom_size > 0x4000, 0x7FFF, 0x3FFF),
+            chr_banks: mappers::bank_count(chr_size, CHR_BANK_SIZE),
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping> {
+        if address >= PRG_ROM_START {
+            return Some(PrgMapping::Rom((address & self.prg_mask) as usize));
+        }
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn cpu_map_write(&mut self, address: u16, data: u8) -> Option<PrgMapping> {
+        if address >= PRG_ROM_START {
+            self.chr_bank = (data & 0x03) as usize % self.chr_banks;
+            return None;
+        }
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn ppu_map_read(&self, address: u16) -> Option<usize> {
+        if address < 0x2000 {
+            return Some(self.chr_bank * CHR_BANK_SIZE + address as usize);
+        }
+        return None;
+    }
+}
+
+impl Snapshot for CnRom {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.chr_bank as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.chr_bank = state.u8()? as usize % self.chr_banks;
+        return Ok(());
+    }
+}
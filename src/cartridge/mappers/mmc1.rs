@@ -0,0 +1,163 @@
+use crate::cartridge::ines::Mirroring;
+use crate::cartridge::mappers::{self, Mapper, PrgMapping, PRG_ROM_START};
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+static PRG_BANK_SIZE: usize = 0x4000;
+static CHR_BANK_SIZE: usize = 0x1000;
+
+// Shift register is empty when only the marker bit is left in it
+static SHIFT_RESET: u8 = 0x10;
+
+// Mapper 1 (MMC1 / SxROM). Registers are loaded serially, one bit per write
+// to $8000 -> $FFFF, and the fifth write commits the value to the register
+// selected by address bits 13 and 14. A write on the cycle right after
+// another one is ignored, so the dummy write of a read-modify-write
+// instruction is the only one that counts.
+pub struct Mmc1 {
+    prg_banks: usize, // 16KB banks
+    chr_banks: usize, // 4KB banks
+
+    shift: u8,
+    control: u8,    // $8000: mirroring, PRG and CHR bank modes
+    chr_bank_0: u8, // $A000
+    chr_bank_1: u8, // $C000
+    prg_bank: u8,   // $E000: PRG bank and PRG RAM disable
+
+    cycle: u64,              // CPU cycles clocked
+    last_write: Option<u64>, // cycle of the last write to $8000 -> $FFFF
+}
+
+impl Mmc1 {
+    pub fn new(prg_rom_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_banks: mappers::bank_count(prg_rom_size, PRG_BANK_SIZE),
+            chr_banks: mappers::bank_count(chr_size, CHR_BANK_SIZE),
+            shift: SHIFT_RESET,
+            // Power on with the last PRG bank fixed at $C000
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address & 0x6000 {
+            0x0000 => self.control = value,
+            0x2000 => self.chr_bank_0 = value,
+            0x4000 => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.prg_bank & 0x10 == 0;
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping> {
+        if address < PRG_ROM_START {
+            return mappers::prg_ram_mapping(address).filter(|_| self.prg_ram_enabled());
+        }
+        let selected: usize = (self.prg_bank & 0x0F) as usize;
+        let upper: bool = address >= 0xC000;
+        let bank: usize = match (self.control >> 2) & 0x03 {
+            // 32KB mode, the low bit of the bank number is ignored
+            0 | 1 => (selected & 0x0E) + upper as usize,
+            // First bank fixed at $8000, switchable bank at $C000
+            2 => ternary!(upper, selected, 0),
+            // Switchable bank at $8000, last bank fixed at $C000
+            _ => ternary!(upper, self.prg_banks - 1, selected),
+        };
+        return Some(PrgMapping::Rom(
+            (bank % self.prg_banks) * PRG_BANK_SIZE + (address & 0x3FFF) as usize,
+        ));
+    }
+
+    fn cpu_map_write(&mut self, address: u16, data: u8) -> Option<PrgMapping> {
+        if address < PRG_ROM_START {
+            return mappers::prg_ram_mapping(address).filter(|_| self.prg_ram_enabled());
+        }
+
+        let consecutive: bool = self
+            .last_write
+            .map_or(false, |last| self.cycle.wrapping_sub(last) <= 1);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return None;
+        }
+
+        // Writing a value with bit 7 set resets the shift register and
+        // locks the last PRG bank at $C000
+        if data & 0x80 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0x0C;
+            return None;
+        }
+
+        let complete: bool = self.shift & 0x01 != 0;
+        self.shift = (self.shift >> 1) | ((data & 0x01) << 4);
+        if complete {
+            self.write_register(address, self.shift);
+            self.shift = SHIFT_RESET;
+        }
+        return None;
+    }
+
+    fn ppu_map_read(&self, address: u16) -> Option<usize> {
+        if address >= 0x2000 {
+            return None;
+        }
+        let upper: bool = address >= 0x1000;
+        let bank: usize = if self.control & 0x10 == 0 {
+            // 8KB mode, the low bit of the bank number is ignored
+            (self.chr_bank_0 & 0x1E) as usize + upper as usize
+        } else {
+            ternary!(upper, self.chr_bank_1, self.chr_bank_0) as usize
+        };
+        return Some((bank % self.chr_banks) * CHR_BANK_SIZE + (address & 0x0FFF) as usize);
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        return Some(match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        });
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.shift);
+        state.u8(self.control);
+        state.u8(self.chr_bank_0);
+        state.u8(self.chr_bank_1);
+        state.u8(self.prg_bank);
+        state.u64(self.cycle);
+        state.bool(self.last_write.is_some());
+        state.u64(self.last_write.unwrap_or(0));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift = state.u8()?;
+        self.control = state.u8()?;
+        self.chr_bank_0 = state.u8()?;
+        self.chr_bank_1 = state.u8()?;
+        self.prg_bank = state.u8()?;
+        self.cycle = state.u64()?;
+        let written: bool = state.bool()?;
+        let last_write: u64 = state.u64()?;
+        self.last_write = ternary!(written, Some(last_write), None);
+        return Ok(());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::cartridge::ines::Mirroring;
+use crate::cartridge::mappers::{self, Mapper, PrgMapping, PRG_ROM_START};
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+static PRG_BANK_SIZE: usize = 0x2000;
+static CHR_BANK_SIZE: usize = 0x0400;
+
+// Mapper 4 (MMC3 / TxROM). Four 8KB PRG windows (two switchable), eight
+// 1KB CHR windows (as two 2KB and four 1KB banks) and a scanline counter
+// clocked by rising edges of PPU address line A12.
+pub struct Mmc3 {
+    prg_banks: usize, // 8KB banks
+    chr_banks: usize, // 1KB banks
+
+    bank_select: u8,      // $8000: target register, PRG and CHR modes
+    registers: [u8; 8],   // $8001: R0 -> R7 bank numbers
+    mirroring: Mirroring, // $A000
+    four_screen: bool,    // hardwired four screen VRAM ignores $A000
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    // Scanline IRQ
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12_high: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg_rom_size: usize, chr_size: usize, mirroring: Mirroring) -> Self {
+        Self {
+            prg_banks: mappers::bank_count(prg_rom_size, PRG_BANK_SIZE),
+            chr_banks: mappers::bank_count(chr_size, CHR_BANK_SIZE),
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            four_screen: mirroring == Mirroring::FourScreen,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high: false,
+        }
+    }
+
+    // Clocked once per scanline while rendering (on A12 rising edges)
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping> {
+        if address < PRG_ROM_START {
+            return mappers::prg_ram_mapping(address).filter(|_| self.prg_ram_enabled);
+        }
+        let second_last: usize = self.prg_banks.saturating_sub(2);
+        let swapped: bool = self.bank_select & 0x40 != 0;
+        let bank: usize = match (address - PRG_ROM_START) / 0x2000 {
+            0 => ternary!(swapped, second_last, self.registers[6] as usize),
+            1 => self.registers[7] as usize,
+            2 => ternary!(swapped, self.registers[6] as usize, second_last),
+            _ => self.prg_banks - 1,
+        };
+        return Some(PrgMapping::Rom(
+            (bank % self.prg_banks) * PRG_BANK_SIZE + (address & 0x1FFF) as usize,
+        ));
+    }
+
+    fn cpu_map_write(&mut self, address: u16, data: u8) -> Option<PrgMapping> {
+        if address < PRG_ROM_START {
+            let writable: bool = self.prg_ram_enabled && !self.prg_ram_write_protect;
+            return mappers::prg_ram_mapping(address).filter(|_| writable);
+        }
+
+        // Registers are selected by the address range and whether it is even
+        let even: bool = address & 0x0001 == 0;
+        match (address & 0xE000, even) {
+            (0x8000, true) => self.bank_select = data,
+            (0x8000, false) => self.registers[(self.bank_select & 0x07) as usize] = data,
+            (0xA000, true) => {
+                if !self.four_screen {
+                    self.mirroring =
+                        ternary!(data & 0x01 == 0, Mirroring::Vertical, Mirroring::Horizontal);
+                }
+            }
+            (0xA000, false) => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protect = data & 0x40 != 0;
+            }
+            (0xC000, true) => self.irq_latch = data,
+            (0xC000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // Disabling the IRQ also acknowledges a pending one
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+        return None;
+    }
+
+    fn ppu_map_read(&self, address: u16) -> Option<usize> {
+        if address >= 0x2000 {
+            return None;
+        }
+        // CHR A12 inversion swaps the 2KB and 1KB halves
+        let slot: u16 = ternary!(
+            self.bank_select & 0x80 != 0,
+            (address ^ 0x1000) / 0x0400,
+            address / 0x0400
+        );
+        let bank: usize = match slot {
+            0 => (self.registers[0] & 0xFE) as usize,
+            1 => (self.registers[0] | 0x01) as usize,
+            2 => (self.registers[1] & 0xFE) as usize,
+            3 => (self.registers[1] | 0x01) as usize,
+            _ => self.registers[(slot - 2) as usize] as usize,
+        };
+        return Some((bank % self.chr_banks) * CHR_BANK_SIZE + (address & 0x03FF) as usize);
+    }
+
+    // Pattern fetches from $1000 -> $1FFF after fetches from $0000 -> $0FFF
+    // produce one rising edge of A12 per scanline
+    fn notify_ppu_address(&mut self, address: u16) {
+        let a12_high: bool = address & 0x1000 != 0;
+        if a12_high && !self.a12_high {
+            self.clock_irq_counter();
+        }
+        self.a12_high = a12_high;
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        return ternary!(self.four_screen, None, Some(self.mirroring));
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+}
+
+impl Snapshot for Mmc3 {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.bank_select);
+        state.bytes(&self.registers);
+        state.bool(self.mirroring == Mirroring::Horizontal);
+        state.bool(self.prg_ram_enabled);
+        state.bool(self.prg_ram_write_protect);
+        state.u8(self.irq_latch);
+        state.u8(self.irq_counter);
+        state.bool(self.irq_reload);
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_pending);
+        state.bool(self.a12_high);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = state.u8()?;
+        state.bytes(&mut self.registers)?;
+        // Four screen boards keep their hardwired mirroring
+        let horizontal: bool = state.bool()?;
+        if !self.four_screen {
+            self.mirroring = ternary!(horizontal, Mirroring::Horizontal, Mirroring::Vertical);
+        }
+        self.prg_ram_enabled = state.bool()?;
+        self.prg_ram_write_protect = state.bool()?;
+        self.irq_latch = state.u8()?;
+        self.irq_counter = state.u8()?;
+        self.irq_reload = state.bool()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.a12_high = state.bool()?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::cartridge::ines::{INesHeader, Mirroring, RomError};
+use crate::savestate::savestate::Snapshot;
+
+pub mod cnrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+// CPU windows shared by the supported boards
+pub const PRG_RAM_START: u16 = 0x6000;
+pub const PRG_RAM_END: u16 = 0x7FFF;
+pub const PRG_ROM_START: u16 = 0x8000;
+
+// Location in cartridge memory a CPU address resolves to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrgMapping {
+    Rom(usize), // offset into PRG ROM
+    Ram(usize), // offset into PRG RAM
+}
+
+// A mapper translates CPU and PPU addresses into offsets within the
+// cartridge's PRG and CHR memory. The memory itself is owned by the
+// Cartridge, mappers only hold their bank switching registers, which they
+// save and restore through Snapshot.
+pub trait Mapper: Snapshot {
+    // Resolve a CPU read in $4020 -> $FFFF. Must not have side effects,
+    // so it can also be used to peek.
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping>;
+
+    // Handle a CPU write in $4020 -> $FFFF. Writes to mapper registers are
+    // consumed and return None, otherwise the write's destination is returned.
+    fn cpu_map_write(&mut self, address: u16, data: u8) -> Option<PrgMapping>;
+
+    // Resolve a PPU pattern table address ($0000 -> $1FFF) to a CHR offset
+    fn ppu_map_read(&self, address: u16) -> Option<usize>;
+
+    // Resolve a PPU write to CHR, only honoured by the cartridge for CHR RAM
+    fn ppu_map_write(&self, address: u16) -> Option<usize> {
+        return self.ppu_map_read(address);
+    }
+
+    // Observe every address the PPU puts on its bus (used for A12 clocking)
+    fn notify_ppu_address(&mut self, _address: u16) {}
+
+    // Called once per CPU cycle (through the bus), for mappers that time
+    // CPU accesses
+    fn cpu_clock(&mut self) {}
+
+    // Mirroring selected by the mapper, None for hardwired mirroring
+    fn mirroring(&self) -> Option<Mirroring> {
+        return None;
+    }
+
+    // Whether the mapper is asserting the CPU IRQ line
+    fn irq(&self) -> bool {
+        return false;
+    }
+}
+
+// Create the mapper for a header. Sizes are those of the PRG ROM and of the
+// CHR memory (ROM or RAM) present on the board.
+pub fn create(
+    header: &INesHeader,
+    prg_rom_size: usize,
+    chr_size: usize,
+) -> Result<Box<dyn Mapper>, RomError> {
+    return match header.mapper {
+        0 => Ok(Box::new(nrom::Nrom::new(prg_rom_size))),
+        1 => Ok(Box::new(mmc1::Mmc1::new(prg_rom_size, chr_size))),
+        2 => Ok(Box::new(uxrom::UxRom::new(prg_rom_size))),
+        3 => Ok(Box::new(cnrom::CnRom::new(prg_rom_size, chr_size))),
+        4 => Ok(Box::new(mmc3::Mmc3::new(
+            prg_rom_size,
+            chr_size,
+            header.mirroring,
+        ))),
+        mapper => Err(RomError::UnsupportedMapper(mapper)),
+    };
+}
+
+// PRG RAM at $6000 -> $7FFF, as wired on most boards
+pub fn prg_ram_mapping(address: u16) -> Option<PrgMapping> {
+    if (PRG_RAM_START..=PRG_RAM_END).contains(&address) {
+        return Some(PrgMapping::Ram((address - PRG_RAM_START) as usize));
+    }
+    return None;
+}
+
+// Number of banks of a given size, at least one so offsets can be wrapped
+pub fn bank_count(size: usize, bank_size: usize) -> usize {
+    return (size / bank_size).max(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::cartridge::Cartridge;
+    use crate::cartridge::ines::Mirroring;
+    use crate::cpu::device::Device;
+
+    // iNES image where every 8KB of PRG ROM and every 1KB of CHR ROM is
+    // filled with its index, so reads tell which bank is mapped
+    fn image(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut image: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks];
+        image.push(mapper << 4);
+        image.push(mapper & 0xF0);
+        image.resize(16, 0x00);
+        for bank in 0..prg_banks as usize * 2 {
+            image.extend_from_slice(&[bank as u8; 0x2000]);
+        }
+        for bank in 0..chr_banks as usize * 8 {
+            image.extend_from_slice(&[bank as u8; 0x0400]);
+        }
+        return image;
+    }
+
+    // A store from the CPU, which takes at least two cycles after the last one
+    fn store(cartridge: &mut Cartridge, address: u16, data: u8) {
+        cartridge.cpu_clock();
+        cartridge.cpu_clock();
+        cartridge.write(address, data);
+    }
+
+    // Load an MMC1 register serially, low bit first
+    fn mmc1_load(cartridge: &mut Cartridge, address: u16, value: u8) {
+        for bit in 0..5 {
+            store(cartridge, address, (value >> bit) & 0x01);
+        }
+    }
+
+    #[test]
+    fn mmc1_registers_are_loaded_serially() {
+        // 128KB of PRG ROM, 32KB of CHR ROM
+        let mut cartridge: Cartridge = Cartridge::from_bytes(&image(1, 8, 4)).unwrap();
+
+        // Power on with the last bank fixed at $C000
+        assert_eq!(cartridge.peek(0x8000), Some(0));
+        assert_eq!(cartridge.peek(0xC000), Some(14));
+
+        // Nothing changes until the fifth write
+        for bit in 0..4 {
+            store(&mut cartridge, 0xE000, (0x05 >> bit) & 0x01);
+            assert_eq!(cartridge.peek(0x8000), Some(0));
+        }
+        store(&mut cartridge, 0xE000, 0x00);
+        assert_eq!(cartridge.peek(0x8000), Some(10));
+        assert_eq!(cartridge.peek(0xC000), Some(14));
+
+        // Bit 7 drops the bits shifted in so far
+        store(&mut cartridge, 0xE000, 0x01);
+        store(&mut cartridge, 0xE000, 0x01);
+        store(&mut cartridge, 0xE000, 0x80);
+        mmc1_load(&mut cartridge, 0xE000, 0x02);
+        assert_eq!(cartridge.peek(0x8000), Some(4));
+
+        // First bank fixed at $8000, 4KB CHR banks and horizontal mirroring
+        mmc1_load(&mut cartridge, 0x8000, 0x1B);
+        assert_eq!(cartridge.peek(0x8000), Some(0));
+        assert_eq!(cartridge.peek(0xC000), Some(4));
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        mmc1_load(&mut cartridge, 0xA000, 0x05);
+        mmc1_load(&mut cartridge, 0xC000, 0x02);
+        assert_eq!(cartridge.ppu_peek(0x0000), 20);
+        assert_eq!(cartridge.ppu_peek(0x1C00), 11);
+
+        // 32KB PRG and 8KB CHR modes ignore the low bit of the bank numbers
+        mmc1_load(&mut cartridge, 0x8000, 0x00);
+        assert_eq!(cartridge.peek(0x8000), Some(4));
+        assert_eq!(cartridge.peek(0xC000), Some(6));
+        assert_eq!(cartridge.ppu_peek(0x1000), 20);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+
+        // PRG RAM is disabled by bit 4 of the PRG bank
+        store(&mut cartridge, 0x6000, 0x42);
+        assert_eq!(cartridge.peek(0x6000), Some(0x42));
+        mmc1_load(&mut cartridge, 0xE000, 0x10);
+        assert_eq!(cartridge.peek(0x6000), None);
+    }
+
+    #[test]
+    fn mmc1_ignores_writes_on_consecutive_cycles() {
+        let mut cartridge: Cartridge = Cartridge::from_bytes(&image(1, 8, 4)).unwrap();
+        store(&mut cartridge, 0xE000, 0x01);
+        cartridge.write(0xE000, 0x01);
+        cartridge.cpu_clock();
+        cartridge.write(0xE000, 0x01);
+        for _ in 0..4 {
+            store(&mut cartridge, 0xE000, 0x00);
+        }
+        assert_eq!(cartridge.peek(0x8000), Some(2));
+
+        // Read-modify-write instructions write the old value and then the
+        // new one on the next cycle, only the first write shifts a bit in.
+        // INC on $01 and $00 loads 1, 0, 1, 0, 0 into the PRG bank.
+        let mut cartridge: Cartridge = Cartridge::from_bytes(&image(1, 8, 4)).unwrap();
+        for old in [1, 0, 1, 0, 0].iter() {
+            store(&mut cartridge, 0xE000, *old);
+            cartridge.cpu_clock();
+            cartridge.write(0xE000, *old + 1);
+        }
+        assert_eq!(cartridge.peek(0x8000), Some(10));
+    }
+
+    #[test]
+    fn mmc3_switches_prg_and_chr_banks() {
+        // 128KB of PRG ROM, 32KB of CHR ROM
+        let mut cartridge: Cartridge = Cartridge::from_bytes(&image(4, 8, 4)).unwrap();
+        let prg = |cartridge: &Cartridge| -> Vec<u8> {
+            return (0..4)
+                .map(|window| cartridge.peek(0x8000 + window * 0x2000).unwrap())
+                .collect();
+        };
+        let chr = |cartridge: &Cartridge| -> Vec<u8> {
+            return (0..8)
+                .map(|window| cartridge.ppu_peek(window * 0x0400))
+                .collect();
+        };
+
+        // The last two banks are fixed at $C000 and $E000
+        assert_eq!(prg(&cartridge), [0, 1, 14, 15]);
+        assert_eq!(chr(&cartridge), [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // Bank numbers go to the register selected by the low bits of $8000
+        for (register, bank) in [(6, 3), (7, 9), (0, 8), (1, 13), (2, 20), (5, 31)].iter() {
+            store(&mut cartridge, 0x8000, *register);
+            store(&mut cartridge, 0x8001, *bank);
+        }
+        assert_eq!(prg(&cartridge), [3, 9, 14, 15]);
+        // R0 and R1 select 2KB banks, their low bit is ignored
+        assert_eq!(chr(&cartridge), [8, 9, 12, 13, 20, 5, 6, 31]);
+
+        // Bit 6 swaps $8000 and $C000, bit 7 swaps the CHR halves
+        store(&mut cartridge, 0x8000, 0xC0);
+        assert_eq!(prg(&cartridge), [14, 9, 3, 15]);
+        assert_eq!(chr(&cartridge), [20, 5, 6, 31, 8, 9, 12, 13]);
+
+        // Bank numbers wrap around the ROM size
+        store(&mut cartridge, 0x8000, 0xC7);
+        store(&mut cartridge, 0x8001, 0x11);
+        assert_eq!(prg(&cartridge), [14, 1, 3, 15]);
+
+        store(&mut cartridge, 0xA000, 0x01);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+        store(&mut cartridge, 0xA000, 0x00);
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+
+        // PRG RAM enable and write protection
+        store(&mut cartridge, 0x6000, 0x42);
+        store(&mut cartridge, 0xA001, 0xC0);
+        store(&mut cartridge, 0x6000, 0x24);
+        assert_eq!(cartridge.peek(0x6000), Some(0x42));
+        store(&mut cartridge, 0xA001, 0x00);
+        assert_eq!(cartridge.peek(0x6000), None);
+    }
+
+    #[test]
+    fn mmc3_counts_scanlines_on_a12_rising_edges() {
+        let mut cartridge: Cartridge = Cartridge::from_bytes(&image(4, 8, 4)).unwrap();
+        // Background from $0000 and sprites from $1000, one rising edge of
+        // A12 per scanline
+        let scanline = |cartridge: &mut Cartridge| {
+            cartridge.ppu_read(0x0000);
+            cartridge.ppu_read(0x0FF0);
+            cartridge.ppu_read(0x1000);
+            cartridge.ppu_read(0x1FF0);
+        };
+
+        store(&mut cartridge, 0xC000, 3);
+        store(&mut cartridge, 0xC001, 0);
+        store(&mut cartridge, 0xE001, 0);
+
+        // The first edge reloads the counter with the latch, the IRQ is
+        // raised when it is decremented to 0
+        for _ in 0..3 {
+            scanline(&mut cartridge);
+            assert!(!cartridge.irq());
+        }
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+
+        // The IRQ line stays asserted until $E000 is written, which also
+        // disables further IRQs
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+        store(&mut cartridge, 0xE000, 0);
+        assert!(!cartridge.irq());
+        for _ in 0..8 {
+            scanline(&mut cartridge);
+            assert!(!cartridge.irq());
+        }
+
+        // A12 staying high doesn't clock the counter
+        store(&mut cartridge, 0xC000, 1);
+        store(&mut cartridge, 0xC001, 0);
+        store(&mut cartridge, 0xE001, 0);
+        scanline(&mut cartridge);
+        for _ in 0..10 {
+            cartridge.ppu_read(0x1000);
+        }
+        assert!(!cartridge.irq());
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+
+        // A latch of 0 raises the IRQ on every scanline
+        store(&mut cartridge, 0xE000, 0);
+        store(&mut cartridge, 0xC000, 0);
+        store(&mut cartridge, 0xC001, 0);
+        store(&mut cartridge, 0xE001, 0);
+        for _ in 0..3 {
+            scanline(&mut cartridge);
+            assert!(cartridge.irq());
+            store(&mut cartridge, 0xE000, 0);
+            store(&mut cartridge, 0xE001, 0);
+        }
+    }
+}
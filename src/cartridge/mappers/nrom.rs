@@ -0,0 +1,42 @@
+use crate::cartridge::mappers::{self, Mapper, PrgMapping, PRG_ROM_START};
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Mapper 0 (NROM): 16KB or 32KB of PRG ROM and 8KB of CHR, no bank switching
+pub struct Nrom {
+    prg_mask: u16, // 16KB images are mirrored into $C000 -> $FFFF
+}
+
+impl Nrom {
+    pub fn new(prg_rom_size: usize) -> Self {
+        Self {
+            prg_mask: ternary!(prg_rom_size > 0x4000, 0x7FFF, 0x3FFF),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping> {
+        if address >= PRG_ROM_START {
+            return Some(PrgMapping::Rom((address & self.prg_mask) as usize));
+        }
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn cpu_map_write(&mut self, address: u16, _data: u8) -> Option<PrgMapping> {
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn ppu_map_read(&self, address: u16) -> Option<usize> {
+        return ternary!(address < 0x2000, Some(address as usize), None);
+    }
+}
+
+// No registers, the PRG mask follows from the ROM
+impl Snapshot for Nrom {
+    fn save(&self, _state: &mut StateWriter) {}
+
+    fn load(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        return Ok(());
+    }
+}
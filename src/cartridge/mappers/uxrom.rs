@@ -0,0 +1,56 @@
+use crate::cartridge::mappers::{self, Mapper, PrgMapping, PRG_ROM_START};
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+static PRG_BANK_SIZE: usize = 0x4000;
+
+// Mapper 2 (UxROM): switchable 16KB PRG bank at $8000, last bank fixed at
+// $C000 and 8KB of unbanked CHR (usually RAM)
+pub struct UxRom {
+    prg_banks: usize,
+    prg_bank: usize,
+}
+
+impl UxRom {
+    pub fn new(prg_rom_size: usize) -> Self {
+        Self {
+            prg_banks: mappers::bank_count(prg_rom_size, PRG_BANK_SIZE),
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn cpu_map_read(&self, address: u16) -> Option<PrgMapping> {
+        if address >= PRG_ROM_START {
+            let bank: usize = ternary!(address < 0xC000, self.prg_bank, self.prg_banks - 1);
+            return Some(PrgMapping::Rom(
+                bank * PRG_BANK_SIZE + (address & 0x3FFF) as usize,
+            ));
+        }
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn cpu_map_write(&mut self, address: u16, data: u8) -> Option<PrgMapping> {
+        if address >= PRG_ROM_START {
+            self.prg_bank = (data & 0x0F) as usize % self.prg_banks;
+            return None;
+        }
+        return mappers::prg_ram_mapping(address);
+    }
+
+    fn ppu_map_read(&self, address: u16) -> Option<usize> {
+        return ternary!(address < 0x2000, Some(address as usize), None);
+    }
+}
+
+impl Snapshot for UxRom {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.prg_bank as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.u8()? as usize % self.prg_banks;
+        return Ok(());
+    }
+}
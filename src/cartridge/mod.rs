@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod cartridge;
+pub mod ines;
+pub mod mappers;
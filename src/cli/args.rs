@@ -0,0 +1,173 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+
+use nes_emulator::ternary;
+
+pub static USAGE: &str = "\
+Usage: nes_emulator <rom.nes> [options]
+
+Runs a ROM headless and reports the result through the exit code.
+
+Options:
+  --frames <n>            Frame limit (default 600)
+  --until-pc <addr>       Stop once the CPU is about to execute <addr>
+  --until-mem <cond>      Stop once a memory condition holds
+  --expect-mem <cond>     Fail unless the condition holds at the end (repeatable)
+  --screenshot <file>     Save the final picture (.png or .ppm)
+  --dump-ram <file>       Save a hex dump of the internal RAM
+  --trace <file>          Write a nestest style trace of every instruction
+  --load-state <file>     Start from a save state taken with the same ROM
+  --save-state <file>     Save the machine state at the end of the run
+  --movie <file.fm2>      Play back an FM2 movie instead of running for a
+                          number of frames, failing on a desync
+  --debug                 Start the interactive debugger instead of running
+  --gdb <addr|path>       Wait for a gdb client on a TCP address (host:port)
+                          or a Unix socket path, and let it drive the CPU
+  -h, --help              Show this message
+
+Numbers are decimal, or hex with a $ or 0x prefix. Conditions are
+<addr>=<value> or <addr>!=<value>, e.g. $6000!=$80.
+
+Exit codes:
+  0  passed: the stop condition was reached (or the frame limit, when no stop
+     condition is given) and every expectation holds
+  1  failed: an expectation does not hold or the movie desynced
+  2  timed out: the frame limit was reached before the stop condition
+  3  error: bad arguments, unreadable ROM or output failure";
+
+pub static DEFAULT_FRAMES: u64 = 600;
+
+// Comparison of a memory location against a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryCondition {
+    pub address: u16,
+    pub value: u8,
+    pub equal: bool,
+}
+
+impl MemoryCondition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (address, value, equal) = match text.find("!=") {
+            Some(index) => (&text[..index], &text[index + 2..], false),
+            None => match text.find('=') {
+                Some(index) => (&text[..index], &text[index + 1..], true),
+                None => return Err(format!("Invalid memory condition '{}'", text)),
+            },
+        };
+        return Ok(Self {
+            address: parse_number(address)?,
+            value: parse_number(value)?,
+            equal,
+        });
+    }
+
+    pub fn holds(&self, value: u8) -> bool {
+        return (value == self.value) == self.equal;
+    }
+}
+
+impl fmt::Display for MemoryCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator: &str = ternary!(self.equal, "=", "!=");
+        return write!(f, "${:04X}{}${:02X}", self.address, operator, self.value);
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub frames: u64,
+    pub until_pc: Option<u16>,
+    pub until_mem: Option<MemoryCondition>,
+    pub expect_mem: Vec<MemoryCondition>,
+    pub screenshot: Option<PathBuf>,
+    pub dump_ram: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub load_state: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    pub debug: bool,
+    pub gdb: Option<String>,
+    pub movie: Option<PathBuf>,
+}
+
+impl Options {
+    // Whether the run ends on a condition rather than the frame limit
+    pub fn has_stop_condition(&self) -> bool {
+        return self.until_pc.is_some() || self.until_mem.is_some();
+    }
+}
+
+// Parse the arguments (without the program name). Returns Ok(None) when
+// help was asked for.
+pub fn parse(args: &[String]) -> Result<Option<Options>, String> {
+    let mut rom: Option<PathBuf> = None;
+    let mut options: Options = Options {
+        rom: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        until_pc: None,
+        until_mem: None,
+        expect_mem: Vec::new(),
+        screenshot: None,
+        dump_ram: None,
+        trace: None,
+        load_state: None,
+        save_state: None,
+        debug: false,
+        gdb: None,
+        movie: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || -> Result<&String, String> {
+            return args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg));
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--frames" => options.frames = parse_number(value()?)?,
+            "--until-pc" => options.until_pc = Some(parse_number(value()?)?),
+            "--until-mem" => options.until_mem = Some(MemoryCondition::parse(value()?)?),
+            "--expect-mem" => options.expect_mem.push(MemoryCondition::parse(value()?)?),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+            "--dump-ram" => options.dump_ram = Some(PathBuf::from(value()?)),
+            "--trace" => options.trace = Some(PathBuf::from(value()?)),
+            "--load-state" => options.load_state = Some(PathBuf::from(value()?)),
+            "--save-state" => options.save_state = Some(PathBuf::from(value()?)),
+            "--debug" => options.debug = true,
+            "--gdb" => options.gdb = Some(value()?.clone()),
+            "--movie" => options.movie = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| String::from("No ROM given"))?;
+    // Movies start from their own state and end with their input
+    if options.movie.is_some() && options.load_state.is_some() {
+        return Err(String::from("--movie can't be combined with --load-state"));
+    }
+    if options.movie.is_some() && options.has_stop_condition() {
+        return Err(String::from(
+            "--movie can't be combined with stop conditions",
+        ));
+    }
+    return Ok(Some(options));
+}
+
+// Decimal, or hexadecimal with a $ or 0x prefix
+pub fn parse_number<T: TryFrom<u64>>(text: &str) -> Result<T, String> {
+    let text: &str = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        text.parse::<u64>()
+    };
+    let number: u64 = parsed.map_err(|_| format!("Invalid number '{}'", text))?;
+    return T::try_from(number).map_err(|_| format!("Number out of range '{}'", text));
+}
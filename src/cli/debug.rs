@@ -0,0 +1,297 @@
+use std::io::{self, BufRead, Write};
+
+use nes_emulator::cpu::bus::BusAccess;
+use nes_emulator::cpu::disasm::{self, SymbolTable};
+use nes_emulator::cpu::trace;
+use nes_emulator::debugger::debugger::{Breakpoint, Condition, Debugger, StopReason, Watchpoint};
+use nes_emulator::{ternary, Nes};
+
+use crate::cli::args::parse_number;
+
+static HELP: &str = "\
+Commands (an empty line repeats the last one):
+  s, step [n]              Execute n instructions (default 1)
+  n, next                  Step over a JSR
+  f, finish                Run until the current subroutine returns
+  c, continue              Run until a breakpoint or watchpoint
+  u, until <cycle>         Run until a CPU cycle
+  b, break <addr> [if <cond>]
+  b, break if <cond>       Break when a condition becomes true
+  w, watch <rwx> <addr>[-<end>]
+                           Watch reads, writes and/or execution of a range
+  d, delete [id]           Delete a breakpoint or watchpoint, or all of them
+  l, list                  List breakpoints and watchpoints
+  r, regs                  Show the registers and the next instruction
+  x, mem <addr> [len]      Hex dump memory
+  dis [addr] [count]       Disassemble, from the PC by default
+  q, quit                  Exit
+
+Conditions compare a register (A X Y SP P PC) or flag (C Z I D V N) with a
+value using == != < <= > >=, e.g. A == $10 or Z == 1.";
+
+static DEFAULT_DUMP_LENGTH: u16 = 64;
+static DEFAULT_DISASSEMBLY_COUNT: usize = 10;
+
+// Interactive debugger over stdin and stdout
+pub fn repl(nes: &mut Nes) -> io::Result<()> {
+    let mut session: Session = Session {
+        debugger: Debugger::new(),
+        symbols: disasm::register_symbols(),
+        last_command: String::new(),
+    };
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", session.status(nes))?;
+
+    loop {
+        write!(stdout, "(nes) ")?;
+        stdout.flush()?;
+        let mut line: String = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line: String = ternary!(
+            line.trim().is_empty(),
+            session.last_command.clone(),
+            line.trim().to_string()
+        );
+        session.last_command = line.clone();
+        match session.execute(nes, &line) {
+            Ok(Some(output)) => writeln!(stdout, "{}", output)?,
+            Ok(None) => return Ok(()),
+            Err(e) => writeln!(stdout, "{}", e)?,
+        }
+    }
+}
+
+struct Session {
+    debugger: Debugger,
+    symbols: SymbolTable,
+    last_command: String,
+}
+
+impl Session {
+    // Run a command, returning its output or None to quit
+    fn execute(&mut self, nes: &mut Nes, line: &str) -> Result<Option<String>, String> {
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        let arguments: Vec<&str> = rest.split_whitespace().collect();
+        let output: String = match command {
+            "" => String::new(),
+            "h" | "help" => String::from(HELP),
+            "q" | "quit" => return Ok(None),
+            "s" | "step" => {
+                let count: u64 = arguments.first().map_or(Ok(1), |n| parse_number(n))?;
+                let mut reason: StopReason = StopReason::Done;
+                for _ in 0..count {
+                    reason = self.debugger.step_into(nes);
+                    if reason != StopReason::Done {
+                        break;
+                    }
+                }
+                self.stopped(nes, reason)
+            }
+            "n" | "next" => {
+                let reason: StopReason = self.debugger.step_over(nes);
+                self.stopped(nes, reason)
+            }
+            "f" | "finish" => {
+                let reason: StopReason = self.debugger.step_out(nes);
+                self.stopped(nes, reason)
+            }
+            "c" | "continue" => {
+                let reason: StopReason = self.debugger.run(nes);
+                self.stopped(nes, reason)
+            }
+            "u" | "until" => {
+                let cycle: u64 = parse_number(arguments.first().ok_or("Missing cycle")?)?;
+                let reason: StopReason = self.debugger.run_to_cycle(nes, cycle);
+                self.stopped(nes, reason)
+            }
+            "b" | "break" => {
+                let breakpoint: Breakpoint = parse_breakpoint(rest)?;
+                let id: usize = self.debugger.add_breakpoint(breakpoint);
+                format!("Breakpoint {}: {}", id, breakpoint)
+            }
+            "w" | "watch" => {
+                let watchpoint: Watchpoint = parse_watchpoint(&arguments)?;
+                let id: usize = self.debugger.add_watchpoint(watchpoint);
+                format!("Watchpoint {}: {}", id, watchpoint)
+            }
+            "d" | "delete" => match arguments.first() {
+                Some(id) => {
+                    let id: usize = parse_number(id)?;
+                    ternary!(
+                        self.debugger.remove(id),
+                        format!("Deleted {}", id),
+                        format!("No breakpoint or watchpoint {}", id)
+                    )
+                }
+                None => {
+                    self.debugger.clear();
+                    String::from("Deleted all breakpoints and watchpoints")
+                }
+            },
+            "l" | "list" => self.list(),
+            "r" | "regs" => self.status(nes),
+            "x" | "mem" => {
+                let address: u16 = parse_number(arguments.first().ok_or("Missing address")?)?;
+                let length: u16 = arguments
+                    .get(1)
+                    .map_or(Ok(DEFAULT_DUMP_LENGTH), |n| parse_number(n))?;
+                dump(nes, address, length)
+            }
+            "dis" => {
+                let address: u16 = arguments
+                    .first()
+                    .map_or(Ok(nes.cpu.registers.pc), |a| parse_number(a))?;
+                let count: usize = arguments
+                    .get(1)
+                    .map_or(Ok(DEFAULT_DISASSEMBLY_COUNT), |n| parse_number(n))?;
+                self.disassemble(nes, address, count)
+            }
+            _ => return Err(format!("Unknown command {}, try help", command)),
+        };
+        return Ok(Some(output));
+    }
+
+    // Registers and the next instruction, as a trace line
+    fn status(&self, nes: &Nes) -> String {
+        let ppu = nes.ppu();
+        return trace::format_line(&nes.cpu, Some((ppu.scanline(), ppu.dot())));
+    }
+
+    fn stopped(&self, nes: &Nes, reason: StopReason) -> String {
+        let why: String = match reason {
+            StopReason::Done => return self.status(nes),
+            StopReason::Breakpoint(id) => format!("Breakpoint {}", id),
+            StopReason::Watchpoint(id, None) => format!("Watchpoint {}: execute", id),
+            StopReason::Watchpoint(id, Some(access)) => {
+                format!("Watchpoint {}: {}", id, describe(&access))
+            }
+            StopReason::Budget => format!("No break after {} instructions", self.debugger.budget),
+        };
+        return format!("{}\n{}", why, self.status(nes));
+    }
+
+    fn list(&self) -> String {
+        let breakpoints = self
+            .debugger
+            .breakpoints()
+            .iter()
+            .map(|(id, b)| format!("{:>3}  break  {}", id, b));
+        let watchpoints = self
+            .debugger
+            .watchpoints()
+            .iter()
+            .map(|(id, w)| format!("{:>3}  watch  {}", id, w));
+        let lines: Vec<String> = breakpoints.chain(watchpoints).collect();
+        return ternary!(
+            lines.is_empty(),
+            String::from("No breakpoints or watchpoints"),
+            lines.join("\n")
+        );
+    }
+
+    fn disassemble(&self, nes: &Nes, address: u16, count: usize) -> String {
+        let fetch = |address: u16| -> u8 { nes.cpu.bus.peek(address).unwrap_or(0x00) };
+        let mut lines: Vec<String> = Vec::new();
+        let mut address: u16 = address;
+        for _ in 0..count {
+            let decoded = disasm::decode(address, &fetch);
+            let marker: char = ternary!(address == nes.cpu.registers.pc, '>', ' ');
+            let bytes: Vec<String> = decoded.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            lines.push(format!(
+                "{} {:04X}  {:<9} {}",
+                marker,
+                address,
+                bytes.join(" "),
+                decoded.text_with_symbols(&self.symbols)
+            ));
+            address = decoded.next_address();
+        }
+        return lines.join("\n");
+    }
+}
+
+fn describe(access: &BusAccess) -> String {
+    if access.write {
+        return format!("write ${:02X} to ${:04X}", access.data, access.address);
+    }
+    return format!("read ${:02X} from ${:04X}", access.data, access.address);
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let (register, comparison, value) =
+        Condition::split(text).ok_or_else(|| format!("Invalid condition '{}'", text))?;
+    return Ok(Condition {
+        register,
+        comparison,
+        value: parse_number(value)?,
+    });
+}
+
+// <addr> [if <cond>] or if <cond>
+fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    if let Some(condition) = text.strip_prefix("if ") {
+        return Ok(Breakpoint::when(parse_condition(condition)?));
+    }
+    let (address, condition) = match text.split_once(" if ") {
+        Some((address, condition)) => (address, Some(parse_condition(condition)?)),
+        None => (text, None),
+    };
+    if address.is_empty() {
+        return Err(String::from("Missing breakpoint address"));
+    }
+    return Ok(Breakpoint {
+        address: Some(parse_number(address)?),
+        condition,
+    });
+}
+
+// <rwx> <addr>[-<end>]
+fn parse_watchpoint(arguments: &[&str]) -> Result<Watchpoint, String> {
+    let (kinds, range) = match arguments {
+        [kinds, range] => (*kinds, *range),
+        _ => return Err(String::from("Usage: watch <rwx> <addr>[-<end>]")),
+    };
+    if kinds.is_empty() || !kinds.chars().all(|c| "rwx".contains(c)) {
+        return Err(format!("Invalid watch kinds '{}', use r, w and x", kinds));
+    }
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => {
+            let address: u16 = parse_number(range)?;
+            (address, address)
+        }
+    };
+    if start > end {
+        return Err(String::from("Watch range ends before it starts"));
+    }
+    return Ok(Watchpoint {
+        start,
+        end,
+        read: kinds.contains('r'),
+        write: kinds.contains('w'),
+        execute: kinds.contains('x'),
+    });
+}
+
+fn dump(nes: &Nes, address: u16, length: u16) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for row in (0..length).step_by(16) {
+        let start: u16 = address.wrapping_add(row);
+        let bytes: Vec<String> = (0..16.min(length - row))
+            .map(
+                |offset| match nes.cpu.bus.peek(start.wrapping_add(offset)) {
+                    Some(value) => format!("{:02X}", value),
+                    None => String::from("--"),
+                },
+            )
+            .collect();
+        lines.push(format!("{:04X}  {}", start, bytes.join(" ")));
+    }
+    return lines.join("\n");
+}
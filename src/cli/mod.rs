@@ -0,0 +1,3 @@
+pub mod args;
+pub mod debug;
+pub mod runner;
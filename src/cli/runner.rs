@@ -0,0 +1,238 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use nes_emulator::cpu::bus::RAM_SIZE;
+use nes_emulator::cpu::trace::Tracer;
+use nes_emulator::debugger::gdb;
+use nes_emulator::movie::fm2::{Movie, MovieError};
+use nes_emulator::movie::playback::Player;
+use nes_emulator::ppu::image;
+use nes_emulator::ppu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_emulator::{ternary, Nes, LOGGER};
+
+use crate::cli::args::{MemoryCondition, Options};
+use crate::cli::debug;
+
+// Frames between progress messages
+static PROGRESS_INTERVAL: u64 = 600;
+
+// Process exit codes, see args::USAGE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Passed = 0,
+    Failed = 1,
+    TimedOut = 2,
+    Error = 3,
+}
+
+// Run a ROM headless as described by the options
+pub fn run(options: &Options) -> ExitStatus {
+    let mut nes: Nes = Nes::new();
+    if let Err(e) = nes.load_rom(&options.rom) {
+        error!(LOGGER, "Failed to load {}: {}", options.rom.display(), e);
+        return ExitStatus::Error;
+    }
+    if let Some(path) = &options.trace {
+        match Tracer::to_file(path) {
+            Ok(tracer) => nes.set_tracer(Some(tracer)),
+            Err(e) => {
+                error!(LOGGER, "Failed to create {}: {}", path.display(), e);
+                return ExitStatus::Error;
+            }
+        }
+    }
+    if let Some(path) = &options.load_state {
+        let loaded: Result<(), String> = fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|state| nes.load_state(&state).map_err(|e| e.to_string()));
+        if let Err(e) = loaded {
+            error!(LOGGER, "Failed to load state {}: {}", path.display(), e);
+            return ExitStatus::Error;
+        }
+        info!(LOGGER, "Loaded state {}", path.display());
+    }
+    if options.debug {
+        if let Err(e) = debug::repl(&mut nes) {
+            error!(LOGGER, "Debugger failed: {}", e);
+            return ExitStatus::Error;
+        }
+        return ExitStatus::Passed;
+    }
+    if let Some(address) = &options.gdb {
+        if let Err(e) = serve_gdb(&mut nes, address) {
+            error!(LOGGER, "gdb server failed: {}", e);
+            return ExitStatus::Error;
+        }
+        return ExitStatus::Passed;
+    }
+
+    let mut status: ExitStatus = ExitStatus::Passed;
+    if let Some(path) = &options.movie {
+        info!(LOGGER, "Loaded {}", options.rom.display(); "movie" => path.display().to_string());
+        match play_movie(&mut nes, path) {
+            Ok(frames) => {
+                info!(LOGGER, "Movie finished"; "frames" => frames, "pc" => format!("${:04X}", nes.cpu.registers.pc));
+            }
+            Err(e @ MovieError::Desync { .. }) => {
+                error!(LOGGER, "{}", e);
+                status = ExitStatus::Failed;
+            }
+            Err(e) => {
+                error!(LOGGER, "Failed to play {}: {}", path.display(), e);
+                return ExitStatus::Error;
+            }
+        }
+    } else {
+        info!(LOGGER, "Loaded {}", options.rom.display(); "frames" => options.frames);
+        let (stopped, frames): (bool, u64) = run_until_stop(&mut nes, options);
+        if options.has_stop_condition() && !stopped {
+            warn!(LOGGER, "Frame limit reached before the stop condition"; "frames" => frames);
+            status = ExitStatus::TimedOut;
+        } else {
+            info!(LOGGER, "Run finished"; "frames" => frames, "pc" => format!("${:04X}", nes.cpu.registers.pc));
+        }
+    }
+
+    for condition in options.expect_mem.iter() {
+        let value: u8 = nes.cpu.bus.peek(condition.address).unwrap_or(0x00);
+        if !condition.holds(value) {
+            error!(
+                LOGGER,
+                "Expectation {} failed, found ${:02X}", condition, value
+            );
+            if status == ExitStatus::Passed {
+                status = ExitStatus::Failed;
+            }
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        if let Err(e) = save_screenshot(&nes, path) {
+            error!(LOGGER, "Failed to write {}: {}", path.display(), e);
+            return ExitStatus::Error;
+        }
+        info!(LOGGER, "Saved screenshot to {}", path.display());
+    }
+    if let Some(path) = &options.dump_ram {
+        if let Err(e) = save_ram_dump(&nes, path) {
+            error!(LOGGER, "Failed to write {}: {}", path.display(), e);
+            return ExitStatus::Error;
+        }
+        info!(LOGGER, "Saved RAM dump to {}", path.display());
+    }
+    if let Some(path) = &options.save_state {
+        if let Err(e) = fs::write(path, nes.save_state()) {
+            error!(LOGGER, "Failed to write {}: {}", path.display(), e);
+            return ExitStatus::Error;
+        }
+        info!(LOGGER, "Saved state to {}", path.display());
+    }
+
+    return status;
+}
+
+// Run until the frame limit or a stop condition, returning whether a stop
+// condition was met and the number of frames run. Conditions are checked
+// between instructions.
+fn run_until_stop(nes: &mut Nes, options: &Options) -> (bool, u64) {
+    if !options.has_stop_condition() {
+        for frame in 1..=options.frames {
+            nes.run_frame();
+            if frame % PROGRESS_INTERVAL == 0 {
+                info!(LOGGER, "Running"; "frame" => frame);
+            }
+        }
+        return (false, options.frames);
+    }
+
+    let start: u64 = nes.ppu().frame();
+    let mut last_progress: u64 = start;
+    loop {
+        let frame: u64 = nes.ppu().frame();
+        if stop_condition_met(nes, options) {
+            return (true, frame - start);
+        }
+        if frame - start >= options.frames {
+            return (false, frame - start);
+        }
+        if frame - last_progress >= PROGRESS_INTERVAL {
+            last_progress = frame;
+            info!(LOGGER, "Running"; "frame" => frame - start);
+        }
+        nes.step_instruction();
+    }
+}
+
+// Play a movie to the end, returning the number of frames played
+fn play_movie(nes: &mut Nes, path: &Path) -> Result<usize, MovieError> {
+    let movie: Movie = Movie::load(path)?;
+    let mut player: Player = Player::start(movie, nes)?;
+    while player.step(nes)? {
+        if player.frame() as u64 % PROGRESS_INTERVAL == 0 {
+            info!(LOGGER, "Playing"; "frame" => player.frame());
+        }
+    }
+    return Ok(player.frame());
+}
+
+fn stop_condition_met(nes: &Nes, options: &Options) -> bool {
+    if options.until_pc == Some(nes.cpu.registers.pc) {
+        return true;
+    }
+    let check = |condition: &MemoryCondition| -> bool {
+        return nes
+            .cpu
+            .bus
+            .peek(condition.address)
+            .map_or(false, |value| condition.holds(value));
+    };
+    return options.until_mem.as_ref().map_or(false, check);
+}
+
+// PNG unless the file name ends in .ppm
+fn save_screenshot(nes: &Nes, path: &Path) -> io::Result<()> {
+    let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+    let framebuffer = nes.framebuffer();
+    let ppm: bool = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("ppm"));
+    if ppm {
+        image::write_ppm(&mut writer, SCREEN_WIDTH, SCREEN_HEIGHT, &framebuffer)?;
+    } else {
+        image::write_png(&mut writer, SCREEN_WIDTH, SCREEN_HEIGHT, &framebuffer)?;
+    }
+    return writer.flush();
+}
+
+// 16 bytes per line: address, hex bytes and printable ASCII
+fn save_ram_dump(nes: &Nes, path: &Path) -> io::Result<()> {
+    let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+    let ram: &[u8; RAM_SIZE] = nes.cpu.bus.ram();
+    for (line, bytes) in ram.chunks(16).enumerate() {
+        write!(writer, "{:04X}:", line * 16)?;
+        for byte in bytes {
+            write!(writer, " {:02X}", byte)?;
+        }
+        let text: String = bytes
+            .iter()
+            .map(|byte| ternary!(byte.is_ascii_graphic() || *byte == b' ', *byte as char, '.'))
+            .collect();
+        writeln!(writer, "  |{}|", text)?;
+    }
+    return writer.flush();
+}
+
+// TCP when the address has a port, otherwise a Unix socket path
+fn serve_gdb(nes: &mut Nes, address: &str) -> io::Result<()> {
+    if address.contains(':') {
+        return gdb::serve_tcp(nes, address);
+    }
+    #[cfg(unix)]
+    return gdb::serve_unix(nes, address);
+    #[cfg(not(unix))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ));
+}
@@ -0,0 +1,282 @@
+use crate::cpu::bus::JOYPAD1;
+use crate::cpu::device::Device;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// Number of controller ports on the console
+pub const PORTS: usize = 2;
+
+// Bits driven by a controller read, the rest comes from the open bus
+static DATA_MASK: u8 = 0x1F;
+
+// Buttons of the standard controller, in the order they are shifted out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A = (1 << 0),
+    B = (1 << 1),
+    Select = (1 << 2),
+    Start = (1 << 3),
+    Up = (1 << 4),
+    Down = (1 << 5),
+    Left = (1 << 6),
+    Right = (1 << 7),
+}
+
+// Standard joypad, read one button at a time through a shift register.
+//
+// While the strobe is high the shift register keeps reloading from the
+// buttons, so reads return the state of A. Once it goes low each read shifts
+// out the next button, and official controllers return 1 after all 8 have
+// been read.
+pub struct Controller {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        return Self {
+            buttons: 0x00,
+            shift: 0x00,
+            strobe: false,
+        };
+    }
+
+    // Button state as a bitmask of Button values
+    pub fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    // Replace the state of all buttons, typically once per frame by the host
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let buttons: u8 = ternary!(
+            pressed,
+            self.buttons | button as u8,
+            self.buttons & !(button as u8)
+        );
+        self.set_buttons(buttons);
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        return self.buttons & button as u8 != 0;
+    }
+
+    // Bit 0 of a $4016 write
+    pub fn write_strobe(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    // Serial read, returning the next button in bit 0
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 0x01;
+        }
+        let bit: u8 = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        return bit;
+    }
+
+    pub fn peek(&self) -> u8 {
+        return ternary!(self.strobe, self.buttons, self.shift) & 0x01;
+    }
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot for Controller {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.buttons);
+        state.u8(self.shift);
+        state.bool(self.strobe);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.buttons = state.u8()?;
+        self.shift = state.u8()?;
+        self.strobe = state.bool()?;
+        return Ok(());
+    }
+}
+
+// The controller ports, attached to the bus at $4016 -> $4017. Writes to
+// $4016 strobe both controllers, $4017 writes belong to the APU frame counter
+// and are left to it.
+pub struct ControllerPorts {
+    controllers: [Controller; PORTS],
+}
+
+impl ControllerPorts {
+    pub fn new() -> Self {
+        return Self {
+            controllers: [Controller::new(), Controller::new()],
+        };
+    }
+
+    // Controller plugged into a port (0 or 1), for the host to set buttons
+    pub fn controller(&mut self, port: usize) -> Option<&mut Controller> {
+        return self.controllers.get_mut(port);
+    }
+}
+
+impl Default for ControllerPorts {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Device for ControllerPorts {
+    fn read(&mut self, address: u16) -> u8 {
+        return self.controllers[(address - JOYPAD1) as usize].read();
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if address == JOYPAD1 {
+            for controller in self.controllers.iter_mut() {
+                controller.write_strobe(data);
+            }
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return Some(self.controllers[(address - JOYPAD1) as usize].peek());
+    }
+
+    fn open_bus_mask(&self, _address: u16) -> u8 {
+        return !DATA_MASK;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.load(state);
+    }
+}
+
+impl Snapshot for ControllerPorts {
+    fn save(&self, state: &mut StateWriter) {
+        for controller in self.controllers.iter() {
+            controller.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for controller in self.controllers.iter_mut() {
+            controller.load(state)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cpu::bus::{Bus, JOYPAD2};
+
+    // Bus with the ports attached, and a handle to set the buttons through
+    fn setup() -> (Bus, Rc<RefCell<ControllerPorts>>) {
+        let ports: Rc<RefCell<ControllerPorts>> = Rc::new(RefCell::new(ControllerPorts::new()));
+        let mut bus: Bus = Bus::new();
+        bus.attach(JOYPAD1, JOYPAD2, Box::new(ports.clone()));
+        return (bus, ports);
+    }
+
+    fn set_button(ports: &Rc<RefCell<ControllerPorts>>, port: usize, button: Button) {
+        ports
+            .borrow_mut()
+            .controller(port)
+            .unwrap()
+            .set_button(button, true);
+    }
+
+    // Eight reads of a port, bit 0 of each
+    fn read_buttons(bus: &mut Bus, address: u16) -> Vec<u8> {
+        return (0..8).map(|_| bus.read(address) & 0x01).collect();
+    }
+
+    #[test]
+    fn buttons_are_shifted_out_after_the_strobe() {
+        let (mut bus, ports) = setup();
+        for button in [Button::A, Button::Start, Button::Left].iter() {
+            set_button(&ports, 0, *button);
+        }
+        set_button(&ports, 1, Button::B);
+
+        // While the strobe is high every read returns A
+        bus.write(0x4016, 0x01);
+        assert_eq!(read_buttons(&mut bus, 0x4016), [1; 8]);
+        assert_eq!(read_buttons(&mut bus, 0x4017), [0; 8]);
+
+        // One strobe latches both controllers, read in the order A, B,
+        // Select, Start, Up, Down, Left, Right and then 1
+        bus.write(0x4016, 0x00);
+        assert_eq!(read_buttons(&mut bus, 0x4016), [1, 0, 0, 1, 0, 0, 1, 0]);
+        assert_eq!(read_buttons(&mut bus, 0x4016), [1; 8]);
+        assert_eq!(read_buttons(&mut bus, 0x4017), [0, 1, 0, 0, 0, 0, 0, 0]);
+
+        // Presses after the strobe went low wait for the next one
+        bus.write(0x4016, 0x01);
+        bus.write(0x4016, 0x00);
+        bus.read(0x4016);
+        set_button(&ports, 0, Button::B);
+        assert_eq!(bus.read(0x4016) & 0x01, 0);
+        ports
+            .borrow_mut()
+            .controller(0)
+            .unwrap()
+            .set_button(Button::A, false);
+        bus.write(0x4016, 0x01);
+        assert_eq!(bus.read(0x4016) & 0x01, 0);
+        bus.write(0x4016, 0x00);
+        assert_eq!(read_buttons(&mut bus, 0x4016), [0, 1, 0, 1, 0, 0, 1, 0]);
+
+        // $4017 writes don't strobe, they belong to the APU
+        bus.write(0x4017, 0x01);
+        assert_eq!(bus.read(0x4016) & 0x01, 1);
+        assert_eq!(read_buttons(&mut bus, 0x4017), [0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn upper_bits_of_controller_reads_are_open_bus() {
+        let (mut bus, ports) = setup();
+        set_button(&ports, 0, Button::A);
+        bus.write(0x4016, 0x01);
+
+        // Usually the high byte of the address, left by LDA $4016
+        bus.write(0x4018, 0x40);
+        assert_eq!(bus.read(0x4016), 0x41);
+        assert_eq!(bus.peek(0x4016), Some(0x41));
+        assert_eq!(bus.read(0x4017), 0x40);
+
+        // Only bits 0 -> 4 are driven
+        bus.write(0x4018, 0xFF);
+        assert_eq!(bus.read(0x4017), 0xE0);
+        assert_eq!(bus.read(0x4016), 0xE1);
+    }
+
+    #[test]
+    fn only_two_ports() {
+        let mut ports: ControllerPorts = ControllerPorts::new();
+        assert!(ports.controller(1).is_some());
+        assert!(ports.controller(2).is_none());
+    }
+}
@@ -0,0 +1,903 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::cpu::bus::Bus;
+use crate::cpu::disasm::SymbolTable;
+use crate::cpu::instructions::{AddrMode, LOOKUP};
+use crate::ternary;
+
+// Two pass 6502 assembler, mainly for writing tests and patches:
+//
+//  PPUSTATUS = $2002       ; constants
+//          .org $8000      ; address of what follows
+//  reset:  LDX #$FF        ; labels end with a colon
+//          TXS
+//  @wait:  BIT PPUSTATUS   ; local labels start with @ and belong to the
+//          BPL @wait       ; global label before them (reset@wait)
+//          LDA #>table + 1 ; < and > take the low and high byte
+//          JMP reset
+//  table:  .byte 1, %10, "text", 'c'
+//          .word reset, * + 2  ; * is the address of the statement
+//
+// Numbers are $hex, %binary, decimal or 'c'haracters. Operands known to fit
+// in the zero page by the first pass use zero page addressing, unless they
+// are written as a 4 digit hex number ($0010). This keeps the output of the
+// disassembler assembling back to the same bytes; only alternative encodings
+// of an instruction (e.g. the unofficial NOPs) assemble to the official one,
+// or the first in the opcode table.
+
+// Error with the source line (from 1) it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: String) -> Self {
+        return Self { line, message };
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Line {}: {}", self.line, self.message);
+    }
+}
+
+impl Error for AsmError {}
+
+// Bytes assembled to consecutive addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+// Output of the assembler
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub segments: Vec<Segment>,
+    pub symbols: HashMap<String, u16>, // labels and constants, local labels as global@local
+}
+
+impl Assembly {
+    // Lowest address written to
+    pub fn origin(&self) -> u16 {
+        return self
+            .segments
+            .iter()
+            .map(|s| s.origin)
+            .min()
+            .unwrap_or(0x0000);
+    }
+
+    // Memory image from origin() to the last byte written, with any gaps
+    // between segments filled with $00
+    pub fn bytes(&self) -> Vec<u8> {
+        let origin: usize = self.origin() as usize;
+        let end: usize = self
+            .segments
+            .iter()
+            .map(|s| s.origin as usize + s.bytes.len())
+            .max()
+            .unwrap_or(origin);
+        let mut image: Vec<u8> = vec![0x00; end - origin];
+        for segment in &self.segments {
+            let start: usize = segment.origin as usize - origin;
+            image[start..start + segment.bytes.len()].copy_from_slice(&segment.bytes);
+        }
+        return image;
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        return self.symbols.get(name).copied();
+    }
+
+    // Write every segment through the bus, e.g. into RAM or a test device
+    pub fn load_into(&self, bus: &mut Bus) {
+        for segment in &self.segments {
+            for (offset, byte) in segment.bytes.iter().enumerate() {
+                bus.write(segment.origin.wrapping_add(offset as u16), *byte);
+            }
+        }
+    }
+
+    // Symbols by address for the disassembler. When several share an
+    // address global names win over local ones, then the alphabetically first.
+    pub fn symbol_table(&self) -> SymbolTable {
+        let mut table: SymbolTable = SymbolTable::new();
+        let rank = |name: &str| -> (bool, String) { (name.contains('@'), name.to_string()) };
+        for (name, address) in &self.symbols {
+            let better: bool = match table.get(address) {
+                Some(current) => rank(name) < rank(current),
+                None => true,
+            };
+            if better {
+                table.insert(*address, name.clone());
+            }
+        }
+        return table;
+    }
+}
+
+// Assemble source into bytes and symbols
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let lines: Vec<Line> = parse(source)?;
+    let mut assembler: Assembler = Assembler::default();
+    let modes: Vec<Option<AddrMode>> = assembler.first_pass(&lines)?;
+    assembler.resolve_constants(&lines)?;
+    assembler.second_pass(&lines, &modes)?;
+
+    let symbols: HashMap<String, u16> = assembler
+        .symbols
+        .into_iter()
+        .map(|(name, value)| (name, value as u16))
+        .collect();
+    return Ok(Assembly {
+        segments: assembler.segments,
+        symbols,
+    });
+}
+
+// Opcode of an instruction in an addressing mode, preferring the official
+// encoding when there are several
+pub fn opcode_for(mnemonic: &str, mode: AddrMode) -> Option<u8> {
+    let mut found: Option<u8> = None;
+    for (opcode, instruction) in LOOKUP.iter().enumerate() {
+        if instruction.name == mnemonic && instruction.mode == mode {
+            if instruction.official {
+                return Some(opcode as u8);
+            }
+            found = found.or(Some(opcode as u8));
+        }
+    }
+    return found;
+}
+
+/*
+
+PARSING
+
+*/
+
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Pc, // address of the current statement
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(Expr),
+    Direct(Expr, Index, bool), // true when written as a 4 digit hex number
+    Indirect(Expr),
+    IndexedIndirect(Expr), // (zp,X)
+    IndirectIndexed(Expr), // (zp),Y
+}
+
+enum Statement {
+    Label(String),
+    Constant(String, Expr),
+    Org(Expr),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    Instruction(String, Operand),
+}
+
+struct Line {
+    number: usize,
+    statement: Statement,
+}
+
+fn parse(source: &str) -> Result<Vec<Line>, AsmError> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut scope: String = String::new(); // last global label
+    for (index, text) in source.lines().enumerate() {
+        let number: usize = index + 1;
+        let mut push = |statement: Statement| lines.push(Line { number, statement });
+        let error = |message: String| AsmError::new(number, message);
+
+        let mut rest: &str = strip_comment(text).trim();
+        while let Some((label, after)) = split_label(rest) {
+            if !label.starts_with('@') {
+                scope = label.to_string();
+            }
+            push(Statement::Label(qualify(label, &scope)));
+            rest = after.trim_start();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (word, arguments) = match rest.find(char::is_whitespace) {
+            Some(split) => (&rest[..split], rest[split..].trim()),
+            None => (rest, ""),
+        };
+        if let Some((name, value)) = rest.split_once('=') {
+            let name: &str = name.trim();
+            if is_identifier(name) && !name.starts_with('@') {
+                push(Statement::Constant(
+                    name.to_string(),
+                    parse_expr(value, &scope).map_err(error)?,
+                ));
+                continue;
+            }
+        }
+
+        let statement: Statement = match word.to_ascii_lowercase().as_str() {
+            ".org" => Statement::Org(parse_expr(arguments, &scope).map_err(error)?),
+            ".byte" => Statement::Bytes(parse_bytes(arguments, &scope).map_err(error)?),
+            ".word" => {
+                let values: Result<Vec<Expr>, String> = split_top_level(arguments)
+                    .iter()
+                    .map(|value| parse_expr(value, &scope))
+                    .collect();
+                Statement::Words(values.map_err(error)?)
+            }
+            directive if directive.starts_with('.') => {
+                return Err(error(format!("Unknown directive {}", word)));
+            }
+            _ => Statement::Instruction(
+                word.to_ascii_uppercase(),
+                parse_operand(arguments, &scope).map_err(error)?,
+            ),
+        };
+        push(statement);
+    }
+    return Ok(lines);
+}
+
+// Cut a ; comment, ignoring any inside quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..index],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    return text;
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    let first_ok: bool = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == '@');
+    return first_ok && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+// "name: rest" -> (name, rest)
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (name, rest) = text.split_once(':')?;
+    return ternary!(is_identifier(name), Some((name, rest)), None);
+}
+
+// Local labels are stored under the global label they belong to
+fn qualify(name: &str, scope: &str) -> String {
+    return ternary!(
+        name.starts_with('@'),
+        format!("{}{}", scope, name),
+        name.to_string()
+    );
+}
+
+// Split on commas outside parentheses and quotes
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = Vec::new();
+    let (mut depth, mut quote, mut start) = (0i32, None::<char>, 0usize);
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    return parts;
+}
+
+fn parse_index(text: &str) -> Result<Index, String> {
+    return match text.to_ascii_uppercase().as_str() {
+        "X" => Ok(Index::X),
+        "Y" => Ok(Index::Y),
+        _ => Err(format!("Invalid index register {}", text)),
+    };
+}
+
+fn parse_operand(text: &str, scope: &str) -> Result<Operand, String> {
+    if text.is_empty() {
+        return Ok(Operand::None);
+    }
+    if text.eq_ignore_ascii_case("A") {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_expr(value, scope)?));
+    }
+
+    let parts: Vec<&str> = split_top_level(text);
+    let (base, index) = match parts.len() {
+        1 => (parts[0], Index::None),
+        2 => (parts[0], parse_index(parts[1])?),
+        _ => return Err(format!("Invalid operand {}", text)),
+    };
+
+    // Indirect when the whole base is in parentheses, not e.g. (1+2)*3
+    if base.starts_with('(') && closing_paren(base) == Some(base.len() - 1) {
+        let inner: Vec<&str> = split_top_level(&base[1..base.len() - 1]);
+        return match (inner.len(), index) {
+            (2, Index::None) if parse_index(inner[1])? == Index::X => {
+                Ok(Operand::IndexedIndirect(parse_expr(inner[0], scope)?))
+            }
+            (1, Index::Y) => Ok(Operand::IndirectIndexed(parse_expr(inner[0], scope)?)),
+            (1, Index::None) => Ok(Operand::Indirect(parse_expr(inner[0], scope)?)),
+            (1, Index::X) => Ok(Operand::Direct(parse_expr(base, scope)?, index, false)),
+            _ => Err(format!("Invalid indirect operand {}", text)),
+        };
+    }
+
+    let word: bool =
+        base.len() > 3 && base.starts_with('$') && base[1..].chars().all(|c| c.is_ascii_hexdigit());
+    return Ok(Operand::Direct(parse_expr(base, scope)?, index, word));
+}
+
+// Position of the parenthesis closing the one text starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth: i32 = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    return None;
+}
+
+fn parse_bytes(text: &str, scope: &str) -> Result<Vec<Expr>, String> {
+    let mut values: Vec<Expr> = Vec::new();
+    for part in split_top_level(text) {
+        match part.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(string) => values.extend(string.bytes().map(|b| Expr::Number(b as i64))),
+            None => values.push(parse_expr(part, scope)?),
+        }
+    }
+    return Ok(values);
+}
+
+/*
+
+EXPRESSIONS
+
+*/
+
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+static OPERATORS: [&str; 13] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "<", ">",
+];
+
+// Binary operators from loosest to tightest binding
+static PRECEDENCE: [(&str, u8); 10] = [
+    ("|", 1),
+    ("^", 2),
+    ("&", 3),
+    ("<<", 4),
+    (">>", 4),
+    ("+", 5),
+    ("-", 5),
+    ("*", 6),
+    ("/", 6),
+    ("%", 6),
+];
+
+fn parse_number(digits: &str, radix: u32) -> Result<i64, String> {
+    return i64::from_str_radix(digits, radix).map_err(|_| format!("Invalid number {}", digits));
+}
+
+fn tokenize(text: &str, scope: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+    let take_while = |start: usize, f: &dyn Fn(char) -> bool| -> usize {
+        let mut end: usize = start;
+        while end < chars.len() && f(chars[end]) {
+            end += 1;
+        }
+        return end;
+    };
+
+    while i < chars.len() {
+        let c: char = chars[i];
+        // Whether an operand is expected, which makes % a binary number
+        let operand_expected: bool =
+            matches!(tokens.last(), None | Some(Token::Op(_)) | Some(Token::Open));
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '$' {
+            let end: usize = take_while(i + 1, &|c| c.is_ascii_hexdigit());
+            let digits: String = chars[i + 1..end].iter().collect();
+            tokens.push(Token::Number(parse_number(&digits, 16)?));
+            i = end;
+        } else if c == '%' && operand_expected {
+            let end: usize = take_while(i + 1, &|c| c == '0' || c == '1');
+            let digits: String = chars[i + 1..end].iter().collect();
+            tokens.push(Token::Number(parse_number(&digits, 2)?));
+            i = end;
+        } else if c.is_ascii_digit() {
+            let end: usize = take_while(i, &|c| c.is_ascii_digit());
+            let digits: String = chars[i..end].iter().collect();
+            tokens.push(Token::Number(parse_number(&digits, 10)?));
+            i = end;
+        } else if c == '\'' {
+            if i + 2 >= chars.len() || chars[i + 2] != '\'' {
+                return Err(String::from("Invalid character literal"));
+            }
+            tokens.push(Token::Number(chars[i + 1] as i64));
+            i += 3;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' {
+            let end: usize = take_while(i + 1, &|c| c.is_ascii_alphanumeric() || c == '_');
+            let name: String = chars[i..end].iter().collect();
+            tokens.push(Token::Symbol(qualify(&name, scope)));
+            i = end;
+        } else if c == '(' || c == ')' {
+            tokens.push(ternary!(c == '(', Token::Open, Token::Close));
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            let op: &'static str = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or(format!("Unexpected character {}", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    return Ok(tokens);
+}
+
+fn parse_expr(text: &str, scope: &str) -> Result<Expr, String> {
+    let tokens: Vec<Token> = tokenize(text.trim(), scope)?;
+    if tokens.is_empty() {
+        return Err(String::from("Missing expression"));
+    }
+    let mut parser: ExprParser = ExprParser {
+        tokens,
+        position: 0,
+    };
+    let expr: Expr = parser.binary(0)?;
+    if parser.position < parser.tokens.len() {
+        return Err(format!("Unexpected text in expression {}", text.trim()));
+    }
+    return Ok(expr);
+}
+
+// Precedence climbing parser over the tokens of an expression
+struct ExprParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl ExprParser {
+    fn next(&mut self) -> Option<&Token> {
+        let token: Option<&Token> = self.tokens.get(self.position);
+        self.position += 1;
+        return token;
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left: Expr = self.unary()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.position) {
+            let op: &'static str = op;
+            let precedence: u8 = match PRECEDENCE.iter().find(|(name, _)| *name == op) {
+                Some((_, precedence)) if *precedence > min_precedence => *precedence,
+                _ => break,
+            };
+            self.position += 1;
+            let right: Expr = self.binary(precedence)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        return Ok(left);
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        return match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(*value)),
+            Some(Token::Symbol(name)) => Ok(Expr::Symbol(name.clone())),
+            Some(Token::Op("*")) => Ok(Expr::Pc),
+            Some(Token::Op(op)) if ["-", "~", "<", ">"].contains(op) => {
+                let op: &'static str = op;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                let expr: Expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(String::from("Missing )")),
+                }
+            }
+            _ => Err(String::from("Invalid expression")),
+        };
+    }
+}
+
+enum EvalError {
+    Undefined(String),
+    Invalid(String),
+}
+
+/*
+
+ASSEMBLY
+
+*/
+
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    pc: u32,
+    addresses: Vec<u32>, // address of each line, from the first pass
+    segments: Vec<Segment>,
+}
+
+impl Assembler {
+    fn eval(&self, expr: &Expr) -> Result<i64, EvalError> {
+        return match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Pc => Ok(self.pc as i64),
+            Expr::Symbol(name) => match self.symbols.get(name) {
+                Some(value) => Ok(*value),
+                None => Err(EvalError::Undefined(name.clone())),
+            },
+            Expr::Unary(op, value) => {
+                let value: i64 = self.eval(value)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    "<" => value & 0xFF,
+                    _ => (value >> 8) & 0xFF,
+                })
+            }
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                let shift = |value: i64| -> Result<u32, EvalError> {
+                    return u32::try_from(value)
+                        .ok()
+                        .filter(|shift| *shift < 64)
+                        .ok_or(EvalError::Invalid(format!("Invalid shift by {}", value)));
+                };
+                let divisor = |value: i64| -> Result<i64, EvalError> {
+                    return ternary!(
+                        value == 0,
+                        Err(EvalError::Invalid(String::from("Division by zero"))),
+                        Ok(value)
+                    );
+                };
+                Ok(match *op {
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" => left.wrapping_div(divisor(right)?),
+                    "%" => left.wrapping_rem(divisor(right)?),
+                    "&" => left & right,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "<<" => left << shift(right)?,
+                    _ => left >> shift(right)?,
+                })
+            }
+        };
+    }
+
+    // Evaluate a value every symbol of which must be known
+    fn value(&self, line: usize, expr: &Expr) -> Result<i64, AsmError> {
+        return self.eval(expr).map_err(|e| {
+            AsmError::new(
+                line,
+                match e {
+                    EvalError::Undefined(name) => format!("Undefined symbol {}", name),
+                    EvalError::Invalid(message) => message,
+                },
+            )
+        });
+    }
+
+    fn define(&mut self, line: usize, name: &str, value: i64) -> Result<(), AsmError> {
+        if !(0..=0xFFFF).contains(&value) {
+            return Err(AsmError::new(
+                line,
+                format!("Value of {} is out of range: {}", name, value),
+            ));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(AsmError::new(
+                line,
+                format!("{} is defined more than once", name),
+            ));
+        }
+        return Ok(());
+    }
+
+    fn advance(&mut self, line: usize, length: u32) -> Result<(), AsmError> {
+        self.pc += length;
+        if self.pc > 0x10000 {
+            return Err(AsmError::new(line, String::from("Code runs past $FFFF")));
+        }
+        return Ok(());
+    }
+
+    // Lay out the code: define labels and choose addressing modes. Values
+    // that aren't known yet are assumed to need 16 bits.
+    fn first_pass(&mut self, lines: &[Line]) -> Result<Vec<Option<AddrMode>>, AsmError> {
+        let mut modes: Vec<Option<AddrMode>> = Vec::new();
+        self.pc = 0;
+        for line in lines {
+            self.addresses.push(self.pc);
+            let mut mode: Option<AddrMode> = None;
+            match &line.statement {
+                Statement::Label(name) => self.define(line.number, name, self.pc as i64)?,
+                Statement::Constant(name, expr) => match self.eval(expr) {
+                    Ok(value) => self.define(line.number, name, value)?,
+                    Err(EvalError::Undefined(_)) => {} // resolved after the pass
+                    Err(EvalError::Invalid(_)) => {
+                        self.value(line.number, expr)?;
+                    }
+                },
+                Statement::Org(expr) => self.pc = self.org(line.number, expr)?,
+                Statement::Bytes(values) => self.advance(line.number, values.len() as u32)?,
+                Statement::Words(values) => self.advance(line.number, 2 * values.len() as u32)?,
+                Statement::Instruction(mnemonic, operand) => {
+                    let selected: AddrMode = self.select_mode(line.number, mnemonic, operand)?;
+                    self.advance(line.number, selected.length() as u32)?;
+                    mode = Some(selected);
+                }
+            }
+            modes.push(mode);
+        }
+        return Ok(modes);
+    }
+
+    fn org(&self, line: usize, expr: &Expr) -> Result<u32, AsmError> {
+        let address: i64 = self.value(line, expr)?;
+        if !(0..=0xFFFF).contains(&address) {
+            return Err(AsmError::new(
+                line,
+                format!("Invalid .org address {}", address),
+            ));
+        }
+        return Ok(address as u32);
+    }
+
+    // Define the constants that refer to labels further on, once all labels
+    // are known
+    fn resolve_constants(&mut self, lines: &[Line]) -> Result<(), AsmError> {
+        loop {
+            let mut progress: bool = false;
+            let mut pending: Option<(usize, &Expr)> = None;
+            for (index, line) in lines.iter().enumerate() {
+                if let Statement::Constant(name, expr) = &line.statement {
+                    if self.symbols.contains_key(name) {
+                        continue;
+                    }
+                    self.pc = self.addresses[index];
+                    match self.eval(expr) {
+                        Ok(value) => {
+                            self.define(line.number, name, value)?;
+                            progress = true;
+                        }
+                        Err(_) => pending = pending.or(Some((line.number, expr))),
+                    }
+                }
+            }
+            match pending {
+                None => return Ok(()),
+                Some((line, expr)) if !progress => return self.value(line, expr).map(|_| ()),
+                Some(_) => {}
+            }
+        }
+    }
+
+    // Addressing mode from the operand syntax, the instruction and whether
+    // the operand fits in the zero page
+    fn select_mode(
+        &self,
+        line: usize,
+        mnemonic: &str,
+        operand: &Operand,
+    ) -> Result<AddrMode, AsmError> {
+        if !LOOKUP
+            .iter()
+            .any(|instruction| instruction.name == mnemonic)
+        {
+            return Err(AsmError::new(
+                line,
+                format!("Unknown instruction {}", mnemonic),
+            ));
+        }
+        let has = |mode: AddrMode| opcode_for(mnemonic, mode).is_some();
+        let zero_page = |expr: &Expr, word: bool| -> bool {
+            return !word && matches!(self.eval(expr), Ok(value) if (0..=0xFF).contains(&value));
+        };
+        let direct = |expr: &Expr, index: Index, word: bool| -> Vec<AddrMode> {
+            let small: bool = zero_page(expr, word);
+            let (zp, absolute) = match index {
+                Index::None if has(AddrMode::REL) => return vec![AddrMode::REL],
+                Index::None => (AddrMode::ZP0, AddrMode::ABS),
+                Index::X => (AddrMode::ZPX, AddrMode::ABX),
+                Index::Y => (AddrMode::ZPY, AddrMode::ABY),
+            };
+            return ternary!(small, vec![zp, absolute], vec![absolute, zp]);
+        };
+
+        let candidates: Vec<AddrMode> = match operand {
+            // BRK skips the byte after it, which is left as padding
+            Operand::None if mnemonic == "BRK" => vec![AddrMode::IMM],
+            Operand::None => vec![AddrMode::IMP, AddrMode::ACC],
+            Operand::Accumulator => vec![AddrMode::ACC],
+            Operand::Immediate(_) => vec![AddrMode::IMM],
+            Operand::IndexedIndirect(_) => vec![AddrMode::IZX],
+            Operand::IndirectIndexed(_) => vec![AddrMode::IZY],
+            Operand::Indirect(_) if has(AddrMode::IND) => vec![AddrMode::IND],
+            Operand::Indirect(expr) => direct(expr, Index::None, false),
+            Operand::Direct(expr, index, word) => direct(expr, *index, *word),
+        };
+        return candidates
+            .into_iter()
+            .find(|mode| has(*mode))
+            .ok_or(AsmError::new(
+                line,
+                format!("Addressing mode not supported by {}", mnemonic),
+            ));
+    }
+
+    fn emit(&mut self, byte: u8) {
+        let pc: u32 = self.pc;
+        match self.segments.last_mut() {
+            Some(segment) if segment.origin as u32 + segment.bytes.len() as u32 == pc => {
+                segment.bytes.push(byte);
+            }
+            _ => self.segments.push(Segment {
+                origin: pc as u16,
+                bytes: vec![byte],
+            }),
+        }
+        self.pc += 1;
+    }
+
+    fn emit_byte(&mut self, line: usize, value: i64) -> Result<(), AsmError> {
+        if !(-0x80..=0xFF).contains(&value) {
+            return Err(AsmError::new(
+                line,
+                format!("Value {} doesn't fit in a byte", value),
+            ));
+        }
+        self.emit(value as u8);
+        return Ok(());
+    }
+
+    fn emit_word(&mut self, line: usize, value: i64) -> Result<(), AsmError> {
+        if !(-0x8000..=0xFFFF).contains(&value) {
+            return Err(AsmError::new(
+                line,
+                format!("Value {} doesn't fit in a word", value),
+            ));
+        }
+        self.emit(value as u8);
+        self.emit((value >> 8) as u8);
+        return Ok(());
+    }
+
+    fn second_pass(&mut self, lines: &[Line], modes: &[Option<AddrMode>]) -> Result<(), AsmError> {
+        self.pc = 0;
+        for (line, mode) in lines.iter().zip(modes) {
+            let number: usize = line.number;
+            match &line.statement {
+                Statement::Label(_) | Statement::Constant(_, _) => {}
+                Statement::Org(expr) => self.pc = self.org(number, expr)?,
+                Statement::Bytes(values) => {
+                    for value in values {
+                        let value: i64 = self.value(number, value)?;
+                        self.emit_byte(number, value)?;
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let value: i64 = self.value(number, value)?;
+                        self.emit_word(number, value)?;
+                    }
+                }
+                Statement::Instruction(mnemonic, operand) => {
+                    let mode: AddrMode = mode.expect("Mode chosen in the first pass");
+                    self.encode(number, mnemonic, operand, mode)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn encode(
+        &mut self,
+        line: usize,
+        mnemonic: &str,
+        operand: &Operand,
+        mode: AddrMode,
+    ) -> Result<(), AsmError> {
+        let start: u32 = self.pc;
+        let value: i64 = match operand {
+            Operand::None | Operand::Accumulator => 0,
+            Operand::Immediate(expr)
+            | Operand::Direct(expr, _, _)
+            | Operand::Indirect(expr)
+            | Operand::IndexedIndirect(expr)
+            | Operand::IndirectIndexed(expr) => self.value(line, expr)?,
+        };
+
+        self.emit(opcode_for(mnemonic, mode).expect("Mode checked in the first pass"));
+        return match mode {
+            AddrMode::IMP | AddrMode::ACC => Ok(()),
+            AddrMode::REL => {
+                let offset: i64 = value - (start as i64 + 2);
+                if !(-0x80..=0x7F).contains(&offset) {
+                    return Err(AsmError::new(
+                        line,
+                        format!("Branch target is out of range ({} bytes)", offset),
+                    ));
+                }
+                self.emit_byte(line, offset)
+            }
+            AddrMode::ABS | AddrMode::ABX | AddrMode::ABY | AddrMode::IND => {
+                if !(0..=0xFFFF).contains(&value) {
+                    return Err(AsmError::new(
+                        line,
+                        format!("Address {} is out of range", value),
+                    ));
+                }
+                self.emit_word(line, value)
+            }
+            AddrMode::IMM => self.emit_byte(line, value),
+            _ => {
+                if !(0..=0xFF).contains(&value) {
+                    return Err(AsmError::new(
+                        line,
+                        format!("Address ${:X} is not in the zero page", value),
+                    ));
+                }
+                self.emit_byte(line, value)
+            }
+        };
+    }
+}
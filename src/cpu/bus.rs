@@ -1,30 +1,255 @@
-use crate::cpu::utils;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::cartridge::Cartridge;
+use crate::cpu::device::Device;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+// CPU memory map (see design/6502Architecture.csv)
+//
+// $0000 -> $07FF   2KB internal RAM
+// $0800 -> $1FFF   Mirrors of $0000 -> $07FF
+// $2000 -> $2007   PPU registers
+// $2008 -> $3FFF   Mirrors of $2000 -> $2007 (repeats every 8 bytes)
+// $4000 -> $4017   APU and I/O registers
+// $4018 -> $401F   APU and I/O test functionality (normally disabled)
+// $4020 -> $FFFF   Cartridge space (PRG ROM, PRG RAM and mapper registers)
+pub const RAM_START: u16 = 0x0000;
+pub const RAM_END: u16 = 0x1FFF;
+pub const RAM_SIZE: usize = 0x0800;
+pub const RAM_MIRROR_MASK: u16 = 0x07FF;
+
+pub const PPU_REGISTERS_START: u16 = 0x2000;
+pub const PPU_REGISTERS_END: u16 = 0x3FFF;
+
+pub const APU_IO_START: u16 = 0x4000;
+pub const APU_IO_END: u16 = 0x4017;
+
+// Writing XX to $4014 copies the page $XX00 -> $XXFF into PPU OAM through
+// OAMDATA, suspending the CPU meanwhile
+pub const OAM_DMA: u16 = 0x4014;
+pub const OAM_DATA: u16 = 0x2004;
+
+// Controller ports (see ControllerPorts), $4017 is shared with the APU
+// frame counter
+pub const JOYPAD1: u16 = 0x4016;
+pub const JOYPAD2: u16 = 0x4017;
+
+pub const TEST_MODE_START: u16 = 0x4018;
+pub const TEST_MODE_END: u16 = 0x401F;
+
+pub const CARTRIDGE_START: u16 = 0x4020;
+pub const CARTRIDGE_END: u16 = 0xFFFF;
+
+// CPU access to a watched address, see Bus::watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub data: u8,
+    pub write: bool,
+}
+
+// Device attached to an inclusive address range
+struct MappedDevice {
+    start: u16,
+    end: u16,
+    device: Box<dyn Device>,
+}
 
 pub struct Bus {
-    ram: [u16; 2048], // change this later
+    ram: [u8; RAM_SIZE],
+    devices: Vec<MappedDevice>,
+    oam_dma_page: Option<u8>, // page written to $4014, until the CPU performs the DMA
+    data_bus: u8,             // last value driven on the data bus (open bus)
+    watched: Vec<(u16, u16)>, // inclusive ranges whose accesses are recorded
+    accesses: Vec<BusAccess>, // recorded accesses, until taken
 }
 impl Bus {
     pub fn new() -> Self {
         Self {
-            ram: [0x0000; 2048],
+            ram: [0x00; RAM_SIZE],
+            devices: Vec::new(),
+            oam_dma_page: None,
+            data_bus: 0x00,
+            watched: Vec::new(),
+            accesses: Vec::new(),
         }
     }
 
-    // Read from RAM
-    pub fn read(&self, address: u16) -> u16 {
-        if utils::check_hex_range(address) {
-            return self.ram[address as usize];
-        } else {
-            panic!("Tried to read from an illegal hex address")
+    // Attach a device to the inclusive address range start..=end. Devices
+    // take precedence over internal RAM. When ranges overlap the device
+    // attached first answers reads, while writes reach all of them (e.g.
+    // $4017 is read from the controller ports and written to the APU).
+    pub fn attach(&mut self, start: u16, end: u16, device: Box<dyn Device>) {
+        assert!(start <= end, "Device address range is empty");
+        self.devices.push(MappedDevice { start, end, device });
+    }
+
+    // Map a cartridge into cartridge space ($4020 -> $FFFF). The cartridge is
+    // shared since the PPU also reads CHR data from it.
+    pub fn insert_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
+        self.attach(CARTRIDGE_START, CARTRIDGE_END, Box::new(cartridge));
+    }
+
+    // Internal RAM, without its mirrors
+    pub fn ram(&self) -> &[u8; RAM_SIZE] {
+        return &self.ram;
+    }
+
+    // Let the attached devices count a CPU cycle
+    pub fn cpu_clock(&mut self) {
+        for mapped in self.devices.iter_mut() {
+            mapped.device.cpu_clock();
         }
     }
 
-    // Write to RAM
-    pub fn write(&mut self, address: u16, data: u8) -> () {
-        if utils::check_hex_range(address) {
-            self.ram[address as usize] = data as u16;
-        } else {
-            panic!("Tried to write to an illegal hex address")
+    // Page of a pending OAM DMA, clearing the request
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        return self.oam_dma_page.take();
+    }
+
+    // Record reads and writes within the given inclusive ranges (for
+    // debugger watchpoints), replacing any previous ones. Peeks are not
+    // recorded.
+    pub fn watch(&mut self, ranges: Vec<(u16, u16)>) {
+        self.watched = ranges;
+        self.accesses.clear();
+    }
+
+    // Accesses recorded since the last call
+    pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+        return std::mem::take(&mut self.accesses);
+    }
+
+    fn record(&mut self, address: u16, data: u8, write: bool) {
+        if self
+            .watched
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&address))
+        {
+            self.accesses.push(BusAccess {
+                address,
+                data,
+                write,
+            });
+        }
+    }
+
+    // Find the device responsible for an address, if any
+    fn device_for(&mut self, address: u16) -> Option<&mut MappedDevice> {
+        return self
+            .devices
+            .iter_mut()
+            .find(|mapped| (mapped.start..=mapped.end).contains(&address));
+    }
+
+    // Read a byte from the CPU address space
+    pub fn read(&mut self, address: u16) -> u8 {
+        let data: u8 = self.read_data(address);
+        self.record(address, data, false);
+        return data;
+    }
+
+    // Nothing drives the data bus for unmapped addresses, or for the bits a
+    // device leaves undriven, so they read as the last value seen on it
+    fn read_data(&mut self, address: u16) -> u8 {
+        let open_bus: u8 = self.data_bus;
+        if let Some(mapped) = self.device_for(address) {
+            let data: u8 = mapped.device.read(address);
+            let mask: u8 = mapped.device.open_bus_mask(address);
+            self.data_bus = (open_bus & mask) | (data & !mask);
+            return self.data_bus;
+        }
+        self.data_bus = match address {
+            RAM_START..=RAM_END => self.ram[(address & RAM_MIRROR_MASK) as usize],
+            PPU_REGISTERS_START..=PPU_REGISTERS_END
+            | APU_IO_START..=APU_IO_END
+            | TEST_MODE_START..=TEST_MODE_END
+            | CARTRIDGE_START..=CARTRIDGE_END => self.data_bus,
+        };
+        return self.data_bus;
+    }
+
+    // Read a byte without side effects on any device. Returns None when the
+    // address is unmapped or its device can't be peeked.
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        if let Some(mapped) = self
+            .devices
+            .iter()
+            .find(|mapped| (mapped.start..=mapped.end).contains(&address))
+        {
+            let mask: u8 = mapped.device.open_bus_mask(address);
+            return mapped
+                .device
+                .peek(address)
+                .map(|data| (self.data_bus & mask) | (data & !mask));
+        }
+        return match address {
+            RAM_START..=RAM_END => Some(self.ram[(address & RAM_MIRROR_MASK) as usize]),
+            _ => None,
+        };
+    }
+
+    // Write a byte to the CPU address space. Writes to unmapped addresses
+    // are ignored.
+    pub fn write(&mut self, address: u16, data: u8) {
+        self.record(address, data, true);
+        self.data_bus = data;
+        if address == OAM_DMA {
+            self.oam_dma_page = Some(data);
+            return;
+        }
+        let mut mapped: bool = false;
+        for device in self
+            .devices
+            .iter_mut()
+            .filter(|device| (device.start..=device.end).contains(&address))
+        {
+            device.device.write(address, data);
+            mapped = true;
+        }
+        if mapped {
+            return;
+        }
+        if let RAM_START..=RAM_END = address {
+            self.ram[(address & RAM_MIRROR_MASK) as usize] = data;
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// RAM and every attached device, each in its own section.
+// Devices are matched up by the order they were attached in.
+impl Snapshot for Bus {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        state.bool(self.oam_dma_page.is_some());
+        state.u8(self.oam_dma_page.unwrap_or(0x00));
+        state.u8(self.data_bus);
+        state.u32(self.devices.len() as u32);
+        for mapped in self.devices.iter() {
+            state.section(|device| mapped.device.save_state(device));
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.ram)?;
+        let dma_pending: bool = state.bool()?;
+        let dma_page: u8 = state.u8()?;
+        self.oam_dma_page = ternary!(dma_pending, Some(dma_page), None);
+        self.data_bus = state.u8()?;
+        if state.u32()? as usize != self.devices.len() {
+            return Err(StateError::Invalid("number of attached devices"));
+        }
+        for mapped in self.devices.iter_mut() {
+            state.section(|device| mapped.device.load_state(device))?;
         }
+        return Ok(());
     }
 }
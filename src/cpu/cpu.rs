@@ -1,12 +1,29 @@
-#![allow(non_snake_case, dead_code)]
+#![allow(non_snake_case, dead_code, clippy::upper_case_acronyms)]
+use crate::apu::apu::{APU, DMC_STALL_CYCLES};
 use crate::cpu::bus;
 use crate::cpu::flags::StatusRegFlags;
+use crate::cpu::instructions::{AddrMode, Instruction, ADDR_MODES, LOOKUP};
 use crate::cpu::registers::Registers;
+use crate::cpu::trace::Tracer;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 use crate::ternary;
 
-type Opcode = u32;
+type Opcode = u8;
 
 static PAGE_SIZE: u16 = 0x00FF;
+static STACK_BASE: u16 = 0x0100;
+
+// Interrupt vectors (address of the low byte)
+static NMI_VECTOR: u16 = 0xFFFA;
+static RESET_VECTOR: u16 = 0xFFFC;
+static IRQ_VECTOR: u16 = 0xFFFE;
+
+// Cycles taken by the IRQ and NMI sequences
+static INTERRUPT_CYCLES: u8 = 7;
+
+// Cycles the CPU is suspended for by OAM DMA, one more when it starts on an
+// odd cycle
+static OAM_DMA_CYCLES: u16 = 513;
 
 // Main CPU object
 pub struct CPU {
@@ -14,13 +31,23 @@ pub struct CPU {
     pub bus: bus::Bus,
 
     // Addressing variables
-    pub addr_abs: u16,  // absolute address
-    pub addr_rel: u8,   // relative address
-    pub addr_temp: u16, // temporary address storage variable
+    pub addr_abs: u16,       // absolute address
+    pub addr_rel: u16,       // relative address
+    pub addr_temp: u16,      // temporary address storage variable
+    pub addr_mode: AddrMode, // addressing mode of the executing instruction
 
     // Utility variables
-    pub cycles: u8,     // instruction cycle counter
-    pub cpu_cycles: u8, // overall global cycle counter
+    pub cycles: u8,        // cycles remaining for the current instruction
+    pub cpu_cycles: u64,   // overall global cycle counter
+    pub stall_cycles: u16, // cycles the CPU is suspended for by DMA
+
+    // Interrupt lines
+    pub irq_line: bool,         // IRQ level held by devices (mappers, APU)
+    pub nmi_pending: bool,      // NMI edge latched until serviced
+    interrupt_hijackable: bool, // BRK/IRQ sequence still before its vector fetch
+
+    // Debugging
+    pub tracer: Option<Tracer>, // nestest style trace of every instruction
 }
 
 // CPU methods
@@ -42,14 +69,20 @@ impl CPU {
             addr_abs: 0x0000,
             addr_rel: 0x00,
             addr_temp: 0x0000,
+            addr_mode: AddrMode::IMP,
             cycles: 0,
             cpu_cycles: 0,
+            stall_cycles: 0,
+            irq_line: false,
+            nmi_pending: false,
+            interrupt_hijackable: false,
+            tracer: None,
         }
     }
-    pub fn read(&self, address: u16) -> u16 {
+    pub fn read(&mut self, address: u16) -> u8 {
         return self.bus.read(address);
     }
-    pub fn write(&mut self, address: u16, data: u8) -> () {
+    pub fn write(&mut self, address: u16, data: u8) {
         self.bus.write(address, data);
     }
 
@@ -62,28 +95,306 @@ impl CPU {
         self.registers.x = 0x00;
         self.registers.y = 0x00;
         self.registers.sp = 0xFD;
-        self.registers.status = 0x00 | self.registers.get_flag(StatusRegFlags::U);
+        self.registers.status = StatusRegFlags::U as u8 | StatusRegFlags::I as u8;
 
         // Set program counter by reading from reset vector (0xFFFD - 0xFFFC)
-        self.registers.pc = (self.bus.read(0xFFFD) << 8) + self.bus.read(0xFFFC);
+        self.registers.pc = self.read_vector(RESET_VECTOR);
 
         self.addr_abs = 0x0000;
         self.addr_rel = 0x0000;
         self.registers.fetched = 0x00;
+        self.nmi_pending = false;
+        self.interrupt_hijackable = false;
+        self.stall_cycles = 0;
+
+        // Reset takes 7 cycles before the first instruction is fetched
+        self.cycles = 7;
+    }
+
+    // Advance the CPU by a single clock cycle. The whole instruction is
+    // executed on its first cycle and the remaining cycles are then counted
+    // down, so other devices can be clocked in between at their own rate
+    // (e.g. the PPU 3 times and the APU once per CPU cycle).
+    pub fn clock(&mut self) {
+        self.bus.cpu_clock();
+
+        // DMA holds the CPU between instructions
+        if self.cycles == 0 {
+            if let Some(page) = self.bus.take_oam_dma() {
+                self.oam_dma(page);
+            }
+        }
+        if self.cycles == 0 && self.stall_cycles > 0 {
+            self.stall_cycles -= 1;
+            self.cpu_cycles += 1;
+            return;
+        }
+        if self.cycles == 0 {
+            // Interrupts are polled at instruction boundaries, NMI first
+            self.interrupt_hijackable = false;
+            if self.nmi_pending {
+                self.nmi();
+            } else if self.irq_line && self.registers.get_flag(StatusRegFlags::I) == 0 {
+                self.irq();
+            } else {
+                self.execute_instruction();
+            }
+        }
+        self.cycles -= 1;
+        self.cpu_cycles += 1;
+    }
+
+    // Whether the current instruction has used up all of its cycles
+    pub fn complete(&self) -> bool {
+        return self.cycles == 0 && self.stall_cycles == 0;
+    }
+
+    // Suspend the CPU for a number of cycles once the current instruction
+    // has finished
+    pub fn stall(&mut self, cycles: u16) {
+        self.stall_cycles += cycles;
+    }
+
+    // Start tracing instructions, replacing any previous tracer
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // Pause or resume tracing without removing the tracer
+    pub fn set_trace_enabled(&mut self, enabled: bool) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.enabled = enabled;
+        }
+    }
+
+    // Copy a page of memory into PPU OAM. The copy happens at once and the
+    // CPU is then stalled for as long as the 256 reads and writes take.
+    fn oam_dma(&mut self, page: u8) {
+        let base: u16 = (page as u16) << 8;
+        for offset in 0x00..=0xFF {
+            let data: u8 = self.read(base | offset);
+            self.write(bus::OAM_DATA, data);
+        }
+        self.stall(OAM_DMA_CYCLES + (self.cpu_cycles & 0x01) as u16);
+    }
+
+    // Perform a DMC sample fetch if the APU needs one. The byte is read
+    // through the CPU bus, which is unavailable to the CPU meanwhile.
+    pub fn service_dmc(&mut self, apu: &mut APU) {
+        if let Some(address) = apu.dmc.dma_request() {
+            let data: u8 = self.read(address);
+            apu.dmc.dma_fill(data);
+            self.stall(DMC_STALL_CYCLES);
+        }
+    }
+
+    // Drive the (level triggered) IRQ line. Devices hold it asserted until
+    // they are acknowledged, it is serviced whenever the I flag is clear.
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    // Signal an NMI edge. An NMI arriving while a BRK or IRQ sequence has
+    // not yet fetched its vector hijacks it, so the NMI vector is used instead
+    // and the NMI is not serviced a second time.
+    pub fn trigger_nmi(&mut self) {
+        if self.interrupt_hijackable && self.cycles > 2 {
+            self.interrupt_hijackable = false;
+            self.registers.pc = self.read_vector(NMI_VECTOR);
+        } else {
+            self.nmi_pending = true;
+        }
+    }
+
+    // Interrupt request, ignored while the I flag is set
+    pub fn irq(&mut self) {
+        if self.registers.get_flag(StatusRegFlags::I) == 0 {
+            self.interrupt(IRQ_VECTOR);
+            self.interrupt_hijackable = true;
+        }
+    }
+
+    // Non-maskable interrupt
+    pub fn nmi(&mut self) {
+        self.nmi_pending = false;
+        self.interrupt(NMI_VECTOR);
+    }
 
-        self.cycles = 8;
+    // Hardware interrupt sequence shared by IRQ and NMI. The program counter
+    // and status (with B clear) are pushed, further IRQs are disabled and
+    // execution continues from the address in the vector.
+    fn interrupt(&mut self, vector: u16) {
+        // The opcode that would have run is read twice and dropped
+        self.read(self.registers.pc);
+        self.read(self.registers.pc);
+        self.push((self.registers.pc >> 8) as u8);
+        self.push((self.registers.pc & 0x00FF) as u8);
+
+        self.registers.set_flag(StatusRegFlags::B, false);
+        self.registers.set_flag(StatusRegFlags::U, true);
+        self.push(self.registers.status);
+        self.registers.set_flag(StatusRegFlags::I, true);
+
+        self.registers.pc = self.read_vector(vector);
+        self.cycles = INTERRUPT_CYCLES;
+    }
+
+    // Read a little endian 16 bit address from an interrupt vector
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        return self.read(vector) as u16 | ((self.read(vector + 1) as u16) << 8);
+    }
+
+    // Push a byte onto the stack (page 0x01), the stack grows downwards
+    fn push(&mut self, data: u8) {
+        self.write(STACK_BASE + self.registers.sp as u16, data);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+    }
+
+    // Pulls start with a dummy read of the stack before S is incremented
+    fn read_stack(&mut self) {
+        self.read(STACK_BASE + self.registers.sp as u16);
+    }
+
+    // Pull a byte from the stack
+    fn pull(&mut self) -> u8 {
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        return self.read(STACK_BASE + self.registers.sp as u16);
+    }
+
+    // Binary addition shared by ADC and SBC (the 2A03 has no decimal mode).
+    // Overflow is set when both operands have the same sign and the result
+    // has a different one.
+    fn add_with_carry(&mut self, value: u8) {
+        let a: u16 = self.registers.a as u16;
+        let temp: u16 = a + value as u16 + self.registers.get_flag(StatusRegFlags::C) as u16;
+        self.registers.set_flag(StatusRegFlags::C, temp > 0x00FF);
+        self.registers
+            .set_flag(StatusRegFlags::Z, temp & 0x00FF == 0x00);
+        self.registers.set_flag(
+            StatusRegFlags::V,
+            (!(a ^ value as u16) & (a ^ temp)) & 0x0080 != 0,
+        );
+        self.registers
+            .set_flag(StatusRegFlags::N, temp & 0x0080 != 0);
+        self.registers.a = (temp & 0x00FF) as u8;
+    }
+
+    // Compare a register with the fetched value, shared by CMP, CPX and CPY
+    fn compare(&mut self, register: u8) {
+        let result: u8 = register.wrapping_sub(self.registers.fetched);
+        self.registers
+            .set_flag(StatusRegFlags::C, register >= self.registers.fetched);
+        self.registers.set_flag(StatusRegFlags::Z, result == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, result & 0x80 != 0);
     }
 
     // Function fetches data from memory and sets the fetched register to the data
     fn fetch(&mut self) -> u8 {
-        // if addrMode == "IMP" {
-        //     self.registers.fetched = self.bus.read(self.addr_abs) as u8;
-        // }
+        match self.addr_mode {
+            // Implied instructions have no operand to fetch
+            AddrMode::IMP => {}
+            // Accumulator instructions operate on the A register
+            AddrMode::ACC => self.registers.fetched = self.registers.a,
+            // Every other mode has resolved the operand address into addr_abs.
+            // Indexed reads that cross a page first read the wrong page.
+            _ => {
+                if let Some(address) = self.uncorrected_address() {
+                    if address != self.addr_abs {
+                        self.read(address);
+                    }
+                }
+                self.registers.fetched = self.read(self.addr_abs);
+            }
+        }
         return self.registers.fetched;
     }
 
-    pub fn execute_instruction(&mut self, opcode: Opcode) {
-        // TODO: Implement Base64 compressed opcode invocation to method
+    // Fetch the operand of a read-modify-write instruction. Indexed modes
+    // always take the dummy read, page crossed or not.
+    fn fetch_modify(&mut self) -> u8 {
+        if let Some(address) = self.uncorrected_address() {
+            self.read(address);
+            self.registers.fetched = self.read(self.addr_abs);
+            return self.registers.fetched;
+        }
+        return self.fetch();
+    }
+
+    // Store a register. Like read-modify-write instructions, indexed stores
+    // always take the dummy read.
+    fn store(&mut self, value: u8) {
+        if let Some(address) = self.uncorrected_address() {
+            self.read(address);
+        }
+        self.write(self.addr_abs, value);
+    }
+
+    // Indexed modes add the index to the low byte of the address first and
+    // read from there while the carry into the high byte is worked out, so
+    // the address read has the right low byte on the base address's page
+    fn uncorrected_address(&self) -> Option<u16> {
+        let index: u8 = match self.addr_mode {
+            AddrMode::ABX => self.registers.x,
+            AddrMode::ABY | AddrMode::IZY => self.registers.y,
+            _ => return None,
+        };
+        let base: u16 = self.addr_abs.wrapping_sub(index as u16);
+        return Some((base & 0xFF00) | (self.addr_abs & 0x00FF));
+    }
+
+    // Taken branches read the next opcode, and the wrong page as well when
+    // the target is on another page
+    fn branch(&mut self, taken: bool) -> u8 {
+        if taken {
+            self.cycles += 1;
+            self.read(self.registers.pc);
+            self.addr_abs = self.registers.pc.wrapping_add(self.addr_rel);
+            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
+                self.cycles += 1;
+                self.read((self.registers.pc & 0xFF00) | (self.addr_abs & 0x00FF));
+            }
+            self.registers.pc = self.addr_abs;
+        }
+        return 0;
+    }
+
+    // Store the result of a read-modify-write instruction where its operand
+    // came from. Memory operands first receive a dummy write of the
+    // unmodified value, just like the real 6502 (some mappers react to it).
+    fn write_back(&mut self, value: u8) {
+        if matches!(self.addr_mode, AddrMode::IMP | AddrMode::ACC) {
+            self.registers.a = value;
+        } else {
+            self.write(self.addr_abs, self.registers.fetched);
+            self.write(self.addr_abs, value);
+        }
+    }
+
+    // Fetch, decode and execute a single instruction at the program counter.
+    // Returns the number of cycles the instruction takes.
+    pub fn execute_instruction(&mut self) -> u8 {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.trace(self);
+            self.tracer = Some(tracer);
+        }
+
+        // Fetch the opcode and move past it
+        let opcode: Opcode = self.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+
+        // Decode
+        let instruction: &Instruction = &LOOKUP[opcode as usize];
+        self.cycles = instruction.cycles;
+        self.addr_mode = instruction.mode;
+
+        // Execute. Only when both the addressing mode and the instruction
+        // report a possible extra cycle (page boundary crossed) is one added.
+        let additional_cycle_mode: u8 = (instruction.addr_mode)(self);
+        let additional_cycle_op: u8 = (instruction.operate)(self);
+        self.cycles += additional_cycle_mode & additional_cycle_op;
+
+        return self.cycles;
     }
 
     /*
@@ -91,30 +402,36 @@ impl CPU {
     ADDRESSING MODE IMPLEMENTATIONS
 
     If addressing mode func and instruction return 1, then extra cycle required
-    // TODO implement this in instructions
-
 
     */
 
     // Implied Addressing
+    // The byte after the opcode is read and ignored
     pub fn IMP(&mut self) -> u8 {
+        self.read(self.registers.pc);
+        return 0;
+    }
+
+    // Accumulator Addressing
+    pub fn ACC(&mut self) -> u8 {
+        self.read(self.registers.pc);
         self.registers.fetched = self.registers.a;
-        return 1;
+        return 0;
     }
 
     // Immediate Addressing
     pub fn IMM(&mut self) -> u8 {
-        self.registers.pc += 1;
         self.addr_abs = self.registers.pc;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         return 0;
     }
 
     // Absolute Addressing
     pub fn ABS(&mut self) -> u8 {
-        let addr_l: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
-        let addr_h: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
+        let addr_l: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        let addr_h: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs = addr_l + (addr_h << 8);
 
         return 0;
@@ -123,14 +440,14 @@ impl CPU {
     // Absolute with offset X addressing mode
     pub fn ABX(&mut self) -> u8 {
         // Convert low and high to u16
-        let addr_l: u16 = self.read(self.registers.pc as u16);
+        let addr_l: u16 = self.read(self.registers.pc) as u16;
 
-        self.registers.pc += 1;
-        let addr_h: u16 = self.read(self.registers.pc as u16);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        let addr_h: u16 = self.read(self.registers.pc) as u16;
 
-        self.registers.pc += 1;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs = addr_l + (addr_h << 8); // concat two u8 -> u16
-        self.addr_abs += self.registers.x as u16; // offset by x register
+        self.addr_abs = self.addr_abs.wrapping_add(self.registers.x as u16); // offset by x register
 
         return ternary!((self.addr_abs & 0xFF00) != (addr_h << 8), 1, 0);
     }
@@ -138,80 +455,89 @@ impl CPU {
     // Absolute with offset Y addressing mode
     pub fn ABY(&mut self) -> u8 {
         // Convert low and high to u16
-        let addr_l: u16 = self.read(self.registers.pc as u16);
+        let addr_l: u16 = self.read(self.registers.pc) as u16;
 
-        self.registers.pc += 1;
-        let addr_h: u16 = self.read(self.registers.pc as u16);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        let addr_h: u16 = self.read(self.registers.pc) as u16;
 
-        self.registers.pc += 1;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs = addr_l + (addr_h << 8);
-        self.addr_abs += self.registers.y as u16;
+        self.addr_abs = self.addr_abs.wrapping_add(self.registers.y as u16);
 
         return ternary!((self.addr_abs & 0xFF00) != (addr_h << 8), 1, 0);
     }
 
     // Zero Page Addressing
     pub fn ZP0(&mut self) -> u8 {
-        self.addr_abs = self.read(self.registers.pc);
-        self.registers.pc += 1;
+        self.addr_abs = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         self.addr_abs &= PAGE_SIZE;
         return 0;
     }
 
     // Zero Page With X Offset
+    // The unindexed address is read while the index is added
     pub fn ZPX(&mut self) -> u8 {
-        self.addr_abs = self.read(self.registers.pc + self.registers.x as u16);
-        self.registers.pc += 1;
-        self.addr_abs &= PAGE_SIZE;
+        let base: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.read(base);
+        self.addr_abs = (base + self.registers.x as u16) & PAGE_SIZE;
         return 0;
     }
 
     // Zero Page With Y Offset
     pub fn ZPY(&mut self) -> u8 {
-        self.addr_abs = self.read(self.registers.pc + self.registers.y as u16);
-        self.registers.pc += 1;
-        self.addr_abs &= PAGE_SIZE;
+        let base: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.read(base);
+        self.addr_abs = (base + self.registers.y as u16) & PAGE_SIZE;
         return 0;
     }
 
     // Relative Addressing (used for branching)
     // Can branch -128 to 128 away from pc
     pub fn REL(&mut self) -> u8 {
-        self.addr_rel = self.read(self.registers.pc as u16) as u8;
-        self.registers.pc += 1;
-        // Checking GSB set to 1 (i.e. signed)
-        if self.addr_rel & 0x80 == 0x10 {
-            self.addr_rel = (self.addr_rel as u16 | 0xFF00) as u8;
+        self.addr_rel = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        // Checking GSB set to 1 (i.e. signed), sign extend into the high byte
+        if self.addr_rel & 0x80 != 0 {
+            self.addr_rel |= 0xFF00;
         }
         return 0;
     }
     // Indirect Addressing (pointer)
     pub fn IND(&mut self) -> u8 {
         // Construct pointer from low / high byte in pc
-        let ptr_l: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
-        let ptr_h: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
+        let ptr_l: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        let ptr_h: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         let ptr: u16 = ptr_l + (ptr_h << 8);
 
         // REPLICATE 6502 INDIRECT ADDRESSING BUG
-        if ptr_l == 0xFF00 {
-            self.addr_abs = (self.read(ptr & 0xFF00) << 8) | self.read(ptr + 0);
+        // The high byte is read from the start of the same page when the
+        // pointer sits on a page boundary
+        let addr_l: u16 = self.read(ptr) as u16;
+        let addr_h: u16 = if ptr_l == 0x00FF {
+            self.read(ptr & 0xFF00) as u16
         } else {
             // Normal
-            self.addr_abs = (self.read(ptr + 1) << 8) | self.read(ptr + 0);
-        }
+            self.read(ptr + 1) as u16
+        };
+        self.addr_abs = (addr_h << 8) | addr_l;
         return 0;
     }
 
     // Indirect with X offset Addressing (pointer)
     pub fn IZX(&mut self) -> u8 {
         // Obtain the data (= another address) at the pc address
-        let temp: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
+        let temp: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        // The pointer is read unindexed while X is added
+        self.read(temp);
         // Offset address and ensure it is in the zero page (with mask)
-        let addr_l: u16 = self.read((temp + (self.registers.x as u16)) & 0x00FF);
-        let addr_h: u16 = self.read((temp + (self.registers.x as u16) + 1) & 0x00FF);
+        let addr_l: u16 = self.read((temp + (self.registers.x as u16)) & 0x00FF) as u16;
+        let addr_h: u16 = self.read((temp + (self.registers.x as u16) + 1) & 0x00FF) as u16;
 
         // Concat into full 16 bit address
         self.addr_abs = addr_h << 8 | addr_l;
@@ -224,15 +550,15 @@ impl CPU {
     // 16 bit address is created (not during).
     pub fn IZY(&mut self) -> u8 {
         // Obtain the data (= another address) at the pc address
-        let temp: u16 = self.read(self.registers.pc as u16);
-        self.registers.pc += 1;
+        let temp: u16 = self.read(self.registers.pc) as u16;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         // Offset address and ensure it is in the zero page (with mask)
-        let addr_l: u16 = self.read(temp & 0x00FF);
-        let addr_h: u16 = self.read((temp + 1) & 0x00FF);
+        let addr_l: u16 = self.read(temp & 0x00FF) as u16;
+        let addr_h: u16 = self.read((temp + 1) & 0x00FF) as u16;
 
         // Concat into full 16 bit address
         self.addr_abs = addr_h << 8 | addr_l;
-        self.addr_abs += self.registers.y as u16;
+        self.addr_abs = self.addr_abs.wrapping_add(self.registers.y as u16);
 
         return ternary!(self.addr_abs & 0xFF00 != addr_h << 8, 1, 0);
     }
@@ -245,27 +571,8 @@ impl CPU {
 
     // Add with carry
     pub fn ADC(&mut self) -> u8 {
-        let mut temp: u16 = (self.registers.a as u16)
-            + (self.registers.fetched as u16)
-            + (self.registers.get_flag(StatusRegFlags::C) as u16);
-        // Carry flag is set when addition exceeds 255 (into bit 9)
-        self.registers.set_flag(StatusRegFlags::C, temp > 255);
-        // Zero flag is set when the result is 0
-        self.registers
-            .set_flag(StatusRegFlags::Z, (temp & 0x00FF) == 0);
-        // Signed overflow is set on the condition if the carry is set and the result exceeds an XOR with the first value
-        self.registers.set_flag(
-            StatusRegFlags::V,
-            ((((self.registers.a as u16) ^ (self.registers.fetched as u16))
-                & ((self.registers.a as u16) ^ temp))
-                & 0x0080)
-                == 0,
-        );
-        // Negative flag is set when the most significant bit is set
-        self.registers
-            .set_flag(StatusRegFlags::N, temp & 0x0080 == 0);
-        // Mask off the last 2 bytes into the 8-bit accumulator
-        self.registers.a = (temp & 0x00FF) as u8;
+        self.fetch();
+        self.add_with_carry(self.registers.fetched);
         // Some variants of the ADC op have additional cycles
         return 1;
     }
@@ -273,9 +580,9 @@ impl CPU {
     // Bitwise AND
     pub fn AND(&mut self) -> u8 {
         // Fetch data
-        let fetched: u16 = self.registers.fetched as u16;
+        self.fetch();
         // Peform AND with data and data in accumulator
-        self.registers.a = self.registers.a & (fetched as u8);
+        self.registers.a &= self.registers.fetched;
         // Zero flag set if result equals 0
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
@@ -288,62 +595,36 @@ impl CPU {
     // Arithmetic Shift Left
     pub fn ASL(&mut self) -> u8 {
         // Fetch data
-        let fetched: u16 = self.registers.fetched as u16;
+        self.fetch_modify();
         // Shift left 1
-        let shifted = fetched << 1;
+        self.addr_temp = (self.registers.fetched as u16) << 1;
         // Set carry flag if bit 8 == 1 (old bit 7 == 1)
         self.registers
-            .set_flag(StatusRegFlags::C, shifted & 0xFF00 != 0);
+            .set_flag(StatusRegFlags::C, self.addr_temp & 0xFF00 != 0);
         // Zero flag set if result equals 0
-        self.registers.set_flag(StatusRegFlags::Z, shifted == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
         // Negative flag is set if the most significant bit of the result is set
         self.registers
-            .set_flag(StatusRegFlags::N, shifted & 0x80 != 0);
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x80 != 0);
 
-        // TODO: need address mode lookup
-        return 1;
+        self.write_back((self.addr_temp & 0x00FF) as u8);
+        return 0;
     }
 
     // Branch if carry clear
     pub fn BCC(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::C) == 0 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + (self.addr_rel as u16);
-            // If over zero page?
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::C) == 0);
     }
 
     // Branch if carry set
     pub fn BCS(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::C) == 1 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + (self.addr_rel as u16);
-
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::C) == 1);
     }
 
     // Branch if equal
     pub fn BEQ(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::Z) == 1 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + (self.addr_rel as u16);
-
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::Z) == 1);
     }
 
     // Test if one or more bits are set at the address (sets the zero flag)
@@ -353,7 +634,7 @@ impl CPU {
         self.registers
             .set_flag(StatusRegFlags::N, self.registers.fetched & (1 << 7) != 0);
         self.registers
-            .set_flag(StatusRegFlags::V, self.registers.fetched & (1 << 7) != 0);
+            .set_flag(StatusRegFlags::V, self.registers.fetched & (1 << 6) != 0);
         self.registers
             .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
         return 0;
@@ -363,107 +644,55 @@ impl CPU {
     // If negative flag set, set absolute address, check if zero page overflow
     // then set pc = addr_abs
     pub fn BMI(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::N) == 1 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + self.addr_rel as u16;
-            // If over zero page?
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::N) == 1);
     }
 
     // Branch if positive
     pub fn BPL(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::N) == 0 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + self.addr_rel as u16;
-            // If over zero page
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::N) == 0);
     }
 
     // Branch if not equal
     // Similar logic to BMI except with zero flag clear.
     pub fn BNE(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::Z) == 0 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + self.addr_rel as u16;
-
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::Z) == 0);
     }
 
     // Break / force interrupt
+    // The padding byte following BRK has already been skipped by the
+    // immediate addressing mode, so the pushed return address is BRK + 2
     pub fn BRK(&mut self) -> u8 {
-        self.registers.pc += 1;
-        self.registers.set_flag(StatusRegFlags::I, true);
-        self.write(
-            0x0100 + self.registers.sp as u16,
-            ((self.registers.pc >> 8) & 0x00FF) as u8,
-        );
-        self.registers.sp -= 1;
-        self.write(
-            0x0100 + self.registers.sp as u16,
-            (self.registers.pc & 0x00FF) as u8,
-        );
-        self.registers.sp -= 1;
+        self.read(self.addr_abs);
+        self.push((self.registers.pc >> 8) as u8);
+        self.push((self.registers.pc & 0x00FF) as u8);
 
-        self.registers.set_flag(StatusRegFlags::B, true);
-        self.write(0x0100 + self.registers.sp as u16, self.registers.status);
-        self.registers.sp -= 1;
-        self.registers.set_flag(StatusRegFlags::B, false);
+        // Status is pushed with B set to tell it apart from a hardware IRQ
+        self.push(self.registers.status | StatusRegFlags::B as u8 | StatusRegFlags::U as u8);
+        self.registers.set_flag(StatusRegFlags::I, true);
 
-        // Load interrupt vector
-        self.registers.pc = self.read(0xFFFE) | self.read(0xFFFF) << 8;
+        // Load interrupt vector, an NMI may still hijack it (see trigger_nmi)
+        self.registers.pc = self.read_vector(IRQ_VECTOR);
+        self.interrupt_hijackable = true;
         return 0;
     }
 
     // Branch if overflow clear
     pub fn BVC(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::V) == 0 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + self.addr_rel as u16;
-
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::V) == 0);
     }
 
     // Branch if overflow set
     pub fn BVS(&mut self) -> u8 {
-        if self.registers.get_flag(StatusRegFlags::V) == 1 {
-            self.cycles += 1;
-            self.addr_abs = self.registers.pc + self.addr_rel as u16;
-
-            if (self.addr_abs & 0xFF00) != (self.registers.pc & 0xFF00) {
-                self.cycles += 1
-            }
-            self.registers.pc = self.addr_abs;
-        }
-        return 0;
+        return self.branch(self.registers.get_flag(StatusRegFlags::V) == 1);
     }
 
     // Clear carry flag
     pub fn CLC(&mut self) -> u8 {
         self.registers.set_flag(StatusRegFlags::C, false);
-        return 0
+        return 0;
     }
     // Clear decimal flag
-    pub fn CDC(&mut self) -> u8 {
+    pub fn CLD(&mut self) -> u8 {
         self.registers.set_flag(StatusRegFlags::D, false);
         return 0;
     }
@@ -483,111 +712,94 @@ impl CPU {
     // Compare accumulator
     pub fn CMP(&mut self) -> u8 {
         self.fetch();
-        self.addr_temp = self.registers.a as u16 - self.registers.fetched as u16;
-        self.registers.set_flag(
-            StatusRegFlags::C,
-            self.registers.a >= self.registers.fetched,
-        );
-        self.registers
-            .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x0000);
-        self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1);
+        self.compare(self.registers.a);
         return 1;
     }
     // Compare X register
-    pub fn CMX(&mut self) -> u8 {
+    pub fn CPX(&mut self) -> u8 {
         self.fetch();
-        self.addr_temp = self.registers.x as u16 - self.registers.fetched as u16;
-        self.registers.set_flag(
-            StatusRegFlags::C,
-            self.registers.x >= self.registers.fetched,
-        );
-        self.registers
-            .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x0000);
-        self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1);
+        self.compare(self.registers.x);
         return 0;
     }
     // Compare Y register
-    pub fn CMY(&mut self) -> u8 {
+    pub fn CPY(&mut self) -> u8 {
         self.fetch();
-        self.addr_temp = self.registers.x as u16 - self.registers.fetched as u16;
-        self.registers.set_flag(
-            StatusRegFlags::C,
-            self.registers.x >= self.registers.fetched,
-        );
-        self.registers
-            .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x0000);
-        self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1);
+        self.compare(self.registers.y);
         return 0;
     }
 
     // Subtract 1 from value at memory location
     pub fn DEC(&mut self) -> u8 {
+        self.fetch_modify();
+        self.addr_temp = (self.registers.fetched as u16).wrapping_sub(1);
+        self.registers
+            .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x80 != 0);
+        self.write_back((self.addr_temp & 0x00FF) as u8);
         return 0;
     }
 
     // Subtract 1 from X register
     pub fn DEX(&mut self) -> u8 {
-        self.registers.x -= 1;
+        self.registers.x = self.registers.x.wrapping_sub(1);
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.x == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 != 0);
         return 0;
     }
 
     // Subtract 1 from Y register
     pub fn DEY(&mut self) -> u8 {
-        self.registers.y -= 1;
+        self.registers.y = self.registers.y.wrapping_sub(1);
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.y == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 != 0);
         return 0;
     }
 
     // Exclusive OR (XOR)
     pub fn EOR(&mut self) -> u8 {
         self.fetch();
-        self.registers.a = self.registers.a ^ self.registers.fetched;
+        self.registers.a ^= self.registers.fetched;
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 != 0);
         return 1;
     }
 
     // Increment fetched memory
     pub fn INC(&mut self) -> u8 {
-        self.fetch();
-        self.addr_temp = (self.registers.fetched + 1) as u16;
-        self.write(self.addr_abs, (self.addr_temp & 0x00FF) as u8);
+        self.fetch_modify();
+        self.addr_temp = self.registers.fetched as u16 + 1;
+        self.write_back((self.addr_temp & 0x00FF) as u8);
         self.registers
             .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x80 != 0);
         return 0;
     }
 
     // Increment X register
     pub fn INX(&mut self) -> u8 {
-        self.registers.x += 1;
+        self.registers.x = self.registers.x.wrapping_add(1);
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.x == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 != 0);
         return 0;
     }
 
     // Increment Y register
     pub fn INY(&mut self) -> u8 {
-        self.registers.y += 1;
+        self.registers.y = self.registers.y.wrapping_add(1);
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.y == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 != 0);
         return 0;
     }
 
@@ -598,19 +810,18 @@ impl CPU {
     }
 
     // Jump to subroutine
+    // The return address pushed is the last byte of the JSR instruction.
+    // Only the low byte of the target has been read by the addressing mode
+    // (see LOOKUP), the high byte is read after the pushes.
     pub fn JSR(&mut self) -> u8 {
-        self.registers.pc -= 1;
-        self.write(
-            0x0100 + self.registers.sp as u16,
-            ((self.registers.pc >> 8) & 0x00FF) as u8,
-        );
-        self.registers.sp -= 1;
-        self.write(
-            0x0100 + self.registers.sp as u16,
-            (self.registers.pc & 0x00FF) as u8,
-        );
-        self.registers.sp -= 1;
-
+        let addr_l: u16 = self.read(self.addr_abs) as u16;
+        self.read_stack();
+        let return_address: u16 = self.registers.pc;
+        self.push((return_address >> 8) as u8);
+        self.push((return_address & 0x00FF) as u8);
+
+        let addr_h: u16 = self.read(self.registers.pc) as u16;
+        self.addr_abs = (addr_h << 8) | addr_l;
         self.registers.pc = self.addr_abs;
         return 0;
     }
@@ -622,7 +833,7 @@ impl CPU {
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 != 0);
         return 1;
     }
 
@@ -633,7 +844,7 @@ impl CPU {
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.x == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 != 0);
         return 1;
     }
 
@@ -644,77 +855,74 @@ impl CPU {
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.y == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 == 1); // high byte set
+            .set_flag(StatusRegFlags::N, self.registers.y & 0x80 != 0); // high byte set
         return 1;
     }
 
-    // Logical Shift Left
+    // Logical Shift Right
     pub fn LSR(&mut self) -> u8 {
-        self.fetch();
+        self.fetch_modify();
         self.registers
             .set_flag(StatusRegFlags::C, self.registers.fetched & 0x0001 == 1);
         self.addr_temp = self.registers.fetched as u16 >> 1;
         self.registers
             .set_flag(StatusRegFlags::Z, self.addr_temp == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1); // high byte set
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 != 0); // high byte set
 
-        // TODO lookup address mode. If implied. set accumulator = temp & 0x00FF (i.e. bottom portion of addr_temp)
+        self.write_back((self.addr_temp & 0x00FF) as u8);
         return 0;
     }
 
     // No operation
+    // Some illegal NOPs use absolute X addressing and take an extra cycle when
+    // a page boundary is crossed. Every other addressing mode they use returns
+    // 0, so always reporting 1 here is safe.
     pub fn NOP(&mut self) -> u8 {
-        // TODO Every repo i've seen does this differently
-        return 0;
+        self.fetch();
+        return 1;
     }
 
     // Bitwise logical inclusive OR
     pub fn ORA(&mut self) -> u8 {
         self.fetch();
-        self.registers.a = self.registers.a | self.registers.fetched;
+        self.registers.a |= self.registers.fetched;
         self.registers
             .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 != 0);
         return 1;
     }
 
     // Pushes a copy of accumulator to the stack
     pub fn PHA(&mut self) -> u8 {
-        self.write(0x0100 + self.registers.sp as u16, self.registers.a);
-        self.registers.sp -= 1;
+        self.push(self.registers.a);
         return 0;
     }
 
-    // Pushes a copy of status flags onto the stack
+    // Pushes a copy of status flags onto the stack, with B and U set
     pub fn PHP(&mut self) -> u8 {
-        self.write(
-            0x0100 + self.registers.sp as u16,
-            self.registers.status
-                | self.registers.get_flag(StatusRegFlags::B)
-                | self.registers.get_flag(StatusRegFlags::B),
-        );
-        self.registers.set_flag(StatusRegFlags::B, true);
-        self.registers.set_flag(StatusRegFlags::U, true);
-        self.registers.sp -= 1;
-
+        self.push(self.registers.status | StatusRegFlags::B as u8 | StatusRegFlags::U as u8);
         return 0;
     }
 
     // Pulls an 8bit value from stack into accumulator
     pub fn PLA(&mut self) -> u8 {
-        self.registers.sp += 1;
-        self.registers.a = self.read(0x0100 + (self.registers.sp as u16)) as u8; // TODO why do all read functions start at 0100.
+        self.read_stack();
+        self.registers.a = self.pull();
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 == 1);
+            .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x80 != 0);
         return 0;
     }
 
-    // Pull 8bit value from stack into status register flags
+    // Pull 8bit value from stack into status register flags. B only exists
+    // on the stack, U always reads as set.
     pub fn PLP(&mut self) -> u8 {
-        self.registers.sp += 1;
-        self.registers.status = self.read(0x0100 + (self.registers.sp as u16)) as u8;
+        self.read_stack();
+        self.registers.status = self.pull();
+        self.registers.set_flag(StatusRegFlags::B, false);
         self.registers.set_flag(StatusRegFlags::U, true);
         return 0;
     }
@@ -722,24 +930,24 @@ impl CPU {
     // Rotate left
 
     pub fn ROL(&mut self) -> u8 {
-        self.fetch();
+        self.fetch_modify();
 
-        self.addr_temp =
-            (self.registers.fetched << 1 | self.registers.get_flag(StatusRegFlags::C)) as u16;
+        self.addr_temp = (self.registers.fetched as u16) << 1
+            | self.registers.get_flag(StatusRegFlags::C) as u16;
         self.registers
-            .set_flag(StatusRegFlags::C, self.addr_temp & 0xFF00 == 1);
+            .set_flag(StatusRegFlags::C, self.addr_temp & 0xFF00 != 0);
         self.registers
             .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1);
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 != 0);
 
-        // TODO address lookup required
+        self.write_back((self.addr_temp & 0x00FF) as u8);
         return 0;
     }
 
     // Rotate right
     pub fn ROR(&mut self) -> u8 {
-        self.fetch();
+        self.fetch_modify();
 
         self.addr_temp = (self.registers.get_flag(StatusRegFlags::C) << 7
             | (self.registers.fetched >> 1)) as u16;
@@ -748,58 +956,40 @@ impl CPU {
         self.registers
             .set_flag(StatusRegFlags::Z, self.addr_temp & 0x00FF == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 == 1);
+            .set_flag(StatusRegFlags::N, self.addr_temp & 0x0080 != 0);
 
-        // TODO WRITE REQUIRED HERE
+        self.write_back((self.addr_temp & 0x00FF) as u8);
         return 0;
     }
 
     // Return from interrupt
     pub fn RTI(&mut self) -> u8 {
-        self.registers.sp += 1;
-        self.registers.status = self.read(0x0100 + self.registers.sp as u16) as u8;
-        self.registers.status &= !self.registers.get_flag(StatusRegFlags::B);
-        self.registers.status &= !self.registers.get_flag(StatusRegFlags::U);
+        self.read_stack();
+        self.registers.status = self.pull();
+        self.registers.set_flag(StatusRegFlags::B, false);
+        self.registers.set_flag(StatusRegFlags::U, true);
 
-        self.registers.sp += 1;
-        self.registers.pc = self.read(0x0100 + self.registers.sp as u16);
-        self.registers.sp += 1;
-        self.registers.pc |= self.read(0x0100 + self.registers.sp as u16) << 8;
+        self.registers.pc = self.pull() as u16;
+        self.registers.pc |= (self.pull() as u16) << 8;
         return 0;
     }
 
     // Return from subroutine
     pub fn RTS(&mut self) -> u8 {
-        self.registers.sp += 1;
-        self.registers.pc = self.read(0x0100 + self.registers.sp as u16);
-        self.registers.sp += 1;
-        self.registers.pc |= self.read(0x0100 + self.registers.sp as u16) << 8;
-        self.registers.pc += 1;
+        self.read_stack();
+        self.registers.pc = self.pull() as u16;
+        self.registers.pc |= (self.pull() as u16) << 8;
+        // The pulled address is read while it is incremented
+        self.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         return 0;
     }
 
     // Subtract with carry
     pub fn SBC(&mut self) -> u8 {
-        let inverted_fetched: u16 = (self.registers.fetched as u16) ^ 0x00FF;
-        let mut temp: u16 = (self.registers.a as u16)
-            + inverted_fetched
-            + (self.registers.get_flag(StatusRegFlags::C) as u16);
-        // Carry flag is set when upper bits (8-16) have a value
-        self.registers
-            .set_flag(StatusRegFlags::C, temp & 0xFF00 != 0);
-        // Zero flag is set when result is 0
-        self.registers
-            .set_flag(StatusRegFlags::Z, (temp & 0x00FF) == 0);
-        // Signed overflow is set on the condition if there is wrap around underflow
-        self.registers.set_flag(
-            StatusRegFlags::V,
-            ((temp ^ (self.registers.a as u16)) & (temp ^ inverted_fetched)) == 0,
-        );
-        // Negative flag is set when the most significant bit is set
-        self.registers
-            .set_flag(StatusRegFlags::N, (temp & 0x0080) != 0);
-        // Mask off the last 1 bytes into the 8-bit accumulator
-        self.registers.a = (temp & 0x00FF) as u8;
+        self.fetch();
+        // A - M - (1 - C) is the same as A + !M + C
+        self.add_with_carry(!self.registers.fetched);
         // Some variants of the SBC op have additional cycles
         return 1;
     }
@@ -824,19 +1014,19 @@ impl CPU {
 
     // Store accumulator data in memory
     pub fn STA(&mut self) -> u8 {
-        self.write(self.addr_abs, self.registers.a);
+        self.store(self.registers.a);
         return 0;
     }
 
     // Store X register data in memory
     pub fn STX(&mut self) -> u8 {
-        self.write(self.addr_abs, self.registers.x);
+        self.store(self.registers.x);
         return 0;
     }
 
     // Store Y register data in memory
     pub fn STY(&mut self) -> u8 {
-        self.write(self.addr_abs, self.registers.y);
+        self.store(self.registers.y);
         return 0;
     }
 
@@ -844,9 +1034,9 @@ impl CPU {
     pub fn TAX(&mut self) -> u8 {
         self.registers.x = self.registers.a;
         self.registers
-            .set_flag(StatusRegFlags::Z, self.registers.x & 0x00FF == 0x00);
+            .set_flag(StatusRegFlags::Z, self.registers.x == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.x & 0x0080 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.x & 0x0080 != 0);
         return 0;
     }
 
@@ -854,9 +1044,19 @@ impl CPU {
     pub fn TAY(&mut self) -> u8 {
         self.registers.y = self.registers.a;
         self.registers
-            .set_flag(StatusRegFlags::Z, self.registers.y & 0x00FF == 0x00);
+            .set_flag(StatusRegFlags::Z, self.registers.y == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.y & 0x0080 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.y & 0x0080 != 0);
+        return 0;
+    }
+
+    // Transfer stack pointer content to x register
+    pub fn TSX(&mut self) -> u8 {
+        self.registers.x = self.registers.sp;
+        self.registers
+            .set_flag(StatusRegFlags::Z, self.registers.x == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, self.registers.x & 0x80 != 0);
         return 0;
     }
 
@@ -864,9 +1064,9 @@ impl CPU {
     pub fn TXA(&mut self) -> u8 {
         self.registers.a = self.registers.x;
         self.registers
-            .set_flag(StatusRegFlags::Z, self.registers.a & 0x00FF == 0x00);
+            .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x0080 == 1);
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x0080 != 0);
         return 0;
     }
 
@@ -880,14 +1080,369 @@ impl CPU {
     pub fn TYA(&mut self) -> u8 {
         self.registers.a = self.registers.y;
         self.registers
-            .set_flag(StatusRegFlags::Z, self.registers.a & 0x00FF == 0x00);
+            .set_flag(StatusRegFlags::Z, self.registers.a == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, self.registers.a & 0x0080 != 0);
+        return 0;
+    }
+
+    /*
+
+    UNOFFICIAL INSTRUCTIONS
+
+    Reference: https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+
+    */
+
+    // ASL then ORA with the result
+    pub fn SLO(&mut self) -> u8 {
+        self.ASL();
+        self.set_a_with_flags(self.registers.a | (self.addr_temp & 0x00FF) as u8);
+        return 0;
+    }
+
+    // ROL then AND with the result
+    pub fn RLA(&mut self) -> u8 {
+        self.ROL();
+        self.set_a_with_flags(self.registers.a & (self.addr_temp & 0x00FF) as u8);
+        return 0;
+    }
+
+    // LSR then EOR with the result
+    pub fn SRE(&mut self) -> u8 {
+        self.LSR();
+        self.set_a_with_flags(self.registers.a ^ (self.addr_temp & 0x00FF) as u8);
+        return 0;
+    }
+
+    // ROR then ADC with the result, using the carry out of the rotate
+    pub fn RRA(&mut self) -> u8 {
+        self.ROR();
+        self.add_with_carry((self.addr_temp & 0x00FF) as u8);
+        return 0;
+    }
+
+    // DEC then CMP with the result
+    pub fn DCP(&mut self) -> u8 {
+        self.DEC();
+        self.registers.fetched = (self.addr_temp & 0x00FF) as u8;
+        self.compare(self.registers.a);
+        return 0;
+    }
+
+    // INC then SBC with the result
+    pub fn ISB(&mut self) -> u8 {
+        self.INC();
+        self.add_with_carry(!((self.addr_temp & 0x00FF) as u8));
+        return 0;
+    }
+
+    // Store A AND X
+    pub fn SAX(&mut self) -> u8 {
+        self.store(self.registers.a & self.registers.x);
+        return 0;
+    }
+
+    // LDA and LDX at once
+    pub fn LAX(&mut self) -> u8 {
+        self.LDA();
+        self.registers.x = self.registers.a;
+        return 1;
+    }
+
+    // AND immediate, then copy N into C
+    pub fn ANC(&mut self) -> u8 {
+        self.AND();
+        let negative: bool = self.registers.get_flag(StatusRegFlags::N) == 1;
+        self.registers.set_flag(StatusRegFlags::C, negative);
+        return 0;
+    }
+
+    // AND immediate then LSR A
+    pub fn ALR(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = self.registers.a & self.registers.fetched;
+        self.registers
+            .set_flag(StatusRegFlags::C, value & 0x01 != 0);
+        self.set_a_with_flags(value >> 1);
+        return 0;
+    }
+
+    // AND immediate then ROR A, with C and V taken from bits 6 and 5
+    pub fn ARR(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = self.registers.a & self.registers.fetched;
+        let result: u8 = (value >> 1) | (self.registers.get_flag(StatusRegFlags::C) << 7);
+        self.set_a_with_flags(result);
         self.registers
-            .set_flag(StatusRegFlags::N, self.registers.a & 0x0080 == 1);
+            .set_flag(StatusRegFlags::C, result & 0x40 != 0);
+        self.registers.set_flag(
+            StatusRegFlags::V,
+            ((result >> 6) ^ (result >> 5)) & 0x01 != 0,
+        );
+        return 0;
+    }
+
+    // X = (A AND X) - immediate, setting flags like CMP
+    pub fn AXS(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = self.registers.a & self.registers.x;
+        self.compare(value);
+        self.registers.x = value.wrapping_sub(self.registers.fetched);
+        return 0;
+    }
+
+    // Unstable: A = (A OR magic) AND X AND immediate. The magic constant
+    // varies between chips, $EE is the common value.
+    pub fn XAA(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = (self.registers.a | 0xEE) & self.registers.x & self.registers.fetched;
+        self.set_a_with_flags(value);
+        return 0;
+    }
+
+    // Unstable: A = X = (A OR magic) AND immediate
+    pub fn LXA(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = (self.registers.a | 0xEE) & self.registers.fetched;
+        self.set_a_with_flags(value);
+        self.registers.x = value;
+        return 0;
+    }
+
+    // A = X = SP = memory AND SP
+    pub fn LAS(&mut self) -> u8 {
+        self.fetch();
+        let value: u8 = self.registers.fetched & self.registers.sp;
+        self.set_a_with_flags(value);
+        self.registers.x = value;
+        self.registers.sp = value;
+        return 1;
+    }
+
+    // Store A AND X AND (high byte of the base address + 1)
+    pub fn SHA(&mut self) -> u8 {
+        let value: u8 = self.registers.a & self.registers.x;
+        self.store_high_and(value, self.registers.y);
+        return 0;
+    }
+
+    // Store X AND (high byte of the base address + 1)
+    pub fn SHX(&mut self) -> u8 {
+        self.store_high_and(self.registers.x, self.registers.y);
+        return 0;
+    }
+
+    // Store Y AND (high byte of the base address + 1)
+    pub fn SHY(&mut self) -> u8 {
+        self.store_high_and(self.registers.y, self.registers.x);
+        return 0;
+    }
+
+    // SP = A AND X, then store SP AND (high byte of the base address + 1)
+    pub fn TAS(&mut self) -> u8 {
+        self.registers.sp = self.registers.a & self.registers.x;
+        self.store_high_and(self.registers.sp, self.registers.y);
         return 0;
     }
 
-    // Illegal opcodes
-    pub fn XXX() -> u8 {
+    // Halts the CPU. It keeps executing the JAM until reset.
+    pub fn JAM(&mut self) -> u8 {
+        self.registers.pc = self.registers.pc.wrapping_sub(1);
         return 0;
     }
+
+    fn set_a_with_flags(&mut self, value: u8) {
+        self.registers.a = value;
+        self.registers.set_flag(StatusRegFlags::Z, value == 0x00);
+        self.registers
+            .set_flag(StatusRegFlags::N, value & 0x80 != 0);
+    }
+
+    // The SH* stores AND the value with the high byte of the unindexed
+    // address plus one. When indexing crosses a page the written value also
+    // replaces the high byte of the address.
+    fn store_high_and(&mut self, value: u8, index: u8) {
+        let base: u16 = self.addr_abs.wrapping_sub(index as u16);
+        self.read((base & 0xFF00) | (self.addr_abs & 0x00FF));
+        let value: u8 = value & ((base >> 8) as u8).wrapping_add(1);
+        if base & 0xFF00 != self.addr_abs & 0xFF00 {
+            self.addr_abs = ((value as u16) << 8) | (self.addr_abs & 0x00FF);
+        }
+        self.write(self.addr_abs, value);
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// Registers, addressing state, cycle counters and interrupt lines. The bus
+// is saved on its own, the tracer is left alone.
+impl Snapshot for CPU {
+    fn save(&self, state: &mut StateWriter) {
+        let r: &Registers = &self.registers;
+        state.u8(r.a);
+        state.u8(r.x);
+        state.u8(r.y);
+        state.u16(r.pc);
+        state.u8(r.sp);
+        state.u8(r.status);
+        state.u8(r.fetched);
+        state.u16(self.addr_abs);
+        state.u16(self.addr_rel);
+        state.u16(self.addr_temp);
+        state.u8(self.addr_mode as u8);
+        state.u8(self.cycles);
+        state.u64(self.cpu_cycles);
+        state.u16(self.stall_cycles);
+        state.bool(self.irq_line);
+        state.bool(self.nmi_pending);
+        state.bool(self.interrupt_hijackable);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let r: &mut Registers = &mut self.registers;
+        r.a = state.u8()?;
+        r.x = state.u8()?;
+        r.y = state.u8()?;
+        r.pc = state.u16()?;
+        r.sp = state.u8()?;
+        r.status = state.u8()?;
+        r.fetched = state.u8()?;
+        self.addr_abs = state.u16()?;
+        self.addr_rel = state.u16()?;
+        self.addr_temp = state.u16()?;
+        self.addr_mode = *ADDR_MODES
+            .get(state.u8()? as usize)
+            .ok_or(StateError::Invalid("addressing mode"))?;
+        self.cycles = state.u8()?;
+        self.cpu_cycles = state.u64()?;
+        self.stall_cycles = state.u16()?;
+        self.irq_line = state.bool()?;
+        self.nmi_pending = state.bool()?;
+        self.interrupt_hijackable = state.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::ppu::PPU;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // CPU about to run NOPs from $0200. Everything stays within the 2KB of
+    // RAM, the vectors can't be read until the address space is mapped.
+    fn cpu_on_nops(status: u8) -> CPU {
+        let mut cpu: CPU = CPU::new();
+        for address in 0x0200..0x0210 {
+            cpu.write(address, 0xEA);
+        }
+        cpu.registers.pc = 0x0200;
+        cpu.registers.sp = 0xFD;
+        cpu.registers.status = status;
+        return cpu;
+    }
+
+    #[test]
+    fn irqs_are_masked_by_the_i_flag() {
+        let mut cpu: CPU = cpu_on_nops(StatusRegFlags::U as u8 | StatusRegFlags::I as u8);
+
+        // The line stays asserted, instructions carry on without a push
+        cpu.set_irq_line(true);
+        for _ in 0..4 {
+            cpu.clock();
+        }
+        assert_eq!(cpu.registers.pc, 0x0202);
+        assert_eq!(cpu.registers.sp, 0xFD);
+        assert!(cpu.irq_line);
+
+        cpu.irq();
+        assert_eq!(cpu.registers.pc, 0x0202);
+        assert_eq!(cpu.registers.sp, 0xFD);
+    }
+
+    #[test]
+    fn nmis_are_latched_until_serviced() {
+        let mut cpu: CPU = cpu_on_nops(StatusRegFlags::U as u8 | StatusRegFlags::I as u8);
+
+        // Raised in the middle of an instruction, which still finishes
+        cpu.clock();
+        cpu.trigger_nmi();
+        assert!(cpu.nmi_pending);
+        cpu.clock();
+        assert!(cpu.complete());
+        assert_eq!(cpu.registers.pc, 0x0201);
+        assert!(cpu.nmi_pending);
+    }
+
+    // Run until the CPU is ready for the next instruction, DMA included
+    fn step(cpu: &mut CPU) -> u64 {
+        let start: u64 = cpu.cpu_cycles;
+        loop {
+            cpu.clock();
+            if cpu.complete() {
+                break;
+            }
+        }
+        return cpu.cpu_cycles - start;
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_and_stalls_the_cpu() {
+        let mut cpu: CPU = CPU::new();
+        let ppu: Rc<RefCell<PPU>> = Rc::new(RefCell::new(PPU::new()));
+        cpu.bus.attach(
+            bus::PPU_REGISTERS_START,
+            bus::PPU_REGISTERS_END,
+            Box::new(ppu.clone()),
+        );
+
+        for offset in 0x00..=0xFF {
+            cpu.write(0x0200 + offset, offset as u8 ^ 0xA5);
+        }
+
+        // Point OAMADDR at $10 and copy page $02 three times, the last one an
+        // odd number of cycles after the second
+        #[rustfmt::skip]
+        let program: [u8; 21] = [
+            0xA9, 0x10,             // LDA #$10
+            0x8D, 0x03, 0x20,       // STA $2003
+            0xA9, 0x02,             // LDA #$02
+            0x8D, 0x14, 0x40,       // STA $4014
+            0x8D, 0x14, 0x40,       // STA $4014
+            0xE6, 0x00,             // INC $00
+            0x8D, 0x14, 0x40,       // STA $4014
+            0x4C, 0x12, 0x03,       // JMP $0312
+        ];
+        for (offset, byte) in program.iter().enumerate() {
+            cpu.write(0x0300 + offset as u16, *byte);
+        }
+        cpu.registers.pc = 0x0300;
+
+        for _ in 0..4 {
+            step(&mut cpu);
+        }
+
+        // The CPU is held before the next instruction, an extra cycle when
+        // the DMA starts on an odd cycle. The second one then starts on an odd
+        // cycle and the third on an even one.
+        let parity: u64 = cpu.cpu_cycles & 0x01;
+        assert_eq!(step(&mut cpu), 513 + parity);
+        assert_eq!(step(&mut cpu), 4);
+        assert_eq!(step(&mut cpu), 514);
+        assert_eq!(step(&mut cpu), 5);
+        assert_eq!(step(&mut cpu), 4);
+        assert_eq!(step(&mut cpu), 513);
+
+        // Copied from OAMADDR on, wrapping around
+        let expected: Vec<u8> = (0x00..=0xFF)
+            .map(|i: u8| i.wrapping_sub(0x10) ^ 0xA5)
+            .collect();
+        assert_eq!(ppu.borrow().oam().to_vec(), expected);
+    }
 }
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::savestate::savestate::{StateError, StateReader, StateWriter};
+
+// A memory mapped device attached to the CPU bus (PPU, APU, controllers,
+// cartridge, debug peripherals, ...). Devices receive the full CPU address
+// and are responsible for any mirroring within their range.
+pub trait Device {
+    // Read a byte, which may have side effects on the device (e.g. reading
+    // PPUSTATUS clears the vblank flag)
+    fn read(&mut self, address: u16) -> u8;
+
+    // Write a byte to the device
+    fn write(&mut self, address: u16, data: u8);
+
+    // Read a byte without any side effects, for debuggers and tracing.
+    // Devices that can't do so return None.
+    fn peek(&self, _address: u16) -> Option<u8> {
+        return None;
+    }
+
+    // Bits of a read the device doesn't drive, which keep the last value
+    // seen on the data bus (open bus)
+    fn open_bus_mask(&self, _address: u16) -> u8 {
+        return 0x00;
+    }
+
+    // Called by the bus once per CPU cycle, for devices that time CPU
+    // accesses (e.g. MMC1 ignoring writes on consecutive cycles)
+    fn cpu_clock(&mut self) {}
+
+    // Save the device's state into a save state (see savestate). Devices
+    // without state of their own keep the default and save nothing.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    // Restore what save_state wrote
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        return Ok(());
+    }
+}
+
+// Lets a device be attached to the bus while its owner keeps a handle to it
+// (e.g. to clock it or read out a framebuffer)
+impl<T: Device> Device for Rc<RefCell<T>> {
+    fn read(&mut self, address: u16) -> u8 {
+        return self.borrow_mut().read(address);
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.borrow_mut().write(address, data);
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return self.borrow().peek(address);
+    }
+
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        return self.borrow().open_bus_mask(address);
+    }
+
+    fn cpu_clock(&mut self) {
+        self.borrow_mut().cpu_clock();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.borrow().save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.borrow_mut().load_state(state);
+    }
+}
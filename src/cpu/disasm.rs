@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::bus::Bus;
+use crate::cpu::instructions::{AddrMode, Instruction, LOOKUP};
+use crate::ternary;
+
+// Names for addresses, substituted for the operand when disassembling
+pub type SymbolTable = HashMap<u16, String>;
+
+// NES memory mapped registers, named as on the NESdev wiki
+static REGISTER_SYMBOLS: [(u16, &str); 30] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+// Symbol table with the PPU, APU and I/O register names
+pub fn register_symbols() -> SymbolTable {
+    return REGISTER_SYMBOLS
+        .iter()
+        .map(|(address, name)| (*address, name.to_string()))
+        .collect();
+}
+
+// A single disassembled instruction. Bytes at the end of a slice that are too
+// few to hold a whole instruction are kept as data, with no instruction.
+pub struct Decoded {
+    pub address: u16,
+    pub bytes: Vec<u8>, // opcode included
+    pub instruction: Option<&'static Instruction>,
+}
+
+impl Decoded {
+    pub fn length(&self) -> u16 {
+        return self.bytes.len() as u16;
+    }
+
+    // Address of the following instruction
+    pub fn next_address(&self) -> u16 {
+        return self.address.wrapping_add(self.length());
+    }
+
+    // 8 or 16 bit operand as encoded after the opcode
+    pub fn operand(&self) -> u16 {
+        return match self.bytes.len() {
+            2 => self.bytes[1] as u16,
+            3 => ((self.bytes[2] as u16) << 8) | self.bytes[1] as u16,
+            _ => 0x0000,
+        };
+    }
+
+    // Address a branch, jump or memory access refers to, before indexing.
+    // Branch offsets are resolved to the target address.
+    pub fn target(&self) -> Option<u16> {
+        let instruction: &Instruction = self.instruction?;
+        return match instruction.mode {
+            AddrMode::IMP | AddrMode::ACC | AddrMode::IMM => None,
+            AddrMode::REL => {
+                let offset: u16 = self.operand() as u8 as i8 as u16;
+                Some(self.next_address().wrapping_add(offset))
+            }
+            _ => Some(self.operand()),
+        };
+    }
+
+    // Assembly syntax, e.g. "LDA ($20),Y" or "BNE $C012"
+    pub fn text(&self) -> String {
+        return self.format(None);
+    }
+
+    // Same as text() with addresses found in the table replaced by their
+    // names, e.g. "STA PPUCTRL"
+    pub fn text_with_symbols(&self, symbols: &SymbolTable) -> String {
+        return self.format(Some(symbols));
+    }
+
+    fn format(&self, symbols: Option<&SymbolTable>) -> String {
+        let instruction: &Instruction = match self.instruction {
+            Some(instruction) => instruction,
+            None => {
+                let data: Vec<String> = self.bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                return format!(".byte {}", data.join(", "));
+            }
+        };
+        let operand: u16 = self.operand();
+        let name = |address: u16, digits: usize| -> String {
+            if let Some(symbol) = symbols.and_then(|symbols| symbols.get(&address)) {
+                return symbol.clone();
+            }
+            return format!("${:0width$X}", address, width = digits);
+        };
+
+        let text: String = match instruction.mode {
+            // The byte after BRK is padding, it still counts in the length
+            _ if instruction.name == "BRK" => String::new(),
+            AddrMode::IMP => String::new(),
+            AddrMode::ACC => String::from("A"),
+            AddrMode::IMM => format!("#${:02X}", operand),
+            AddrMode::ZP0 => name(operand, 2),
+            AddrMode::ZPX => format!("{},X", name(operand, 2)),
+            AddrMode::ZPY => format!("{},Y", name(operand, 2)),
+            AddrMode::REL => name(self.target().unwrap_or(0x0000), 4),
+            AddrMode::ABS => name(operand, 4),
+            AddrMode::ABX => format!("{},X", name(operand, 4)),
+            AddrMode::ABY => format!("{},Y", name(operand, 4)),
+            AddrMode::IND => format!("({})", name(operand, 4)),
+            AddrMode::IZX => format!("({},X)", name(operand, 2)),
+            AddrMode::IZY => format!("({}),Y", name(operand, 2)),
+        };
+        return format!("{} {}", instruction.name, text)
+            .trim_end()
+            .to_string();
+    }
+}
+
+// Listing line in the same layout as the trace, unofficial opcodes marked
+// with a '*':
+//
+// C000  4C F5 C5  JMP $C5F5
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let official: bool = self
+            .instruction
+            .map_or(true, |instruction| instruction.official);
+        return write!(
+            f,
+            "{:04X}  {:<9}{}{}",
+            self.address,
+            bytes.join(" "),
+            ternary!(official, ' ', '*'),
+            self.text()
+        );
+    }
+}
+
+// Decode the instruction at address, fetching its bytes through fetch
+pub fn decode(address: u16, fetch: &dyn Fn(u16) -> u8) -> Decoded {
+    let instruction: &'static Instruction = &LOOKUP[fetch(address) as usize];
+    let bytes: Vec<u8> = (0..instruction.mode.length())
+        .map(|offset| fetch(address.wrapping_add(offset)))
+        .collect();
+    return Decoded {
+        address,
+        bytes,
+        instruction: Some(instruction),
+    };
+}
+
+// Disassemble a slice of code loaded at origin
+pub fn disassemble(code: &[u8], origin: u16) -> Vec<Decoded> {
+    let mut decoded: Vec<Decoded> = Vec::new();
+    let mut offset: usize = 0;
+    while offset < code.len() {
+        let address: u16 = origin.wrapping_add(offset as u16);
+        let length: usize = LOOKUP[code[offset] as usize].mode.length() as usize;
+        if offset + length > code.len() {
+            decoded.push(Decoded {
+                address,
+                bytes: code[offset..].to_vec(),
+                instruction: None,
+            });
+            break;
+        }
+        decoded.push(decode(address, &|a: u16| {
+            code[offset + a.wrapping_sub(address) as usize]
+        }));
+        offset += length;
+    }
+    return decoded;
+}
+
+// Disassemble the instructions starting in start..=end of a live bus. Memory
+// is peeked, so devices see no reads; unreadable addresses show as $00.
+pub fn disassemble_bus(bus: &Bus, start: u16, end: u16) -> Vec<Decoded> {
+    let fetch = |address: u16| -> u8 { bus.peek(address).unwrap_or(0x00) };
+    let mut decoded: Vec<Decoded> = Vec::new();
+    let mut address: u32 = start as u32;
+    while address <= end as u32 {
+        let instruction: Decoded = decode(address as u16, &fetch);
+        address += instruction.length() as u32;
+        decoded.push(instruction);
+    }
+    return decoded;
+}
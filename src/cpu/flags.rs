@@ -1,5 +1,5 @@
 // Defines the 8 flags for the status register
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatusRegFlags {
     C = (1 << 0), // Carry bit
     Z = (1 << 1), // Zero
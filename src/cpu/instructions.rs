@@ -0,0 +1,179 @@
+use crate::cpu::cpu::CPU;
+
+// Signature shared by addressing mode and instruction functions.
+// Returning 1 signals that an additional cycle may be required.
+pub type Operation = fn(&mut CPU) -> u8;
+
+// Addressing modes, named after their implementing functions on CPU
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrMode {
+    IMP, // Implied
+    ACC, // Accumulator
+    IMM, // Immediate
+    ZP0, // Zero page
+    ZPX, // Zero page with X offset
+    ZPY, // Zero page with Y offset
+    REL, // Relative
+    ABS, // Absolute
+    ABX, // Absolute with X offset
+    ABY, // Absolute with Y offset
+    IND, // Indirect
+    IZX, // Indirect with X offset
+    IZY, // Indirect with Y offset
+}
+
+// Every addressing mode, in declaration order (so indexed by `mode as u8`)
+pub static ADDR_MODES: [AddrMode; 13] = [
+    AddrMode::IMP,
+    AddrMode::ACC,
+    AddrMode::IMM,
+    AddrMode::ZP0,
+    AddrMode::ZPX,
+    AddrMode::ZPY,
+    AddrMode::REL,
+    AddrMode::ABS,
+    AddrMode::ABX,
+    AddrMode::ABY,
+    AddrMode::IND,
+    AddrMode::IZX,
+    AddrMode::IZY,
+];
+
+impl AddrMode {
+    // Instruction length in bytes, opcode included
+    pub fn length(&self) -> u16 {
+        return match self {
+            AddrMode::IMP | AddrMode::ACC => 1,
+            AddrMode::ABS | AddrMode::ABX | AddrMode::ABY | AddrMode::IND => 3,
+            _ => 2,
+        };
+    }
+}
+
+// Single entry of the opcode lookup table
+pub struct Instruction {
+    pub name: &'static str,   // Mnemonic used for disassembly and logging
+    pub operate: Operation,   // Instruction implementation
+    pub addr_mode: Operation, // Addressing mode implementation
+    pub mode: AddrMode,       // Addressing mode the implementation belongs to
+    pub cycles: u8,           // Base number of cycles required
+    pub official: bool,       // Part of the documented instruction set
+}
+
+// Builds a lookup table entry from instruction and addressing mode names
+macro_rules! op {
+    ($name:literal, $operate:ident, $addr_mode:ident, $cycles:literal) => {
+        Instruction {
+            name: $name,
+            operate: CPU::$operate,
+            addr_mode: CPU::$addr_mode,
+            mode: AddrMode::$addr_mode,
+            cycles: $cycles,
+            official: true,
+        }
+    };
+}
+
+// Same as op! for the unofficial (illegal) opcodes
+macro_rules! un {
+    ($name:literal, $operate:ident, $addr_mode:ident, $cycles:literal) => {
+        Instruction {
+            official: false,
+            ..op!($name, $operate, $addr_mode, $cycles)
+        }
+    };
+}
+
+// 16x16 opcode matrix indexed by the opcode byte. The upper nibble of the
+// opcode selects the row and the lower nibble selects the column.
+//
+// Unofficial opcodes use the mnemonics from nestest.log where it has them
+// (e.g. ISB rather than ISC). The JAM opcodes lock up the CPU. JSR reads the
+// high byte of its target after pushing the return address, so it is given
+// the immediate addressing function and reads the address itself.
+//
+// Reference: http://www.oxyron.de/html/opcodes02.html
+#[rustfmt::skip]
+pub static LOOKUP: [Instruction; 256] = [
+    // 0x00
+    op!("BRK", BRK, IMM, 7), op!("ORA", ORA, IZX, 6), un!("JAM", JAM, IMP, 2), un!("SLO", SLO, IZX, 8),
+    un!("NOP", NOP, ZP0, 3), op!("ORA", ORA, ZP0, 3), op!("ASL", ASL, ZP0, 5), un!("SLO", SLO, ZP0, 5),
+    op!("PHP", PHP, IMP, 3), op!("ORA", ORA, IMM, 2), op!("ASL", ASL, ACC, 2), un!("ANC", ANC, IMM, 2),
+    un!("NOP", NOP, ABS, 4), op!("ORA", ORA, ABS, 4), op!("ASL", ASL, ABS, 6), un!("SLO", SLO, ABS, 6),
+    // 0x10
+    op!("BPL", BPL, REL, 2), op!("ORA", ORA, IZY, 5), un!("JAM", JAM, IMP, 2), un!("SLO", SLO, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("ORA", ORA, ZPX, 4), op!("ASL", ASL, ZPX, 6), un!("SLO", SLO, ZPX, 6),
+    op!("CLC", CLC, IMP, 2), op!("ORA", ORA, ABY, 4), un!("NOP", NOP, IMP, 2), un!("SLO", SLO, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("ORA", ORA, ABX, 4), op!("ASL", ASL, ABX, 7), un!("SLO", SLO, ABX, 7),
+    // 0x20
+    Instruction { addr_mode: CPU::IMM, ..op!("JSR", JSR, ABS, 6) }, op!("AND", AND, IZX, 6), un!("JAM", JAM, IMP, 2), un!("RLA", RLA, IZX, 8),
+    op!("BIT", BIT, ZP0, 3), op!("AND", AND, ZP0, 3), op!("ROL", ROL, ZP0, 5), un!("RLA", RLA, ZP0, 5),
+    op!("PLP", PLP, IMP, 4), op!("AND", AND, IMM, 2), op!("ROL", ROL, ACC, 2), un!("ANC", ANC, IMM, 2),
+    op!("BIT", BIT, ABS, 4), op!("AND", AND, ABS, 4), op!("ROL", ROL, ABS, 6), un!("RLA", RLA, ABS, 6),
+    // 0x30
+    op!("BMI", BMI, REL, 2), op!("AND", AND, IZY, 5), un!("JAM", JAM, IMP, 2), un!("RLA", RLA, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("AND", AND, ZPX, 4), op!("ROL", ROL, ZPX, 6), un!("RLA", RLA, ZPX, 6),
+    op!("SEC", SEC, IMP, 2), op!("AND", AND, ABY, 4), un!("NOP", NOP, IMP, 2), un!("RLA", RLA, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("AND", AND, ABX, 4), op!("ROL", ROL, ABX, 7), un!("RLA", RLA, ABX, 7),
+    // 0x40
+    op!("RTI", RTI, IMP, 6), op!("EOR", EOR, IZX, 6), un!("JAM", JAM, IMP, 2), un!("SRE", SRE, IZX, 8),
+    un!("NOP", NOP, ZP0, 3), op!("EOR", EOR, ZP0, 3), op!("LSR", LSR, ZP0, 5), un!("SRE", SRE, ZP0, 5),
+    op!("PHA", PHA, IMP, 3), op!("EOR", EOR, IMM, 2), op!("LSR", LSR, ACC, 2), un!("ALR", ALR, IMM, 2),
+    op!("JMP", JMP, ABS, 3), op!("EOR", EOR, ABS, 4), op!("LSR", LSR, ABS, 6), un!("SRE", SRE, ABS, 6),
+    // 0x50
+    op!("BVC", BVC, REL, 2), op!("EOR", EOR, IZY, 5), un!("JAM", JAM, IMP, 2), un!("SRE", SRE, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("EOR", EOR, ZPX, 4), op!("LSR", LSR, ZPX, 6), un!("SRE", SRE, ZPX, 6),
+    op!("CLI", CLI, IMP, 2), op!("EOR", EOR, ABY, 4), un!("NOP", NOP, IMP, 2), un!("SRE", SRE, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("EOR", EOR, ABX, 4), op!("LSR", LSR, ABX, 7), un!("SRE", SRE, ABX, 7),
+    // 0x60
+    op!("RTS", RTS, IMP, 6), op!("ADC", ADC, IZX, 6), un!("JAM", JAM, IMP, 2), un!("RRA", RRA, IZX, 8),
+    un!("NOP", NOP, ZP0, 3), op!("ADC", ADC, ZP0, 3), op!("ROR", ROR, ZP0, 5), un!("RRA", RRA, ZP0, 5),
+    op!("PLA", PLA, IMP, 4), op!("ADC", ADC, IMM, 2), op!("ROR", ROR, ACC, 2), un!("ARR", ARR, IMM, 2),
+    op!("JMP", JMP, IND, 5), op!("ADC", ADC, ABS, 4), op!("ROR", ROR, ABS, 6), un!("RRA", RRA, ABS, 6),
+    // 0x70
+    op!("BVS", BVS, REL, 2), op!("ADC", ADC, IZY, 5), un!("JAM", JAM, IMP, 2), un!("RRA", RRA, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("ADC", ADC, ZPX, 4), op!("ROR", ROR, ZPX, 6), un!("RRA", RRA, ZPX, 6),
+    op!("SEI", SEI, IMP, 2), op!("ADC", ADC, ABY, 4), un!("NOP", NOP, IMP, 2), un!("RRA", RRA, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("ADC", ADC, ABX, 4), op!("ROR", ROR, ABX, 7), un!("RRA", RRA, ABX, 7),
+    // 0x80
+    un!("NOP", NOP, IMM, 2), op!("STA", STA, IZX, 6), un!("NOP", NOP, IMM, 2), un!("SAX", SAX, IZX, 6),
+    op!("STY", STY, ZP0, 3), op!("STA", STA, ZP0, 3), op!("STX", STX, ZP0, 3), un!("SAX", SAX, ZP0, 3),
+    op!("DEY", DEY, IMP, 2), un!("NOP", NOP, IMM, 2), op!("TXA", TXA, IMP, 2), un!("XAA", XAA, IMM, 2),
+    op!("STY", STY, ABS, 4), op!("STA", STA, ABS, 4), op!("STX", STX, ABS, 4), un!("SAX", SAX, ABS, 4),
+    // 0x90
+    op!("BCC", BCC, REL, 2), op!("STA", STA, IZY, 6), un!("JAM", JAM, IMP, 2), un!("SHA", SHA, IZY, 6),
+    op!("STY", STY, ZPX, 4), op!("STA", STA, ZPX, 4), op!("STX", STX, ZPY, 4), un!("SAX", SAX, ZPY, 4),
+    op!("TYA", TYA, IMP, 2), op!("STA", STA, ABY, 5), op!("TXS", TXS, IMP, 2), un!("TAS", TAS, ABY, 5),
+    un!("SHY", SHY, ABX, 5), op!("STA", STA, ABX, 5), un!("SHX", SHX, ABY, 5), un!("SHA", SHA, ABY, 5),
+    // 0xA0
+    op!("LDY", LDY, IMM, 2), op!("LDA", LDA, IZX, 6), op!("LDX", LDX, IMM, 2), un!("LAX", LAX, IZX, 6),
+    op!("LDY", LDY, ZP0, 3), op!("LDA", LDA, ZP0, 3), op!("LDX", LDX, ZP0, 3), un!("LAX", LAX, ZP0, 3),
+    op!("TAY", TAY, IMP, 2), op!("LDA", LDA, IMM, 2), op!("TAX", TAX, IMP, 2), un!("LXA", LXA, IMM, 2),
+    op!("LDY", LDY, ABS, 4), op!("LDA", LDA, ABS, 4), op!("LDX", LDX, ABS, 4), un!("LAX", LAX, ABS, 4),
+    // 0xB0
+    op!("BCS", BCS, REL, 2), op!("LDA", LDA, IZY, 5), un!("JAM", JAM, IMP, 2), un!("LAX", LAX, IZY, 5),
+    op!("LDY", LDY, ZPX, 4), op!("LDA", LDA, ZPX, 4), op!("LDX", LDX, ZPY, 4), un!("LAX", LAX, ZPY, 4),
+    op!("CLV", CLV, IMP, 2), op!("LDA", LDA, ABY, 4), op!("TSX", TSX, IMP, 2), un!("LAS", LAS, ABY, 4),
+    op!("LDY", LDY, ABX, 4), op!("LDA", LDA, ABX, 4), op!("LDX", LDX, ABY, 4), un!("LAX", LAX, ABY, 4),
+    // 0xC0
+    op!("CPY", CPY, IMM, 2), op!("CMP", CMP, IZX, 6), un!("NOP", NOP, IMM, 2), un!("DCP", DCP, IZX, 8),
+    op!("CPY", CPY, ZP0, 3), op!("CMP", CMP, ZP0, 3), op!("DEC", DEC, ZP0, 5), un!("DCP", DCP, ZP0, 5),
+    op!("INY", INY, IMP, 2), op!("CMP", CMP, IMM, 2), op!("DEX", DEX, IMP, 2), un!("AXS", AXS, IMM, 2),
+    op!("CPY", CPY, ABS, 4), op!("CMP", CMP, ABS, 4), op!("DEC", DEC, ABS, 6), un!("DCP", DCP, ABS, 6),
+    // 0xD0
+    op!("BNE", BNE, REL, 2), op!("CMP", CMP, IZY, 5), un!("JAM", JAM, IMP, 2), un!("DCP", DCP, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("CMP", CMP, ZPX, 4), op!("DEC", DEC, ZPX, 6), un!("DCP", DCP, ZPX, 6),
+    op!("CLD", CLD, IMP, 2), op!("CMP", CMP, ABY, 4), un!("NOP", NOP, IMP, 2), un!("DCP", DCP, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("CMP", CMP, ABX, 4), op!("DEC", DEC, ABX, 7), un!("DCP", DCP, ABX, 7),
+    // 0xE0
+    op!("CPX", CPX, IMM, 2), op!("SBC", SBC, IZX, 6), un!("NOP", NOP, IMM, 2), un!("ISB", ISB, IZX, 8),
+    op!("CPX", CPX, ZP0, 3), op!("SBC", SBC, ZP0, 3), op!("INC", INC, ZP0, 5), un!("ISB", ISB, ZP0, 5),
+    op!("INX", INX, IMP, 2), op!("SBC", SBC, IMM, 2), op!("NOP", NOP, IMP, 2), un!("SBC", SBC, IMM, 2),
+    op!("CPX", CPX, ABS, 4), op!("SBC", SBC, ABS, 4), op!("INC", INC, ABS, 6), un!("ISB", ISB, ABS, 6),
+    // 0xF0
+    op!("BEQ", BEQ, REL, 2), op!("SBC", SBC, IZY, 5), un!("JAM", JAM, IMP, 2), un!("ISB", ISB, IZY, 8),
+    un!("NOP", NOP, ZPX, 4), op!("SBC", SBC, ZPX, 4), op!("INC", INC, ZPX, 6), un!("ISB", ISB, ZPX, 6),
+    op!("SED", SED, IMP, 2), op!("SBC", SBC, ABY, 4), un!("NOP", NOP, IMP, 2), un!("ISB", ISB, ABY, 7),
+    un!("NOP", NOP, ABX, 4), op!("SBC", SBC, ABX, 4), op!("INC", INC, ABX, 7), un!("ISB", ISB, ABX, 7),
+];
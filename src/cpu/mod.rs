@@ -1,6 +1,11 @@
+pub mod asm;
 pub mod bus;
+#[allow(clippy::module_inception)]
 pub mod cpu;
+pub mod device;
+pub mod disasm;
 pub mod flags;
+pub mod instructions;
 pub mod opcode_compression;
-mod registers;
-pub mod utils;
+pub mod registers;
+pub mod trace;
@@ -1,3 +1,4 @@
+#![allow(unused_assignments)]
 type Opcode = u32;
 
 #[derive(Debug)]
@@ -13,7 +14,7 @@ fn char_to_u32(c: char) -> u32 {
 
 fn decode_base64(value: u32) -> u32 {
     if value >= char_to_u32('A') && value <= char_to_u32('Z') {
-        return value - char_to_u32('A') + 0u32;
+        return value - char_to_u32('A');
     } else if value >= char_to_u32('a') && value <= char_to_u32('z') {
         return value - char_to_u32('a') + 26u32;
     } else if value >= char_to_u32('0') && value <= char_to_u32('9') {
@@ -21,7 +21,7 @@ impl Registers {
         ternary!(
             condition,
             self.status |= flag as u8,
-            self.status &= flag as u8
+            self.status &= !(flag as u8)
         );
     }
 }
@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::disasm::{self, Decoded};
+use crate::cpu::instructions::{AddrMode, Instruction, LOOKUP};
+use crate::ternary;
+use crate::LOGGER;
+
+// Where trace lines go
+pub enum TraceSink {
+    Writer(Box<dyn Write>),
+    Callback(Box<dyn FnMut(&str)>),
+    None, // only routed through the logger, if enabled
+}
+
+// Per instruction execution trace in the format of nestest.log:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// Each line shows the state before the instruction executes. Unofficial
+// opcodes are marked with a '*' before the mnemonic.
+pub struct Tracer {
+    sink: TraceSink,
+    pub enabled: bool,
+    pub log: bool, // also send lines to LOGGER (and so its JSON file)
+    ppu_position: Option<Box<dyn Fn() -> (u16, u16)>>,
+}
+
+impl Tracer {
+    pub fn new(sink: TraceSink) -> Self {
+        Self {
+            sink,
+            enabled: true,
+            log: false,
+            ppu_position: None,
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file: File = File::create(path)?;
+        return Ok(Self::new(TraceSink::Writer(Box::new(BufWriter::new(file)))));
+    }
+
+    pub fn to_callback<F: FnMut(&str) + 'static>(callback: F) -> Self {
+        return Self::new(TraceSink::Callback(Box::new(callback)));
+    }
+
+    pub fn to_logger() -> Self {
+        let mut tracer: Self = Self::new(TraceSink::None);
+        tracer.log = true;
+        return tracer;
+    }
+
+    // Source of the PPU (scanline, dot) shown on each line. The field is
+    // left out when there is none.
+    pub fn set_ppu_position<F: Fn() -> (u16, u16) + 'static>(&mut self, position: F) {
+        self.ppu_position = Some(Box::new(position));
+    }
+
+    // Trace the instruction the CPU is about to execute
+    pub fn trace(&mut self, cpu: &CPU) {
+        if !self.enabled {
+            return;
+        }
+        let position: Option<(u16, u16)> = self.ppu_position.as_ref().map(|position| position());
+        let line: String = format_line(cpu, position);
+        match &mut self.sink {
+            TraceSink::Writer(writer) => {
+                if let Err(e) = writeln!(writer, "{}", line) {
+                    error!(LOGGER, "Failed to write trace, disabling it: {}", e);
+                    self.enabled = false;
+                }
+            }
+            TraceSink::Callback(callback) => callback(&line),
+            TraceSink::None => {}
+        }
+        if self.log {
+            info!(LOGGER, "{}", line; "source" => "trace");
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let TraceSink::Writer(writer) = &mut self.sink {
+            return writer.flush();
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// Format the trace line for the instruction at the program counter. Memory
+// is peeked, so tracing has no side effects on devices.
+pub fn format_line(cpu: &CPU, ppu_position: Option<(u16, u16)>) -> String {
+    let pc: u16 = cpu.registers.pc;
+    let peek = |address: u16| -> u8 { cpu.bus.peek(address).unwrap_or(0x00) };
+    let decoded: Decoded = disasm::decode(pc, &peek);
+    let instruction: &Instruction = &LOOKUP[decoded.bytes[0] as usize];
+
+    let bytes: String = decoded
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let marker: char = ternary!(instruction.official, ' ', '*');
+    let text: String = format!(
+        "{} {}",
+        instruction.name,
+        format_operand(cpu, instruction, &peek)
+    );
+
+    let mut line: String = format!(
+        "{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        pc,
+        bytes,
+        marker,
+        text.trim_end(),
+        cpu.registers.a,
+        cpu.registers.x,
+        cpu.registers.y,
+        cpu.registers.status,
+        cpu.registers.sp
+    );
+    if let Some((scanline, dot)) = ppu_position {
+        line.push_str(&format!(" PPU:{:>3},{:>3}", scanline, dot));
+    }
+    line.push_str(&format!(" CYC:{}", cpu.cpu_cycles));
+    return line;
+}
+
+// Operand with the effective address and the value found there resolved
+fn format_operand(cpu: &CPU, instruction: &Instruction, peek: &dyn Fn(u16) -> u8) -> String {
+    let pc: u16 = cpu.registers.pc;
+    let lo: u8 = peek(pc.wrapping_add(1));
+    let hi: u8 = peek(pc.wrapping_add(2));
+    let absolute: u16 = ((hi as u16) << 8) | lo as u16;
+    let (x, y) = (cpu.registers.x, cpu.registers.y);
+    let zero_page_word = |address: u8| -> u16 {
+        return ((peek(address.wrapping_add(1) as u16) as u16) << 8) | peek(address as u16) as u16;
+    };
+    let jump: bool = instruction.name == "JMP" || instruction.name == "JSR";
+
+    return match instruction.mode {
+        AddrMode::IMP => String::new(),
+        AddrMode::ACC => String::from("A"),
+        AddrMode::IMM => format!("#${:02X}", lo),
+        AddrMode::ZP0 => format!("${:02X} = {:02X}", lo, peek(lo as u16)),
+        AddrMode::ZPX => {
+            let address: u8 = lo.wrapping_add(x);
+            format!(
+                "${:02X},X @ {:02X} = {:02X}",
+                lo,
+                address,
+                peek(address as u16)
+            )
+        }
+        AddrMode::ZPY => {
+            let address: u8 = lo.wrapping_add(y);
+            format!(
+                "${:02X},Y @ {:02X} = {:02X}",
+                lo,
+                address,
+                peek(address as u16)
+            )
+        }
+        AddrMode::REL => {
+            let offset: u16 = lo as i8 as u16;
+            format!("${:04X}", pc.wrapping_add(2).wrapping_add(offset))
+        }
+        AddrMode::ABS if jump => format!("${:04X}", absolute),
+        AddrMode::ABS => format!("${:04X} = {:02X}", absolute, peek(absolute)),
+        AddrMode::ABX => {
+            let address: u16 = absolute.wrapping_add(x as u16);
+            format!(
+                "${:04X},X @ {:04X} = {:02X}",
+                absolute,
+                address,
+                peek(address)
+            )
+        }
+        AddrMode::ABY => {
+            let address: u16 = absolute.wrapping_add(y as u16);
+            format!(
+                "${:04X},Y @ {:04X} = {:02X}",
+                absolute,
+                address,
+                peek(address)
+            )
+        }
+        AddrMode::IND => {
+            // The high byte comes from the same page (6502 page wrap bug)
+            let hi_address: u16 = (absolute & 0xFF00) | (absolute.wrapping_add(1) & 0x00FF);
+            let target: u16 = ((peek(hi_address) as u16) << 8) | peek(absolute) as u16;
+            format!("(${:04X}) = {:04X}", absolute, target)
+        }
+        AddrMode::IZX => {
+            let pointer: u8 = lo.wrapping_add(x);
+            let address: u16 = zero_page_word(pointer);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                lo,
+                pointer,
+                address,
+                peek(address)
+            )
+        }
+        AddrMode::IZY => {
+            let base: u16 = zero_page_word(lo);
+            let address: u16 = base.wrapping_add(y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                lo,
+                base,
+                address,
+                peek(address)
+            )
+        }
+    };
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cpu::bus::BusAccess;
+use crate::cpu::cpu::CPU;
+use crate::cpu::flags::StatusRegFlags;
+use crate::nes::nes::Nes;
+use crate::ternary;
+
+static JSR: u8 = 0x20;
+static RTI: u8 = 0x40;
+static RTS: u8 = 0x60;
+static JSR_LENGTH: u16 = 3;
+
+// Instructions a single run may execute before giving up
+pub static DEFAULT_BUDGET: u64 = 2_000_000;
+
+// Something the debugger can run an instruction at a time: a bare CPU or the
+// whole console
+pub trait Target {
+    fn cpu(&self) -> &CPU;
+    fn cpu_mut(&mut self) -> &mut CPU;
+
+    // Run until the next instruction boundary
+    fn step(&mut self);
+}
+
+impl Target for CPU {
+    fn cpu(&self) -> &CPU {
+        return self;
+    }
+
+    fn cpu_mut(&mut self) -> &mut CPU {
+        return self;
+    }
+
+    fn step(&mut self) {
+        loop {
+            self.clock();
+            if self.complete() {
+                break;
+            }
+        }
+    }
+}
+
+impl Target for Nes {
+    fn cpu(&self) -> &CPU {
+        return &self.cpu;
+    }
+
+    fn cpu_mut(&mut self) -> &mut CPU {
+        return &mut self.cpu;
+    }
+
+    fn step(&mut self) {
+        self.step_instruction();
+    }
+}
+
+// Register (or status flag) a condition looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    P,
+    PC,
+    Flag(StatusRegFlags),
+}
+
+impl Register {
+    pub fn parse(text: &str) -> Option<Self> {
+        return match text.to_ascii_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "X" => Some(Register::X),
+            "Y" => Some(Register::Y),
+            "S" | "SP" => Some(Register::SP),
+            "P" => Some(Register::P),
+            "PC" => Some(Register::PC),
+            "C" => Some(Register::Flag(StatusRegFlags::C)),
+            "Z" => Some(Register::Flag(StatusRegFlags::Z)),
+            "I" => Some(Register::Flag(StatusRegFlags::I)),
+            "D" => Some(Register::Flag(StatusRegFlags::D)),
+            "V" => Some(Register::Flag(StatusRegFlags::V)),
+            "N" => Some(Register::Flag(StatusRegFlags::N)),
+            _ => None,
+        };
+    }
+
+    pub fn value(&self, cpu: &CPU) -> u16 {
+        return match self {
+            Register::A => cpu.registers.a as u16,
+            Register::X => cpu.registers.x as u16,
+            Register::Y => cpu.registers.y as u16,
+            Register::SP => cpu.registers.sp as u16,
+            Register::P => cpu.registers.status as u16,
+            Register::PC => cpu.registers.pc,
+            Register::Flag(flag) => cpu.registers.get_flag(*flag) as u16,
+        };
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Register::Flag(flag) => write!(f, "{:?}", flag),
+            _ => write!(f, "{:?}", self),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// Longer operators first, so "<=" isn't taken for "<"
+static COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        return COMPARISONS
+            .iter()
+            .find(|(_, comparison)| comparison == self)
+            .map(|(symbol, _)| *symbol)
+            .unwrap_or("==");
+    }
+
+    pub fn holds(&self, left: u16, right: u16) -> bool {
+        return match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        };
+    }
+}
+
+// Comparison of a register against a value, e.g. A == $10
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    // Split "<register> <comparison> <value>" into its parts, leaving the
+    // value for the caller to parse
+    pub fn split(text: &str) -> Option<(Register, Comparison, &str)> {
+        for (symbol, comparison) in COMPARISONS.iter() {
+            if let Some((register, value)) = text.split_once(symbol) {
+                let register: Register = Register::parse(register.trim())?;
+                return Some((register, *comparison, value.trim()));
+            }
+        }
+        return None;
+    }
+
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        return self.comparison.holds(self.register.value(cpu), self.value);
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits: usize = ternary!(self.register == Register::PC, 4, 2);
+        return write!(
+            f,
+            "{} {} ${:0width$X}",
+            self.register,
+            self.comparison.symbol(),
+            self.value,
+            width = digits
+        );
+    }
+}
+
+// Stops before the instruction at an address is executed, if the condition
+// (when there is one) holds. Without an address it stops as soon as the
+// condition becomes true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: Option<u16>,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn at(address: u16) -> Self {
+        return Self {
+            address: Some(address),
+            condition: None,
+        };
+    }
+
+    pub fn when(condition: Condition) -> Self {
+        return Self {
+            address: None,
+            condition: Some(condition),
+        };
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match (self.address, self.condition) {
+            (Some(address), Some(condition)) => write!(f, "${:04X} if {}", address, condition),
+            (Some(address), None) => write!(f, "${:04X}", address),
+            (None, Some(condition)) => write!(f, "if {}", condition),
+            (None, None) => write!(f, "never"),
+        };
+    }
+}
+
+// Stops after an instruction reads or writes an address in start..=end, or
+// before one in the range is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &BusAccess) -> bool {
+        let kind: bool = ternary!(access.write, self.write, self.read);
+        return kind && (self.start..=self.end).contains(&access.address);
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: String = [(self.read, 'r'), (self.write, 'w'), (self.execute, 'x')]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, kind)| *kind)
+            .collect();
+        if self.start == self.end {
+            return write!(f, "${:04X} {}", self.start, kinds);
+        }
+        return write!(f, "${:04X}-${:04X} {}", self.start, self.end, kinds);
+    }
+}
+
+// Why a run returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Done,                                 // the step or run to cycle finished
+    Breakpoint(usize),                    // id of the breakpoint
+    Watchpoint(usize, Option<BusAccess>), // id and the access, None when executing
+    Budget,                               // ran out of instructions
+}
+
+// Breakpoints, watchpoints and stepping on top of a Target. Breakpoints and
+// watchpoints share one sequence of ids.
+pub struct Debugger {
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_id: usize,
+    pub budget: u64, // instructions a run may execute before giving up
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        return Self {
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_id: 1,
+            budget: DEFAULT_BUDGET,
+        };
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        self.breakpoints.insert(self.next_id - 1, breakpoint);
+        return self.next_id - 1;
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.next_id += 1;
+        self.watchpoints.insert(self.next_id - 1, watchpoint);
+        return self.next_id - 1;
+    }
+
+    // Remove a breakpoint or watchpoint, returning whether it existed
+    pub fn remove(&mut self, id: usize) -> bool {
+        return self.breakpoints.remove(&id).is_some() || self.watchpoints.remove(&id).is_some();
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Breakpoint> {
+        return &self.breakpoints;
+    }
+
+    pub fn watchpoints(&self) -> &BTreeMap<usize, Watchpoint> {
+        return &self.watchpoints;
+    }
+
+    // Execute a single instruction (or interrupt sequence)
+    pub fn step_into<T: Target>(&mut self, target: &mut T) -> StopReason {
+        return self.run_until(target, |_, _| true);
+    }
+
+    // Execute a single instruction, running a JSR until it returns
+    pub fn step_over<T: Target>(&mut self, target: &mut T) -> StopReason {
+        let cpu: &CPU = target.cpu();
+        if cpu.bus.peek(cpu.registers.pc) != Some(JSR) {
+            return self.step_into(target);
+        }
+        // Recursive calls return to the same address with less on the stack
+        let (pc, sp) = (cpu.registers.pc.wrapping_add(JSR_LENGTH), cpu.registers.sp);
+        return self.run_until(target, |cpu, _| {
+            cpu.registers.pc == pc && cpu.registers.sp == sp
+        });
+    }
+
+    // Run until the current subroutine (or interrupt handler) returns. The
+    // stack pointer is compared as a signed distance, as it wraps around
+    // within page 1.
+    pub fn step_out<T: Target>(&mut self, target: &mut T) -> StopReason {
+        let sp: u8 = target.cpu().registers.sp;
+        return self.run_until(target, |cpu, opcode| {
+            (opcode == RTS || opcode == RTI) && cpu.registers.sp.wrapping_sub(sp) as i8 > 0
+        });
+    }
+
+    // Run until the CPU cycle counter reaches a value
+    pub fn run_to_cycle<T: Target>(&mut self, target: &mut T, cycle: u64) -> StopReason {
+        if target.cpu().cpu_cycles >= cycle {
+            return StopReason::Done;
+        }
+        return self.run_until(target, |cpu, _| cpu.cpu_cycles >= cycle);
+    }
+
+    // Run until a breakpoint or watchpoint
+    pub fn run<T: Target>(&mut self, target: &mut T) -> StopReason {
+        return self.run_until(target, |_, _| false);
+    }
+
+    // Run instructions until done (given the CPU after the instruction and
+    // its opcode) returns true, or a breakpoint or watchpoint stops it. The
+    // first instruction is always executed, so runs can continue from a
+    // breakpoint.
+    fn run_until<T: Target, F: FnMut(&CPU, u8) -> bool>(
+        &mut self,
+        target: &mut T,
+        mut done: F,
+    ) -> StopReason {
+        // Finish what is in progress, e.g. the reset sequence or a DMA
+        if !target.cpu().complete() {
+            target.step();
+        }
+        let ranges: Vec<(u16, u16)> = self
+            .watchpoints
+            .values()
+            .filter(|watchpoint| watchpoint.read || watchpoint.write)
+            .map(|watchpoint| (watchpoint.start, watchpoint.end))
+            .collect();
+        target.cpu_mut().bus.watch(ranges);
+        let mut conditions: BTreeMap<usize, bool> = self.condition_states(target.cpu());
+
+        let mut stop: StopReason = StopReason::Budget;
+        for _ in 0..self.budget {
+            let opcode: u8 = target
+                .cpu()
+                .bus
+                .peek(target.cpu().registers.pc)
+                .unwrap_or(0x00);
+            target.step();
+            let accesses: Vec<BusAccess> = target.cpu_mut().bus.take_accesses();
+            let cpu: &CPU = target.cpu();
+
+            if let Some(reason) = self.check_accesses(&accesses) {
+                stop = reason;
+                break;
+            }
+            if done(cpu, opcode) {
+                stop = StopReason::Done;
+                break;
+            }
+            if let Some(reason) = self.check_breakpoints(cpu, &mut conditions) {
+                stop = reason;
+                break;
+            }
+        }
+        target.cpu_mut().bus.watch(Vec::new());
+        return stop;
+    }
+
+    // Whether each condition only breakpoint holds, to catch it becoming true
+    fn condition_states(&self, cpu: &CPU) -> BTreeMap<usize, bool> {
+        return self
+            .breakpoints
+            .iter()
+            .filter(|(_, breakpoint)| breakpoint.address.is_none())
+            .map(|(id, breakpoint)| (*id, breakpoint.condition.map_or(false, |c| c.holds(cpu))))
+            .collect();
+    }
+
+    fn check_accesses(&self, accesses: &[BusAccess]) -> Option<StopReason> {
+        for access in accesses {
+            for (id, watchpoint) in &self.watchpoints {
+                if watchpoint.matches(access) {
+                    return Some(StopReason::Watchpoint(*id, Some(*access)));
+                }
+            }
+        }
+        return None;
+    }
+
+    // Breakpoints and execute watchpoints on the instruction about to run
+    fn check_breakpoints(
+        &self,
+        cpu: &CPU,
+        conditions: &mut BTreeMap<usize, bool>,
+    ) -> Option<StopReason> {
+        let pc: u16 = cpu.registers.pc;
+        let mut stop: Option<StopReason> = None;
+        for (id, breakpoint) in &self.breakpoints {
+            let holds: bool = breakpoint.condition.map_or(true, |c| c.holds(cpu));
+            let hit: bool = match breakpoint.address {
+                Some(address) => address == pc && holds,
+                None => {
+                    let held: bool = conditions.insert(*id, holds).unwrap_or(false);
+                    holds && !held
+                }
+            };
+            if hit && stop.is_none() {
+                stop = Some(StopReason::Breakpoint(*id));
+            }
+        }
+        if stop.is_some() {
+            return stop;
+        }
+        return self
+            .watchpoints
+            .iter()
+            .find(|(_, watchpoint)| {
+                watchpoint.execute && (watchpoint.start..=watchpoint.end).contains(&pc)
+            })
+            .map(|(id, _)| StopReason::Watchpoint(*id, None));
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use crate::cartridge::mappers::PRG_ROM_START;
+use crate::cpu::bus::{PPU_REGISTERS_START, TEST_MODE_END};
+use crate::cpu::cpu::CPU;
+use crate::debugger::debugger::{Breakpoint, Debugger, StopReason, Target, Watchpoint};
+use crate::LOGGER;
+
+// GDB remote serial protocol stub, so gdb (or lldb) frontends can attach to
+// the CPU:
+//
+//   (gdb) target remote localhost:6502
+//
+// Registers are numbered A, X, Y, PC, SP, P, described to the client by the
+// target.xml below. Memory is peeked and written through the bus, so reads
+// have no side effects on devices. Writes to $2000 -> $401F are refused, as
+// they would trigger the PPU, APU, DMA and controller registers, and so are
+// writes to $8000 -> $FFFF, which would reach mapper registers rather than
+// ROM. Software and hardware breakpoints map to debugger breakpoints and the
+// write, read and access watchpoints to debugger watchpoints. Only one client
+// is served and the target only runs while the client asks it to.
+//
+// Reference: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+static TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nes.m6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+  </feature>
+</target>"#;
+
+// Size in bytes of each register, in register number order
+static REGISTER_SIZES: [usize; 6] = [1, 1, 1, 2, 1, 1];
+
+static PACKET_SIZE: usize = 0x4000;
+
+// Instructions run between checks for an interrupt from the client
+static INTERRUPT_POLL_INSTRUCTIONS: u64 = 10_000;
+
+// Signals reported in stop replies
+static SIGINT: u8 = 2;
+static SIGTRAP: u8 = 5;
+
+static INTERRUPT: u8 = 0x03;
+
+// Stream to a client that can be checked for an interrupt (Ctrl-C) without
+// blocking while the target runs
+pub trait Connection: Read + Write {
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+// Non-blocking read of a single byte, dropping anything but the interrupt
+macro_rules! impl_connection {
+    ($stream:ty) => {
+        impl Connection for $stream {
+            fn interrupted(&mut self) -> io::Result<bool> {
+                self.set_nonblocking(true)?;
+                let mut byte: [u8; 1] = [0x00];
+                let result: io::Result<usize> = self.read(&mut byte);
+                self.set_nonblocking(false)?;
+                return match result {
+                    Ok(1) => Ok(byte[0] == INTERRUPT),
+                    Ok(_) => Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Client disconnected",
+                    )),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+                    Err(e) => Err(e),
+                };
+            }
+        }
+    };
+}
+
+impl_connection!(TcpStream);
+#[cfg(unix)]
+impl_connection!(UnixStream);
+
+// Wait for a client on a TCP address (e.g. "127.0.0.1:6502") and serve it
+// until it detaches
+pub fn serve_tcp<T: Target>(target: &mut T, address: &str) -> io::Result<()> {
+    let listener: TcpListener = TcpListener::bind(address)?;
+    info!(LOGGER, "Waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    info!(LOGGER, "gdb connected from {}", peer);
+    stream.set_nodelay(true)?;
+    return GdbStub::new().serve(target, stream);
+}
+
+// Same as serve_tcp over a Unix socket, which is created at path
+#[cfg(unix)]
+pub fn serve_unix<T: Target, P: AsRef<Path>>(target: &mut T, path: P) -> io::Result<()> {
+    let listener: UnixListener = UnixListener::bind(&path)?;
+    info!(LOGGER, "Waiting for gdb on {}", path.as_ref().display());
+    let (stream, _) = listener.accept()?;
+    info!(LOGGER, "gdb connected");
+    return GdbStub::new().serve(target, stream);
+}
+
+// Kinds of the Z and z packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BreakKind {
+    Software,
+    Hardware,
+    Write,
+    Read,
+    Access,
+}
+
+pub struct GdbStub {
+    pub debugger: Debugger,
+    breaks: HashMap<(BreakKind, u16, u16), usize>, // debugger id of each Z packet
+    ack: bool,                                     // until QStartNoAckMode
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        let mut debugger: Debugger = Debugger::new();
+        debugger.budget = INTERRUPT_POLL_INSTRUCTIONS;
+        return Self {
+            debugger,
+            breaks: HashMap::new(),
+            ack: true,
+        };
+    }
+
+    // Handle packets from a connected client until it detaches, kills the
+    // target or disconnects
+    pub fn serve<T: Target, C: Connection>(
+        &mut self,
+        target: &mut T,
+        mut connection: C,
+    ) -> io::Result<()> {
+        loop {
+            let packet: Vec<u8> = match self.receive(&mut connection)? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+            let packet: String = String::from_utf8_lossy(&packet).into_owned();
+            match packet.as_str() {
+                "D" => {
+                    self.send(&mut connection, "OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                _ => {}
+            }
+            let response: String = self.handle(target, &packet, &mut connection)?;
+            self.send(&mut connection, &response)?;
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+    }
+
+    // Read the next packet, None once the client disconnected. An interrupt
+    // while the target is stopped is answered straight away.
+    fn receive<C: Connection>(&mut self, connection: &mut C) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match read_byte(connection)? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(byte) if byte == INTERRUPT => {
+                    self.send(connection, &format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                Some(_) => continue, // acks
+            }
+
+            let mut data: Vec<u8> = Vec::new();
+            loop {
+                match read_byte(connection)? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum: [u8; 2] = [0x00; 2];
+            connection.read_exact(&mut checksum)?;
+            let valid: bool = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(checksum_of(&data));
+            if self.ack {
+                connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(unescape(&data)));
+            }
+        }
+    }
+
+    fn send<C: Connection>(&self, connection: &mut C, data: &str) -> io::Result<()> {
+        let escaped: Vec<u8> = escape(data.as_bytes());
+        let mut packet: Vec<u8> = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+        connection.write_all(&packet)?;
+        return connection.flush();
+    }
+
+    fn handle<T: Target, C: Connection>(
+        &mut self,
+        target: &mut T,
+        packet: &str,
+        connection: &mut C,
+    ) -> io::Result<String> {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+        let response: String = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => read_registers(target.cpu()),
+            "G" => ok_or_error(write_registers(target.cpu_mut(), arguments)),
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_SIZES.len() => {
+                    let offset: usize = REGISTER_SIZES[..register].iter().sum::<usize>() * 2;
+                    let size: usize = REGISTER_SIZES[register] * 2;
+                    read_registers(target.cpu())[offset..offset + size].to_string()
+                }
+                _ => String::from("E01"),
+            },
+            "P" => ok_or_error(write_register(target.cpu_mut(), arguments)),
+            "m" => match parse_range(arguments) {
+                Some((address, length)) => (0..length)
+                    .map(|offset| {
+                        let address: u16 = address.wrapping_add(offset);
+                        format!("{:02x}", target.cpu().bus.peek(address).unwrap_or(0x00))
+                    })
+                    .collect(),
+                None => String::from("E01"),
+            },
+            "M" => ok_or_error(write_memory(target.cpu_mut(), arguments)),
+            "Z" | "z" => self.breakpoint(command == "Z", arguments),
+            "c" | "s" => {
+                if let Some(address) = parse_hex(arguments) {
+                    target.cpu_mut().registers.pc = address;
+                }
+                let reason: StopReason = match command {
+                    "s" => self.debugger.step_into(target),
+                    _ => self.run(target, connection)?,
+                };
+                self.stop_reply(reason)
+            }
+            "H" => String::from("OK"),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(), // unsupported
+        };
+        return Ok(response);
+    }
+
+    // Continue until something stops the target or the client interrupts
+    fn run<T: Target, C: Connection>(
+        &mut self,
+        target: &mut T,
+        connection: &mut C,
+    ) -> io::Result<StopReason> {
+        loop {
+            let reason: StopReason = self.debugger.run(target);
+            if reason != StopReason::Budget {
+                return Ok(reason);
+            }
+            if connection.interrupted()? {
+                return Ok(StopReason::Budget);
+            }
+        }
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        return match reason {
+            StopReason::Budget => format!("S{:02x}", SIGINT),
+            StopReason::Watchpoint(id, Some(access)) => {
+                let kind: &str = match self.debugger.watchpoints().get(&id) {
+                    Some(watchpoint) if watchpoint.read && watchpoint.write => "awatch",
+                    Some(watchpoint) if watchpoint.read => "rwatch",
+                    _ => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, kind, access.address)
+            }
+            StopReason::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+            StopReason::Done | StopReason::Watchpoint(_, None) => format!("S{:02x}", SIGTRAP),
+        };
+    }
+
+    // Z<kind>,<addr>,<length> inserts and z<kind>,... removes
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let kind: BreakKind = match fields.next() {
+            Some("0") => BreakKind::Software,
+            Some("1") => BreakKind::Hardware,
+            Some("2") => BreakKind::Write,
+            Some("3") => BreakKind::Read,
+            Some("4") => BreakKind::Access,
+            _ => return String::new(),
+        };
+        let (address, length) = match (
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+        ) {
+            (Some(address), Some(length)) => (address, length.max(1)),
+            _ => return String::from("E01"),
+        };
+        let key: (BreakKind, u16, u16) = (kind, address, length);
+
+        if !insert {
+            if let Some(id) = self.breaks.remove(&key) {
+                self.debugger.remove(id);
+            }
+            return String::from("OK");
+        }
+        if self.breaks.contains_key(&key) {
+            return String::from("OK");
+        }
+        let id: usize = match kind {
+            BreakKind::Software | BreakKind::Hardware => {
+                self.debugger.add_breakpoint(Breakpoint::at(address))
+            }
+            _ => self.debugger.add_watchpoint(Watchpoint {
+                start: address,
+                end: address.saturating_add(length - 1),
+                read: kind != BreakKind::Write,
+                write: kind != BreakKind::Read,
+                execute: false,
+            }),
+        };
+        self.breaks.insert(key, id);
+        return String::from("OK");
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+",
+                PACKET_SIZE
+            );
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match range.split_once(',') {
+                Some((offset, length)) => (
+                    usize::from_str_radix(offset, 16).unwrap_or(0),
+                    usize::from_str_radix(length, 16).unwrap_or(0),
+                ),
+                None => return String::from("E01"),
+            };
+            let start: usize = offset.min(TARGET_XML.len());
+            let end: usize = (start + length).min(TARGET_XML.len());
+            let marker: char = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return format!("{}{}", marker, &TARGET_XML[start..end]);
+        }
+        return match packet {
+            "QStartNoAckMode" => String::from("OK"),
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        };
+    }
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+fn read_byte<C: Connection>(connection: &mut C) -> io::Result<Option<u8>> {
+    let mut byte: [u8; 1] = [0x00];
+    loop {
+        return match connection.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    return data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+}
+
+// Bytes that must be escaped as '}' followed by the byte XOR $20
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped: Vec<u8> = Vec::with_capacity(data.len());
+    for byte in data {
+        if let b'#' | b'$' | b'}' | b'*' = byte {
+            escaped.push(b'}');
+            escaped.push(byte ^ 0x20);
+        } else {
+            escaped.push(*byte);
+        }
+    }
+    return escaped;
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped: Vec<u8> = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => unescaped.push(*byte),
+        }
+    }
+    return unescaped;
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    return match result {
+        Some(()) => String::from("OK"),
+        None => String::from("E01"),
+    };
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    return u16::from_str_radix(text, 16).ok();
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+}
+
+// <addr>,<length>
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    return Some((parse_hex(address)?, parse_hex(length)?));
+}
+
+// Registers in order, multi byte ones little endian
+fn read_registers(cpu: &CPU) -> String {
+    let r = &cpu.registers;
+    let pc: [u8; 2] = r.pc.to_le_bytes();
+    return [r.a, r.x, r.y, pc[0], pc[1], r.sp, r.status]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+}
+
+fn write_registers(cpu: &mut CPU, hex: &str) -> Option<()> {
+    let bytes: Vec<u8> = parse_bytes(hex)?;
+    if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+        return None;
+    }
+    let r = &mut cpu.registers;
+    r.a = bytes[0];
+    r.x = bytes[1];
+    r.y = bytes[2];
+    r.pc = u16::from_le_bytes([bytes[3], bytes[4]]);
+    r.sp = bytes[5];
+    r.status = bytes[6];
+    return Some(());
+}
+
+// <register>=<value>
+fn write_register(cpu: &mut CPU, text: &str) -> Option<()> {
+    let (register, value) = text.split_once('=')?;
+    let bytes: Vec<u8> = parse_bytes(value)?;
+    let register: usize = usize::from_str_radix(register, 16).ok()?;
+    if REGISTER_SIZES.get(register) != Some(&bytes.len()) {
+        return None;
+    }
+    let r = &mut cpu.registers;
+    match register {
+        0 => r.a = bytes[0],
+        1 => r.x = bytes[0],
+        2 => r.y = bytes[0],
+        3 => r.pc = u16::from_le_bytes([bytes[0], bytes[1]]),
+        4 => r.sp = bytes[0],
+        _ => r.status = bytes[0],
+    }
+    return Some(());
+}
+
+// I/O registers and mapper registers, which a memory write from the client
+// should not poke
+fn has_write_side_effects(address: u16) -> bool {
+    return (PPU_REGISTERS_START..=TEST_MODE_END).contains(&address) || address >= PRG_ROM_START;
+}
+
+// <addr>,<length>:<bytes>
+fn write_memory(cpu: &mut CPU, text: &str) -> Option<()> {
+    let (range, data) = text.split_once(':')?;
+    let (address, length) = parse_range(range)?;
+    let bytes: Vec<u8> = parse_bytes(data)?;
+    if bytes.len() != length as usize {
+        return None;
+    }
+    if (0..length).any(|offset| has_write_side_effects(address.wrapping_add(offset))) {
+        return None;
+    }
+    for (offset, byte) in bytes.iter().enumerate() {
+        cpu.bus.write(address.wrapping_add(offset as u16), *byte);
+    }
+    return Some(());
+}
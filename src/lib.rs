@@ -0,0 +1,33 @@
+#![allow(clippy::needless_return)]
+// NES emulator core. Nes ties the whole console together, the components can
+// also be driven on their own.
+pub mod apu;
+pub mod cartridge;
+pub mod controller;
+pub mod cpu;
+pub mod debugger;
+pub mod logging;
+pub mod macros;
+pub mod movie;
+pub mod nes;
+pub mod ppu;
+pub mod savestate;
+
+#[macro_use]
+extern crate slog;
+extern crate lazy_static;
+extern crate slog_async;
+extern crate slog_json;
+extern crate slog_term;
+
+pub use apu::apu::APU;
+pub use cartridge::cartridge::Cartridge;
+pub use controller::controller::{Button, Controller};
+pub use cpu::bus::Bus;
+pub use cpu::cpu::CPU;
+pub use cpu::flags::StatusRegFlags;
+pub use cpu::registers::Registers;
+pub use debugger::debugger::Debugger;
+pub use logging::LOGGER;
+pub use nes::nes::Nes;
+pub use ppu::ppu::PPU;
@@ -0,0 +1,58 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use slog::{Drain, Duplicate, Fuse, Logger};
+use slog_async::{Async, AsyncGuard, OverflowStrategy};
+use slog_json::Json;
+use slog_term::{FullFormat, TermDecorator};
+
+// Log to the terminal and to a JSON file under logs/
+pub fn initialize_logging() -> slog::Logger {
+    let log_path: &str = "logs/";
+    let directory_creation_message: &str = match fs::create_dir(log_path) {
+        Ok(_) => "Created logging directory",
+        Err(_) => "Logging directory already exists, skipping",
+    };
+
+    let log_file_path: String = format!("{}{}{}", log_path, chrono::Utc::now(), ".log");
+    let file: File = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(log_file_path.as_str())
+        .unwrap();
+
+    let decorator: TermDecorator = TermDecorator::new().force_color().build();
+
+    type FuseFFTD = Fuse<FullFormat<TermDecorator>>;
+    type FuseJF = Fuse<Json<File>>;
+    type FuseMD = Fuse<Mutex<Duplicate<FuseFFTD, FuseJF>>>;
+
+    let d1: FuseFFTD = FullFormat::new(decorator).build().fuse();
+    let d2: FuseJF = Json::default(file).fuse();
+    let both: FuseMD = Mutex::new(Duplicate::new(d1, d2)).fuse();
+    let (both, guard): (Async, AsyncGuard) = Async::new(both)
+        .overflow_strategy(OverflowStrategy::Block)
+        .build_with_guard();
+    *LOGGER_GUARD.lock().unwrap() = Some(guard);
+    let both: Fuse<Async> = both.fuse();
+    let log: Logger = Logger::root(both, o!());
+
+    info!(log, "{}", directory_creation_message);
+    log
+}
+
+lazy_static! {
+    pub static ref LOGGER: Logger = initialize_logging();
+    static ref LOGGER_GUARD: Mutex<Option<AsyncGuard>> = Mutex::new(None);
+}
+
+// Wait for queued messages to be written out. Statics are never dropped, so
+// this must be called before exiting the process or the last messages are
+// lost. Logging afterwards panics.
+pub fn flush_logging() {
+    let guard: Option<AsyncGuard> = LOGGER_GUARD.lock().unwrap().take();
+    drop(guard);
+}
@@ -5,7 +5,7 @@ macro_rules! ternary {
             $v1
         } else {
             $v2
-        };
+        }
     };
 }
 
@@ -45,7 +45,7 @@ macro_rules! try_except_return {
         match $connection_statement {
             Ok(value) => value,
             Err(e) => {
-                error!(crate::LOGGER, "{}: {}", $msg, e);
+                error!($crate::LOGGER, "{}: {}", $msg, e);
                 return;
             }
         }
@@ -58,7 +58,7 @@ macro_rules! try_except_return_default {
         match $connection_statement {
             Ok(value) => value,
             Err(e) => {
-                error!(crate::LOGGER, "{}: {}", $msg, e);
+                error!($crate::LOGGER, "{}: {}", $msg, e);
                 $default_value
             }
         }
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::savestate::savestate::StateError;
+use crate::ternary;
+
+// Input movies in FCEUX's FM2 text format.
+//
+// A movie is a header of "key value" lines followed by one line per frame:
+//
+//   |commands|port0|port1|port2|
+//
+// commands is a decimal bit set (1 soft reset, 2 power cycle) applied
+// before the frame, and each gamepad port holds the buttons as RLDUTSBA,
+// with '.' or ' ' for a released button. An unplugged port is left empty.
+//
+// Movies start from power on, with battery backed RAM erased, or from the
+// save state in the savestate header. FCEUX states are not readable, so
+// only movies from power on can be imported from it. Two header keys are
+// our own, which FCEUX ignores: romHash, the hash of the cartridge ROM the
+// movie was recorded with, and frameHash, repeated, with a frame count and
+// the hash of the machine after that many frames, to detect desyncs.
+
+static VERSION: &str = "3";
+
+// Gamepad buttons in the order of an input field, highest bit first
+static GAMEPAD_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+pub static COMMAND_RESET: u8 = 0x01;
+pub static COMMAND_POWER: u8 = 0x02;
+
+// Commands FM2 defines for the Famicom Disk System and VS System, which this
+// emulator doesn't have
+static UNSUPPORTED_COMMANDS: &[(u8, &str)] = &[
+    (0x04, "the FDS insert disk command"),
+    (0x08, "the FDS select side command"),
+    (0x10, "the VS insert coin command"),
+];
+
+static BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Errors raised while loading or playing back a movie
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    Unsupported(String), // valid FM2 this emulator can't play back
+    State(StateError),
+    RomMismatch,
+    Desync {
+        frame: usize,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            MovieError::Io(e) => write!(f, "Unable to read movie file: {}", e),
+            MovieError::Parse { line, message } => write!(f, "Line {}: {}", line, message),
+            MovieError::Unsupported(what) => {
+                write!(f, "Movie uses {}, which is not supported", what)
+            }
+            MovieError::State(e) => write!(f, "Invalid movie save state: {}", e),
+            MovieError::RomMismatch => write!(f, "Movie was recorded with a different ROM"),
+            MovieError::Desync {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Movie desynced at frame {}: hash {:016x}, expected {:016x}",
+                frame, actual, expected
+            ),
+        };
+    }
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        return match self {
+            MovieError::Io(e) => Some(e),
+            MovieError::State(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        return MovieError::Io(e);
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        return MovieError::State(e);
+    }
+}
+
+// Input for a single frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Frame {
+    pub commands: u8,
+    pub buttons: [u8; 2], // as given to Controller::set_buttons
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    // Header lines that don't affect playback (emuVersion, romFilename,
+    // comment, ...), kept in order to be written back
+    pub header: Vec<(String, String)>,
+    pub gamepads: [bool; 2], // whether a gamepad is plugged into each port
+    pub savestate: Option<Vec<u8>>,
+    pub rom_hash: Option<u64>,
+    pub hashes: BTreeMap<usize, u64>, // machine hash by frames played
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        return Self {
+            header: Vec::new(),
+            gamepads: [true, true],
+            savestate: None,
+            rom_hash: None,
+            hashes: BTreeMap::new(),
+            frames: Vec::new(),
+        };
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        return Self::parse(&fs::read_to_string(path)?);
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        return fs::write(path, self.to_string());
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut movie: Self = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line: &str = line.trim_end_matches('\r');
+            let error = |message: &str| -> MovieError {
+                return parse_error(index + 1, message);
+            };
+
+            if line.starts_with('|') {
+                let frame: Frame = movie.parse_frame(line, index + 1)?;
+                movie.frames.push(frame);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.find(' ') {
+                Some(index) => (&line[..index], &line[index + 1..]),
+                None => (line, ""),
+            };
+            match key {
+                "version" if value != VERSION => {
+                    return Err(MovieError::Unsupported(format!("FM2 version {}", value)));
+                }
+                "version" => {}
+                "binary" | "fourscore" | "palFlag" | "port2" if value != "0" => {
+                    return Err(MovieError::Unsupported(format!("{} {}", key, value)));
+                }
+                "binary" | "fourscore" | "palFlag" | "port2" => {}
+                "port0" | "port1" => {
+                    let port: usize = ternary!(key == "port0", 0, 1);
+                    movie.gamepads[port] = match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(MovieError::Unsupported(format!("{} {}", key, value))),
+                    };
+                }
+                "savestate" => {
+                    let state: Vec<u8> =
+                        decode_binary(value).ok_or_else(|| error("Invalid savestate"))?;
+                    movie.savestate = Some(state);
+                }
+                "romHash" => {
+                    let hash: u64 =
+                        u64::from_str_radix(value, 16).map_err(|_| error("Invalid romHash"))?;
+                    movie.rom_hash = Some(hash);
+                }
+                "frameHash" => {
+                    let mut parts = value.split_whitespace();
+                    let frame: Option<usize> = parts.next().and_then(|frame| frame.parse().ok());
+                    let hash: Option<u64> = parts
+                        .next()
+                        .and_then(|hash| u64::from_str_radix(hash, 16).ok());
+                    match (frame, hash, parts.next()) {
+                        (Some(frame), Some(hash), None) => movie.hashes.insert(frame, hash),
+                        _ => return Err(error("Invalid frameHash")),
+                    };
+                }
+                _ => movie.header.push((String::from(key), String::from(value))),
+            }
+        }
+        return Ok(movie);
+    }
+
+    // Input line number, for the errors
+    fn parse_frame(&self, line: &str, number: usize) -> Result<Frame, MovieError> {
+        let error = |message: &str| -> MovieError {
+            return parse_error(number, message);
+        };
+
+        let fields: Vec<&str> = line.split('|').collect();
+        if fields.len() < 5 {
+            return Err(error("Input line needs a commands field and 3 ports"));
+        }
+
+        let commands: u8 = fields[1]
+            .trim()
+            .parse()
+            .map_err(|_| error("Invalid commands"))?;
+        for (command, what) in UNSUPPORTED_COMMANDS.iter() {
+            if commands & command != 0 {
+                return Err(MovieError::Unsupported(String::from(*what)));
+            }
+        }
+        if commands & !(COMMAND_RESET | COMMAND_POWER) != 0 {
+            return Err(error("Invalid commands"));
+        }
+        if !fields[4].is_empty() {
+            return Err(error("Input for the unplugged expansion port"));
+        }
+
+        let mut frame: Frame = Frame {
+            commands,
+            buttons: [0x00; 2],
+        };
+        for port in 0..2 {
+            let field: &str = fields[2 + port];
+            if !self.gamepads[port] {
+                if !field.is_empty() {
+                    return Err(error("Input for an unplugged port"));
+                }
+                continue;
+            }
+            if field.len() != GAMEPAD_BUTTONS.len() {
+                return Err(error("Gamepad input needs 8 buttons"));
+            }
+            for (i, button) in field.bytes().enumerate() {
+                if button != b'.' && button != b' ' {
+                    frame.buttons[port] |= 0x80 >> i;
+                }
+            }
+        }
+        return Ok(frame);
+    }
+}
+
+impl Default for Movie {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version {}", VERSION)?;
+        for (key, value) in self.header.iter() {
+            writeln!(f, "{} {}", key, value)?;
+        }
+        writeln!(f, "palFlag 0")?;
+        writeln!(f, "fourscore 0")?;
+        writeln!(f, "binary 0")?;
+        for port in 0..2 {
+            writeln!(f, "port{} {}", port, self.gamepads[port] as u8)?;
+        }
+        writeln!(f, "port2 0")?;
+        if let Some(hash) = self.rom_hash {
+            writeln!(f, "romHash {:016x}", hash)?;
+        }
+        for (frame, hash) in self.hashes.iter() {
+            writeln!(f, "frameHash {} {:016x}", frame, hash)?;
+        }
+        if let Some(state) = &self.savestate {
+            writeln!(f, "savestate base64:{}", encode_base64(state))?;
+        }
+
+        for frame in self.frames.iter() {
+            write!(f, "|{}|", frame.commands)?;
+            for port in 0..2 {
+                if self.gamepads[port] {
+                    for (i, button) in GAMEPAD_BUTTONS.iter().enumerate() {
+                        let pressed: bool = frame.buttons[port] & (0x80 >> i) != 0;
+                        write!(f, "{}", ternary!(pressed, *button as char, '.'))?;
+                    }
+                }
+                write!(f, "|")?;
+            }
+            writeln!(f, "|")?;
+        }
+        return Ok(());
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> MovieError {
+    return MovieError::Parse {
+        line,
+        message: String::from(message),
+    };
+}
+
+// Binary header values are base64 with a "base64:" prefix, or hex with "0x"
+fn decode_binary(value: &str) -> Option<Vec<u8>> {
+    if let Some(base64) = value.strip_prefix("base64:") {
+        return decode_base64(base64);
+    }
+    let hex: &str = value.strip_prefix("0x")?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut text: String = String::new();
+    for chunk in data.chunks(3) {
+        let bits: u32 = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, byte)| bits | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            let digit: char = BASE64[(bits >> (18 - 6 * i)) as usize & 0x3F] as char;
+            text.push(ternary!(i <= chunk.len(), digit, '='));
+        }
+    }
+    return text;
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text: &[u8] = text.trim_end_matches('=').as_bytes();
+    let mut data: Vec<u8> = Vec::new();
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits: u32 = 0;
+        for (i, digit) in chunk.iter().enumerate() {
+            let value: usize = BASE64.iter().position(|c| c == digit)?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            data.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    return Some(data);
+}
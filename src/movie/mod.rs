@@ -0,0 +1,2 @@
+pub mod fm2;
+pub mod playback;
@@ -0,0 +1,147 @@
+use crate::movie::fm2::{Frame, Movie, MovieError, COMMAND_POWER, COMMAND_RESET};
+use crate::nes::nes::Nes;
+use crate::savestate::savestate;
+
+// Frames between the hashes a recorder embeds in the movie
+pub static DEFAULT_HASH_INTERVAL: usize = 60;
+
+// Hash of what a movie can observe: the internal RAM and the picture. Save
+// states would be stricter but change with the state format.
+pub fn frame_hash(nes: &Nes) -> u64 {
+    let mut data: Vec<u8> = nes.cpu.bus.ram().to_vec();
+    data.extend_from_slice(&nes.framebuffer());
+    return savestate::hash(&data);
+}
+
+fn rom_hash(nes: &Nes) -> u64 {
+    return nes.cartridge().map_or(0, |cartridge| cartridge.hash());
+}
+
+// Run a frame with its input. Commands are handled before the frame.
+fn advance(nes: &mut Nes, frame: &Frame) {
+    if frame.commands & COMMAND_POWER != 0 {
+        nes.power_on();
+    } else if frame.commands & COMMAND_RESET != 0 {
+        nes.reset();
+    }
+    for (port, buttons) in frame.buttons.iter().enumerate() {
+        if let Some(mut controller) = nes.controller(port) {
+            controller.set_buttons(*buttons);
+        }
+    }
+    nes.run_frame();
+}
+
+// Records the input given to the console, one frame at a time
+pub struct Recorder {
+    pub hash_interval: usize, // 0 to embed no hashes
+    movie: Movie,
+}
+
+impl Recorder {
+    // Power cycle the console, with battery backed RAM erased, and record
+    // from there
+    pub fn from_power_on(nes: &mut Nes) -> Self {
+        nes.power_on_erased();
+        return Self::start(nes, None);
+    }
+
+    // Record from the console's current state
+    pub fn from_state(nes: &Nes) -> Self {
+        return Self::start(nes, Some(nes.save_state()));
+    }
+
+    fn start(nes: &Nes, savestate: Option<Vec<u8>>) -> Self {
+        let mut movie: Movie = Movie::new();
+        movie.savestate = savestate;
+        movie.rom_hash = Some(rom_hash(nes));
+        return Self {
+            hash_interval: DEFAULT_HASH_INTERVAL,
+            movie,
+        };
+    }
+
+    // Run a frame with the given input and record it
+    pub fn step(&mut self, nes: &mut Nes, frame: Frame) {
+        advance(nes, &frame);
+        self.movie.frames.push(frame);
+        let played: usize = self.movie.frames.len();
+        if self.hash_interval != 0 && played % self.hash_interval == 0 {
+            self.movie.hashes.insert(played, frame_hash(nes));
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        return &self.movie;
+    }
+
+    // The recorded movie, with a hash of the last frame so desyncs are
+    // caught up to the end
+    pub fn finish(mut self, nes: &Nes) -> Movie {
+        let played: usize = self.movie.frames.len();
+        if self.hash_interval != 0 && played != 0 {
+            self.movie.hashes.insert(played, frame_hash(nes));
+        }
+        return self.movie;
+    }
+}
+
+// Plays a movie back on a console, checking the embedded hashes
+pub struct Player {
+    movie: Movie,
+    frame: usize, // frames played
+}
+
+impl Player {
+    // Put the console in the movie's starting state
+    pub fn start(movie: Movie, nes: &mut Nes) -> Result<Self, MovieError> {
+        if movie.rom_hash.map_or(false, |hash| hash != rom_hash(nes)) {
+            return Err(MovieError::RomMismatch);
+        }
+        match &movie.savestate {
+            Some(state) => nes.load_state(state)?,
+            None => nes.power_on_erased(),
+        }
+        return Ok(Self { movie, frame: 0 });
+    }
+
+    pub fn movie(&self) -> &Movie {
+        return &self.movie;
+    }
+
+    pub fn frame(&self) -> usize {
+        return self.frame;
+    }
+
+    pub fn finished(&self) -> bool {
+        return self.frame >= self.movie.frames.len();
+    }
+
+    // Play the next frame. Returns false once the movie is over.
+    pub fn step(&mut self, nes: &mut Nes) -> Result<bool, MovieError> {
+        let frame: Frame = match self.movie.frames.get(self.frame) {
+            Some(frame) => *frame,
+            None => return Ok(false),
+        };
+        advance(nes, &frame);
+        self.frame += 1;
+
+        if let Some(expected) = self.movie.hashes.get(&self.frame) {
+            let actual: u64 = frame_hash(nes);
+            if actual != *expected {
+                return Err(MovieError::Desync {
+                    frame: self.frame,
+                    expected: *expected,
+                    actual,
+                });
+            }
+        }
+        return Ok(true);
+    }
+
+    // Play to the end of the movie
+    pub fn play(&mut self, nes: &mut Nes) -> Result<(), MovieError> {
+        while self.step(nes)? {}
+        return Ok(());
+    }
+}
@@ -0,0 +1,268 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::apu::apu::APU;
+use crate::cartridge::cartridge::Cartridge;
+use crate::cartridge::ines::RomError;
+use crate::controller::controller::{Controller, ControllerPorts, PORTS};
+use crate::cpu::bus::{self, Bus};
+use crate::cpu::cpu::CPU;
+use crate::cpu::trace::Tracer;
+use crate::ppu::ppu::PPU;
+use crate::savestate::savestate::{ParsedState, SaveState, StateError};
+
+pub static DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+// Save state chunks, see savestate
+static CPU_CHUNK: &str = "CPU ";
+static BUS_CHUNK: &str = "BUS ";
+
+// PPU dots per CPU cycle (NTSC)
+static DOTS_PER_CPU_CYCLE: u8 = 3;
+
+// The whole console. The CPU owns the bus, which has the PPU, APU,
+// controller ports and cartridge attached; the console keeps its own handles
+// to them to clock them, to route their interrupts to the CPU and to pass on
+// the host's input.
+pub struct Nes {
+    pub cpu: CPU,
+    ppu: Rc<RefCell<PPU>>,
+    apu: Rc<RefCell<APU>>,
+    controllers: Rc<RefCell<ControllerPorts>>,
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        let mut nes: Self = Self {
+            cpu: CPU::new(),
+            ppu: Rc::new(RefCell::new(PPU::new())),
+            apu: Rc::new(RefCell::new(APU::new(DEFAULT_SAMPLE_RATE))),
+            controllers: Rc::new(RefCell::new(ControllerPorts::new())),
+            cartridge: None,
+        };
+        nes.connect();
+        return nes;
+    }
+
+    // Wire up a fresh bus with the devices attached
+    fn connect(&mut self) {
+        let mut bus: Bus = Bus::new();
+        bus.attach(
+            bus::PPU_REGISTERS_START,
+            bus::PPU_REGISTERS_END,
+            Box::new(self.ppu.clone()),
+        );
+        // Ahead of the APU, to answer reads of $4017
+        bus.attach(
+            bus::JOYPAD1,
+            bus::JOYPAD2,
+            Box::new(self.controllers.clone()),
+        );
+        bus.attach(
+            bus::APU_IO_START,
+            bus::APU_IO_END,
+            Box::new(self.apu.clone()),
+        );
+        if let Some(cartridge) = &self.cartridge {
+            bus.insert_cartridge(cartridge.clone());
+            self.ppu.borrow_mut().insert_cartridge(cartridge.clone());
+        }
+        self.cpu.bus = bus;
+        self.connect_tracer();
+    }
+
+    // Let the tracer show the current PPU position
+    fn connect_tracer(&mut self) {
+        if let Some(tracer) = &mut self.cpu.tracer {
+            let ppu: Rc<RefCell<PPU>> = self.ppu.clone();
+            tracer.set_ppu_position(move || {
+                let ppu = ppu.borrow();
+                return (ppu.scanline(), ppu.dot());
+            });
+        }
+    }
+
+    // Trace every instruction, see cpu::trace. The tracer survives power
+    // cycles and ROM changes.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.set_tracer(tracer);
+        self.connect_tracer();
+    }
+
+    // Load an iNES file and power the console on with it
+    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RomError> {
+        let cartridge: Cartridge = Cartridge::load(path)?;
+        self.insert_cartridge(cartridge);
+        return Ok(());
+    }
+
+    // Swap in a cartridge and power the console on with it
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(Rc::new(RefCell::new(cartridge)));
+        self.power_on();
+    }
+
+    pub fn cartridge(&self) -> Option<Ref<'_, Cartridge>> {
+        return self.cartridge.as_ref().map(|cartridge| cartridge.borrow());
+    }
+
+    // Cold boot: RAM and every device start from their power up state, the
+    // mapper included. Cartridge RAM is kept, as it is battery backed on the
+    // boards with it.
+    pub fn power_on(&mut self) {
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().power_on();
+        }
+        let sample_rate: u32 = self.apu.borrow().sample_rate();
+        let tracer: Option<Tracer> = self.cpu.tracer.take();
+        self.cpu = CPU::new();
+        self.cpu.tracer = tracer;
+        self.ppu = Rc::new(RefCell::new(PPU::new()));
+        self.apu = Rc::new(RefCell::new(APU::new(sample_rate)));
+        self.controllers = Rc::new(RefCell::new(ControllerPorts::new()));
+        self.connect();
+        self.reset();
+    }
+
+    // Power on with the cartridge as freshly loaded, battery backed RAM
+    // erased, so runs from power on can be reproduced exactly
+    pub fn power_on_erased(&mut self) {
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().erase();
+        }
+        self.power_on();
+    }
+
+    // Reset button: memory is left as it is
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
+    }
+
+    // Advance the console by a single CPU cycle
+    pub fn clock(&mut self) {
+        self.cpu.clock();
+        self.apu.borrow_mut().clock();
+        self.cpu.service_dmc(&mut self.apu.borrow_mut());
+
+        for _ in 0..DOTS_PER_CPU_CYCLE {
+            self.ppu.borrow_mut().clock();
+        }
+
+        // The PPU raises edges, the APU and mappers hold the IRQ line
+        if self.ppu.borrow_mut().take_nmi() {
+            self.cpu.trigger_nmi();
+        }
+        let cartridge_irq: bool = match &self.cartridge {
+            Some(cartridge) => cartridge.borrow().irq(),
+            None => false,
+        };
+        let irq: bool = cartridge_irq || self.apu.borrow().irq();
+        self.cpu.set_irq_line(irq);
+    }
+
+    // Run until the next instruction boundary, returning the cycles taken
+    pub fn step_instruction(&mut self) -> u64 {
+        let start: u64 = self.cpu.cpu_cycles;
+        loop {
+            self.clock();
+            if self.cpu.complete() {
+                break;
+            }
+        }
+        return self.cpu.cpu_cycles - start;
+    }
+
+    // Run until the PPU moves to the next scanline
+    pub fn step_scanline(&mut self) {
+        let scanline: u16 = self.ppu.borrow().scanline();
+        while self.ppu.borrow().scanline() == scanline {
+            self.clock();
+        }
+    }
+
+    // Run until the PPU finishes a picture (at the start of vblank)
+    pub fn run_frame(&mut self) {
+        self.ppu.borrow_mut().take_frame_complete();
+        loop {
+            self.clock();
+            if self.ppu.borrow_mut().take_frame_complete() {
+                break;
+            }
+        }
+    }
+
+    // Snapshot of the whole machine (CPU, RAM, PPU, APU, controllers,
+    // cartridge RAM and mapper), which can be taken at any cycle
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state: SaveState = SaveState::new(self.rom_hash());
+        state.chunk(CPU_CHUNK, &self.cpu);
+        state.chunk(BUS_CHUNK, &self.cpu.bus);
+        return state.into_bytes();
+    }
+
+    // Restore a snapshot taken by save_state with the same ROM inserted.
+    // Emulation resumes exactly where it was saved. On error the machine
+    // is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let state: ParsedState = ParsedState::parse(data)?;
+        if state.rom_hash != self.rom_hash() {
+            return Err(StateError::RomMismatch);
+        }
+        let backup: Vec<u8> = self.save_state();
+        if let Err(e) = self.restore(&state) {
+            let backup: ParsedState = ParsedState::parse(&backup).expect("Invalid backup state");
+            self.restore(&backup)
+                .expect("Unable to restore the backup state");
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    fn restore(&mut self, state: &ParsedState) -> Result<(), StateError> {
+        state.restore(CPU_CHUNK, &mut self.cpu)?;
+        return state.restore(BUS_CHUNK, &mut self.cpu.bus);
+    }
+
+    fn rom_hash(&self) -> u64 {
+        return self.cartridge().map_or(0, |cartridge| cartridge.hash());
+    }
+
+    pub fn ppu(&self) -> Ref<'_, PPU> {
+        return self.ppu.borrow();
+    }
+
+    pub fn apu(&self) -> RefMut<'_, APU> {
+        return self.apu.borrow_mut();
+    }
+
+    // RGB framebuffer, see PPU::framebuffer
+    pub fn framebuffer(&self) -> Ref<'_, [u8]> {
+        return Ref::map(self.ppu.borrow(), |ppu| ppu.framebuffer());
+    }
+
+    // Move the pending audio samples into out, returning how many were
+    // written
+    pub fn audio_samples(&mut self, out: &mut [f32]) -> usize {
+        return self.apu.borrow_mut().samples().drain_into(out);
+    }
+
+    // Controller plugged into a port (0 or 1), None for any other port
+    pub fn controller(&mut self, port: usize) -> Option<RefMut<'_, Controller>> {
+        if port >= PORTS {
+            return None;
+        }
+        return Some(RefMut::map(self.controllers.borrow_mut(), |ports| {
+            ports.controller(port).expect("Port is in range")
+        }));
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
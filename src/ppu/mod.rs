@@ -0,0 +1,4 @@
+pub mod image;
+pub mod palette;
+#[allow(clippy::module_inception)]
+pub mod ppu;
@@ -0,0 +1,948 @@
+#![allow(clippy::upper_case_acronyms)]
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::cartridge::Cartridge;
+use crate::cartridge::ines::Mirroring;
+use crate::cpu::device::Device;
+use crate::ppu::palette::SYSTEM_PALETTE;
+use crate::savestate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::ternary;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// Frame timing (NTSC)
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+static VBLANK_SCANLINE: u16 = 241;
+static PRE_RENDER_SCANLINE: u16 = 261;
+
+// CPU visible registers, mirrored every 8 bytes through $3FFF
+const PPUCTRL: u16 = 0;
+const PPUMASK: u16 = 1;
+const PPUSTATUS: u16 = 2;
+const OAMADDR: u16 = 3;
+const OAMDATA: u16 = 4;
+const PPUSCROLL: u16 = 5;
+const PPUADDR: u16 = 6;
+const PPUDATA: u16 = 7;
+
+// PPUCTRL bits
+static CTRL_INCREMENT_32: u8 = 1 << 2;
+static CTRL_SPRITE_TABLE: u8 = 1 << 3;
+static CTRL_BACKGROUND_TABLE: u8 = 1 << 4;
+static CTRL_SPRITE_SIZE_16: u8 = 1 << 5;
+static CTRL_NMI_ENABLE: u8 = 1 << 7;
+
+// PPUMASK bits
+static MASK_GREYSCALE: u8 = 1 << 0;
+static MASK_BACKGROUND_LEFT: u8 = 1 << 1;
+static MASK_SPRITES_LEFT: u8 = 1 << 2;
+static MASK_BACKGROUND: u8 = 1 << 3;
+static MASK_SPRITES: u8 = 1 << 4;
+
+// PPUSTATUS bits
+static STATUS_SPRITE_OVERFLOW: u8 = 1 << 5;
+static STATUS_SPRITE_ZERO_HIT: u8 = 1 << 6;
+static STATUS_VBLANK: u8 = 1 << 7;
+
+// Sprite attribute bits
+static SPRITE_PALETTE: u8 = 0x03;
+static SPRITE_BEHIND_BACKGROUND: u8 = 1 << 5;
+static SPRITE_FLIP_HORIZONTAL: u8 = 1 << 6;
+static SPRITE_FLIP_VERTICAL: u8 = 1 << 7;
+
+static MAX_SPRITES_PER_LINE: usize = 8;
+
+// Picture processing unit (2C02), stepped one dot at a time.
+//
+// Scrolling uses the "loopy" internal registers:
+//   v: current VRAM address   yyy NN YYYYY XXXXX
+//   t: temporary VRAM address (same layout)
+//   x: fine X scroll (3 bits)
+//   w: first/second write toggle shared by PPUSCROLL and PPUADDR
+// where yyy is fine Y, NN the nametable, YYYYY coarse Y and XXXXX coarse X.
+//
+// Reference: https://www.nesdev.org/wiki/PPU_rendering
+pub struct PPU {
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
+
+    // Memory
+    vram: [u8; 0x1000], // nametables, large enough for four screen boards
+    palette: [u8; 32],
+    oam: [u8; 256],
+    secondary_oam: [u8; 32],
+
+    // Registers
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    data_buffer: u8, // PPUDATA read buffer
+    io_latch: u8,    // value left on the CPU data lines by the last access
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+
+    // Timing
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    odd_frame: bool,
+
+    // Background pipeline
+    bg_next_tile_id: u8,
+    bg_next_tile_attr: u8,
+    bg_next_tile_lsb: u8,
+    bg_next_tile_msb: u8,
+    bg_shifter_pattern_lo: u16,
+    bg_shifter_pattern_hi: u16,
+    bg_shifter_attr_lo: u16,
+    bg_shifter_attr_hi: u16,
+
+    // Sprites for the line being drawn
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+    sprite_pattern_lo: [u8; 8],
+    sprite_pattern_hi: [u8; 8],
+    sprite_attr: [u8; 8],
+    sprite_x: [u8; 8],
+
+    // Output
+    framebuffer: Vec<u8>, // RGB, 3 bytes per pixel
+    nmi: bool,            // NMI raised, waiting to be taken by the CPU
+    frame_complete: bool,
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        Self {
+            cartridge: None,
+            vram: [0x00; 0x1000],
+            palette: [0x00; 32],
+            oam: [0x00; 256],
+            secondary_oam: [0xFF; 32],
+            ctrl: 0x00,
+            mask: 0x00,
+            status: 0x00,
+            oam_addr: 0x00,
+            data_buffer: 0x00,
+            io_latch: 0x00,
+            v: 0x0000,
+            t: 0x0000,
+            x: 0x00,
+            w: false,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            bg_next_tile_id: 0x00,
+            bg_next_tile_attr: 0x00,
+            bg_next_tile_lsb: 0x00,
+            bg_next_tile_msb: 0x00,
+            bg_shifter_pattern_lo: 0x0000,
+            bg_shifter_pattern_hi: 0x0000,
+            bg_shifter_attr_lo: 0x0000,
+            bg_shifter_attr_hi: 0x0000,
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            sprite_pattern_lo: [0x00; 8],
+            sprite_pattern_hi: [0x00; 8],
+            sprite_attr: [0x00; 8],
+            sprite_x: [0x00; 8],
+            framebuffer: vec![0x00; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            nmi: false,
+            frame_complete: false,
+        }
+    }
+
+    // Connect the cartridge providing the pattern tables and mirroring
+    pub fn insert_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
+        self.cartridge = Some(cartridge);
+    }
+
+    // Reset leaves memory intact but clears the registers and timing
+    pub fn reset(&mut self) {
+        self.ctrl = 0x00;
+        self.mask = 0x00;
+        self.status = 0x00;
+        self.data_buffer = 0x00;
+        self.w = false;
+        self.t = 0x0000;
+        self.x = 0x00;
+        self.scanline = 0;
+        self.dot = 0;
+        self.odd_frame = false;
+        self.nmi = false;
+        self.frame_complete = false;
+    }
+
+    pub fn scanline(&self) -> u16 {
+        return self.scanline;
+    }
+
+    pub fn dot(&self) -> u16 {
+        return self.dot;
+    }
+
+    pub fn frame(&self) -> u64 {
+        return self.frame;
+    }
+
+    // RGB framebuffer of SCREEN_WIDTH * SCREEN_HEIGHT pixels
+    pub fn framebuffer(&self) -> &[u8] {
+        return &self.framebuffer;
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        return &self.oam;
+    }
+
+    // Write a byte straight into OAM at OAMADDR (used by OAM DMA)
+    pub fn write_oam(&mut self, data: u8) {
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    // Whether an NMI was raised since the last call. The system forwards it
+    // to CPU::trigger_nmi.
+    pub fn take_nmi(&mut self) -> bool {
+        let nmi: bool = self.nmi;
+        self.nmi = false;
+        return nmi;
+    }
+
+    // Whether a full picture was produced (vblank started) since the last call
+    pub fn take_frame_complete(&mut self) -> bool {
+        let complete: bool = self.frame_complete;
+        self.frame_complete = false;
+        return complete;
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        return self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0;
+    }
+
+    fn sprite_height(&self) -> u16 {
+        return ternary!(self.ctrl & CTRL_SPRITE_SIZE_16 != 0, 16, 8);
+    }
+
+    /*
+
+    PPU BUS
+
+    $0000 -> $1FFF  Pattern tables (cartridge CHR)
+    $2000 -> $2FFF  Nametables, mirrored through $3EFF
+    $3F00 -> $3F1F  Palette RAM, mirrored through $3FFF
+
+    */
+
+    // Index into VRAM for a nametable address according to the mirroring
+    fn nametable_index(&self, address: u16) -> usize {
+        let mirroring: Mirroring = match &self.cartridge {
+            Some(cartridge) => cartridge.borrow().mirroring(),
+            None => Mirroring::Horizontal,
+        };
+        let address: u16 = address & 0x0FFF;
+        let table: u16 = address / 0x0400;
+        let offset: u16 = address & 0x03FF;
+        let physical: u16 = match mirroring {
+            Mirroring::Vertical => table & 0x01,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        return (physical * 0x0400 + offset) as usize;
+    }
+
+    // Palette RAM index, the backdrop entries of the sprite palettes
+    // ($3F10/$3F14/$3F18/$3F1C) mirror those of the background palettes
+    fn palette_index(address: u16) -> usize {
+        let index: u16 = address & 0x001F;
+        return ternary!(index & 0x13 == 0x10, index & 0x0F, index) as usize;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let address: u16 = address & 0x3FFF;
+        return match address {
+            0x0000..=0x1FFF => match &self.cartridge {
+                Some(cartridge) => cartridge.borrow_mut().ppu_read(address),
+                None => 0x00,
+            },
+            0x2000..=0x3EFF => self.vram[self.nametable_index(address)],
+            _ => {
+                let colour: u8 = self.palette[Self::palette_index(address)];
+                ternary!(
+                    self.mask & MASK_GREYSCALE != 0,
+                    colour & 0x30,
+                    colour & 0x3F
+                )
+            }
+        };
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        let address: u16 = address & 0x3FFF;
+        return match address {
+            0x0000..=0x1FFF => match &self.cartridge {
+                Some(cartridge) => cartridge.borrow().ppu_peek(address),
+                None => 0x00,
+            },
+            0x2000..=0x3EFF => self.vram[self.nametable_index(address)],
+            _ => self.palette[Self::palette_index(address)],
+        };
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let address: u16 = address & 0x3FFF;
+        match address {
+            0x0000..=0x1FFF => {
+                if let Some(cartridge) = &self.cartridge {
+                    cartridge.borrow_mut().ppu_write(address, data);
+                }
+            }
+            0x2000..=0x3EFF => {
+                let index: usize = self.nametable_index(address);
+                self.vram[index] = data;
+            }
+            _ => self.palette[Self::palette_index(address)] = data,
+        }
+    }
+
+    /*
+
+    TIMING AND RENDERING
+
+    */
+
+    // Advance the PPU by a single dot
+    pub fn clock(&mut self) {
+        let visible: bool = self.scanline < SCREEN_HEIGHT as u16;
+        let pre_render: bool = self.scanline == PRE_RENDER_SCANLINE;
+
+        if pre_render && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW);
+        }
+
+        if (visible || pre_render) && self.rendering_enabled() {
+            self.render_cycle(pre_render);
+        }
+
+        if visible && (1..=SCREEN_WIDTH as u16).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.status |= STATUS_VBLANK;
+            self.frame_complete = true;
+            if self.ctrl & CTRL_NMI_ENABLE != 0 {
+                self.nmi = true;
+            }
+        }
+
+        self.advance();
+    }
+
+    // Move to the next dot. The last dot of the pre-render line is skipped
+    // on odd frames while rendering.
+    fn advance(&mut self) {
+        self.dot += 1;
+        if self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.odd_frame
+            && self.rendering_enabled()
+        {
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline >= SCANLINES_PER_FRAME {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    // Memory fetches and scroll updates on the visible and pre-render lines
+    fn render_cycle(&mut self, pre_render: bool) {
+        let dot: u16 = self.dot;
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.bg_next_tile_id = self.ppu_read(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => {
+                    let address: u16 = 0x23C0
+                        | (self.v & 0x0C00)
+                        | ((self.v >> 4) & 0x38)
+                        | ((self.v >> 2) & 0x07);
+                    let mut attr: u8 = self.ppu_read(address);
+                    // Pick the 2 bits for the quadrant of the 32x32 block
+                    if self.coarse_y() & 0x02 != 0 {
+                        attr >>= 4;
+                    }
+                    if self.coarse_x() & 0x02 != 0 {
+                        attr >>= 2;
+                    }
+                    self.bg_next_tile_attr = attr & 0x03;
+                }
+                4 => {
+                    let address: u16 = self.background_pattern_address();
+                    self.bg_next_tile_lsb = self.ppu_read(address);
+                }
+                6 => {
+                    let address: u16 = self.background_pattern_address() + 8;
+                    self.bg_next_tile_msb = self.ppu_read(address);
+                }
+                7 => self.increment_scroll_x(),
+                _ => {}
+            }
+        }
+
+        if dot == 256 {
+            self.increment_scroll_y();
+        }
+        if dot == 257 {
+            self.load_background_shifters();
+            self.transfer_address_x();
+            if pre_render {
+                // No sprites are drawn on the first visible line
+                self.secondary_oam = [0xFF; 32];
+                self.sprite_count = 0;
+                self.sprite_zero_on_line = false;
+            } else {
+                self.evaluate_sprites();
+            }
+        }
+        if pre_render && (280..=304).contains(&dot) {
+            self.transfer_address_y();
+        }
+
+        // Sprite pattern fetches for the next line, one sprite per 8 dots
+        if (257..=320).contains(&dot) {
+            self.oam_addr = 0x00;
+            let slot: usize = ((dot - 257) / 8) as usize;
+            match (dot - 257) % 8 {
+                4 => {
+                    let address: u16 = self.sprite_pattern_address(slot);
+                    self.sprite_pattern_lo[slot] = self.fetch_sprite_row(slot, address);
+                }
+                6 => {
+                    let address: u16 = self.sprite_pattern_address(slot) + 8;
+                    self.sprite_pattern_hi[slot] = self.fetch_sprite_row(slot, address);
+                }
+                _ => {}
+            }
+        }
+
+        // Unused nametable fetches at the end of the line
+        if dot == 338 || dot == 340 {
+            self.bg_next_tile_id = self.ppu_read(0x2000 | (self.v & 0x0FFF));
+        }
+    }
+
+    fn coarse_x(&self) -> u16 {
+        return self.v & 0x001F;
+    }
+
+    fn coarse_y(&self) -> u16 {
+        return (self.v >> 5) & 0x001F;
+    }
+
+    fn fine_y(&self) -> u16 {
+        return (self.v >> 12) & 0x0007;
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let table: u16 = ternary!(self.ctrl & CTRL_BACKGROUND_TABLE != 0, 0x1000, 0x0000);
+        return table + ((self.bg_next_tile_id as u16) << 4) + self.fine_y();
+    }
+
+    // Move to the next tile, wrapping into the horizontally adjacent nametable
+    fn increment_scroll_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // Move to the next pixel row, wrapping into the vertically adjacent
+    // nametable after row 29 (rows 30 and 31 hold attributes)
+    fn increment_scroll_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y: u16 = self.coarse_y();
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    // Copy coarse X and the horizontal nametable bit from t to v
+    fn transfer_address_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    // Copy fine Y, coarse Y and the vertical nametable bit from t to v
+    fn transfer_address_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    // Put the next tile into the low byte of the background shifters
+    fn load_background_shifters(&mut self) {
+        self.bg_shifter_pattern_lo =
+            (self.bg_shifter_pattern_lo & 0xFF00) | self.bg_next_tile_lsb as u16;
+        self.bg_shifter_pattern_hi =
+            (self.bg_shifter_pattern_hi & 0xFF00) | self.bg_next_tile_msb as u16;
+        self.bg_shifter_attr_lo = (self.bg_shifter_attr_lo & 0xFF00)
+            | ternary!(self.bg_next_tile_attr & 0x01 != 0, 0x00FF, 0x0000);
+        self.bg_shifter_attr_hi = (self.bg_shifter_attr_hi & 0xFF00)
+            | ternary!(self.bg_next_tile_attr & 0x02 != 0, 0x00FF, 0x0000);
+    }
+
+    fn shift_background(&mut self) {
+        if self.mask & MASK_BACKGROUND != 0 {
+            self.bg_shifter_pattern_lo <<= 1;
+            self.bg_shifter_pattern_hi <<= 1;
+            self.bg_shifter_attr_lo <<= 1;
+            self.bg_shifter_attr_hi <<= 1;
+        }
+    }
+
+    // Find the sprites for the next line and copy them into secondary OAM.
+    // Once 8 sprites are found the search continues for the overflow flag,
+    // reproducing the hardware bug which also increments the byte offset.
+    fn evaluate_sprites(&mut self) {
+        self.secondary_oam = [0xFF; 32];
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+
+        let scanline: i32 = self.scanline as i32;
+        let height: i32 = self.sprite_height() as i32;
+        let in_range = |y: u8| -> bool {
+            let row: i32 = scanline - y as i32;
+            return row >= 0 && row < height;
+        };
+
+        let mut n: usize = 0;
+        while n < 64 && self.sprite_count < MAX_SPRITES_PER_LINE {
+            if in_range(self.oam[n * 4]) {
+                let slot: usize = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+                if n == 0 {
+                    self.sprite_zero_on_line = true;
+                }
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+
+        let mut m: usize = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0x03;
+        }
+    }
+
+    // Pattern address of a row of the sprite in a secondary OAM slot. Empty
+    // slots fetch tile $FF, which keeps the A12 pattern seen by mappers intact.
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let sprite: &[u8] = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attr) = (sprite[0], sprite[1] as u16, sprite[2]);
+        let mut row: u16 = ternary!(
+            slot < self.sprite_count,
+            (self.scanline.wrapping_sub(y as u16)) & 0x0F,
+            0
+        );
+        if attr & SPRITE_FLIP_VERTICAL != 0 && slot < self.sprite_count {
+            row = self.sprite_height() - 1 - row;
+        }
+
+        if self.ctrl & CTRL_SPRITE_SIZE_16 == 0 {
+            let table: u16 = ternary!(self.ctrl & CTRL_SPRITE_TABLE != 0, 0x1000, 0x0000);
+            return table | (tile << 4) | (row & 0x07);
+        }
+        // 8x16 sprites take the table from bit 0 of the tile number
+        let table: u16 = (tile & 0x01) * 0x1000;
+        let tile: u16 = (tile & 0xFE) + ternary!(row >= 8, 1, 0);
+        return table | (tile << 4) | (row & 0x07);
+    }
+
+    // Fetch one pattern plane of a sprite row, flipping it horizontally if
+    // needed. Empty slots are fetched but come out transparent.
+    fn fetch_sprite_row(&mut self, slot: usize, address: u16) -> u8 {
+        let data: u8 = self.ppu_read(address);
+        if slot >= self.sprite_count {
+            return 0x00;
+        }
+        self.sprite_attr[slot] = self.secondary_oam[slot * 4 + 2];
+        self.sprite_x[slot] = self.secondary_oam[slot * 4 + 3];
+        return ternary!(
+            self.secondary_oam[slot * 4 + 2] & SPRITE_FLIP_HORIZONTAL != 0,
+            data.reverse_bits(),
+            data
+        );
+    }
+
+    // Combine the background and sprite pixels for the current dot
+    fn output_pixel(&mut self) {
+        let x: usize = (self.dot - 1) as usize;
+        let y: usize = self.scanline as usize;
+
+        // Background
+        let mut bg_pixel: u8 = 0x00;
+        let mut bg_palette: u8 = 0x00;
+        if self.mask & MASK_BACKGROUND != 0 && (x >= 8 || self.mask & MASK_BACKGROUND_LEFT != 0) {
+            let bit: u16 = 0x8000 >> self.x;
+            bg_pixel = ((self.bg_shifter_pattern_hi & bit != 0) as u8) << 1
+                | (self.bg_shifter_pattern_lo & bit != 0) as u8;
+            bg_palette = ((self.bg_shifter_attr_hi & bit != 0) as u8) << 1
+                | (self.bg_shifter_attr_lo & bit != 0) as u8;
+        }
+
+        // Sprites, the first opaque one in OAM order wins
+        let mut fg_pixel: u8 = 0x00;
+        let mut fg_palette: u8 = 0x00;
+        let mut fg_behind: bool = false;
+        let mut sprite_zero_rendered: bool = false;
+        if self.mask & MASK_SPRITES != 0 && (x >= 8 || self.mask & MASK_SPRITES_LEFT != 0) {
+            for slot in 0..self.sprite_count {
+                if self.sprite_x[slot] != 0 {
+                    continue;
+                }
+                let pixel: u8 = ((self.sprite_pattern_hi[slot] & 0x80 != 0) as u8) << 1
+                    | (self.sprite_pattern_lo[slot] & 0x80 != 0) as u8;
+                if pixel != 0 {
+                    fg_pixel = pixel;
+                    fg_palette = (self.sprite_attr[slot] & SPRITE_PALETTE) + 4;
+                    fg_behind = self.sprite_attr[slot] & SPRITE_BEHIND_BACKGROUND != 0;
+                    sprite_zero_rendered = slot == 0 && self.sprite_zero_on_line;
+                    break;
+                }
+            }
+        }
+        if self.mask & MASK_SPRITES != 0 {
+            self.shift_sprites();
+        }
+
+        let (pixel, palette) = match (bg_pixel, fg_pixel) {
+            (0, 0) => (0x00, 0x00),
+            (0, _) => (fg_pixel, fg_palette),
+            (_, 0) => (bg_pixel, bg_palette),
+            _ => {
+                if sprite_zero_rendered && x != 255 {
+                    self.status |= STATUS_SPRITE_ZERO_HIT;
+                }
+                ternary!(fg_behind, (bg_pixel, bg_palette), (fg_pixel, fg_palette))
+            }
+        };
+
+        let colour: u8 = self.ppu_read(0x3F00 + ((palette as u16) << 2) + pixel as u16);
+        let (r, g, b) = SYSTEM_PALETTE[(colour & 0x3F) as usize];
+        let offset: usize = (y * SCREEN_WIDTH + x) * 3;
+        self.framebuffer[offset] = r;
+        self.framebuffer[offset + 1] = g;
+        self.framebuffer[offset + 2] = b;
+    }
+
+    // Count down sprite X positions, shifting out sprites once reached
+    fn shift_sprites(&mut self) {
+        for slot in 0..self.sprite_count {
+            if self.sprite_x[slot] > 0 {
+                self.sprite_x[slot] -= 1;
+            } else {
+                self.sprite_pattern_lo[slot] <<= 1;
+                self.sprite_pattern_hi[slot] <<= 1;
+            }
+        }
+    }
+
+    fn increment_address(&mut self) {
+        let increment: u16 = ternary!(self.ctrl & CTRL_INCREMENT_32 != 0, 32, 1);
+        self.v = self.v.wrapping_add(increment) & 0x7FFF;
+    }
+}
+
+// CPU side registers at $2000 -> $2007 (mirrored through $3FFF)
+impl Device for PPU {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x0007 {
+            PPUSTATUS => {
+                // Only the top 3 bits are driven, reading clears vblank and w
+                self.io_latch = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+            }
+            OAMDATA => self.io_latch = self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                // Reads are delayed by a buffer, except for palette RAM which
+                // is returned immediately (the buffer gets the nametable byte
+                // "underneath" it instead)
+                let address: u16 = self.v & 0x3FFF;
+                let data: u8 = self.ppu_read(address);
+                if address >= 0x3F00 {
+                    self.io_latch = (data & 0x3F) | (self.io_latch & 0xC0);
+                    self.data_buffer = self.ppu_read(address - 0x1000);
+                } else {
+                    self.io_latch = self.data_buffer;
+                    self.data_buffer = data;
+                }
+                self.increment_address();
+            }
+            // Write only registers return whatever is left on the latch
+            _ => {}
+        }
+        return self.io_latch;
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.io_latch = data;
+        match address & 0x0007 {
+            PPUCTRL => {
+                // Enabling NMI during vblank raises one immediately
+                if self.ctrl & CTRL_NMI_ENABLE == 0
+                    && data & CTRL_NMI_ENABLE != 0
+                    && self.status & STATUS_VBLANK != 0
+                {
+                    self.nmi = true;
+                }
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | (((data & 0x03) as u16) << 10);
+            }
+            PPUMASK => self.mask = data,
+            OAMADDR => self.oam_addr = data,
+            OAMDATA => self.write_oam(data),
+            PPUSCROLL => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (data >> 3) as u16;
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | (((data & 0x07) as u16) << 12)
+                        | (((data >> 3) as u16) << 5);
+                }
+                self.w = !self.w;
+            }
+            PPUADDR => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | (((data & 0x3F) as u16) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            PPUDATA => {
+                self.ppu_write(self.v, data);
+                self.increment_address();
+            }
+            _ => {}
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return Some(match address & 0x0007 {
+            PPUSTATUS => (self.status & 0xE0) | (self.io_latch & 0x1F),
+            OAMDATA => self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                let address: u16 = self.v & 0x3FFF;
+                ternary!(address >= 0x3F00, self.ppu_peek(address), self.data_buffer)
+            }
+            _ => self.io_latch,
+        });
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.load(state);
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// Everything but the cartridge, which is saved by the bus
+impl Snapshot for PPU {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_addr);
+        state.u8(self.data_buffer);
+        state.u8(self.io_latch);
+        state.u16(self.v);
+        state.u16(self.t);
+        state.u8(self.x);
+        state.bool(self.w);
+        state.u16(self.scanline);
+        state.u16(self.dot);
+        state.u64(self.frame);
+        state.bool(self.odd_frame);
+        state.u8(self.bg_next_tile_id);
+        state.u8(self.bg_next_tile_attr);
+        state.u8(self.bg_next_tile_lsb);
+        state.u8(self.bg_next_tile_msb);
+        state.u16(self.bg_shifter_pattern_lo);
+        state.u16(self.bg_shifter_pattern_hi);
+        state.u16(self.bg_shifter_attr_lo);
+        state.u16(self.bg_shifter_attr_hi);
+        state.bytes(&self.vram);
+        state.bytes(&self.palette);
+        state.bytes(&self.oam);
+        state.bytes(&self.secondary_oam);
+        state.u8(self.sprite_count as u8);
+        state.bool(self.sprite_zero_on_line);
+        state.bytes(&self.sprite_pattern_lo);
+        state.bytes(&self.sprite_pattern_hi);
+        state.bytes(&self.sprite_attr);
+        state.bytes(&self.sprite_x);
+        // The picture drawn so far, for states saved mid-frame
+        state.bytes(&self.framebuffer);
+        state.bool(self.nmi);
+        state.bool(self.frame_complete);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+        self.data_buffer = state.u8()?;
+        self.io_latch = state.u8()?;
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.x = state.u8()?;
+        self.w = state.bool()?;
+        self.scanline = state.u16()?;
+        self.dot = state.u16()?;
+        self.frame = state.u64()?;
+        self.odd_frame = state.bool()?;
+        self.bg_next_tile_id = state.u8()?;
+        self.bg_next_tile_attr = state.u8()?;
+        self.bg_next_tile_lsb = state.u8()?;
+        self.bg_next_tile_msb = state.u8()?;
+        self.bg_shifter_pattern_lo = state.u16()?;
+        self.bg_shifter_pattern_hi = state.u16()?;
+        self.bg_shifter_attr_lo = state.u16()?;
+        self.bg_shifter_attr_hi = state.u16()?;
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.palette)?;
+        state.bytes(&mut self.oam)?;
+        state.bytes(&mut self.secondary_oam)?;
+        self.sprite_count = (state.u8()? as usize).min(MAX_SPRITES_PER_LINE);
+        self.sprite_zero_on_line = state.bool()?;
+        state.bytes(&mut self.sprite_pattern_lo)?;
+        state.bytes(&mut self.sprite_pattern_hi)?;
+        state.bytes(&mut self.sprite_attr)?;
+        state.bytes(&mut self.sprite_x)?;
+        state.bytes(&mut self.framebuffer)?;
+        self.nmi = state.bool()?;
+        self.frame_complete = state.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v, t, x and w
+    fn loopy(ppu: &PPU) -> (u16, u16, u8, bool) {
+        return (ppu.v, ppu.t, ppu.x, ppu.w);
+    }
+
+    fn run_to_vblank(ppu: &mut PPU) {
+        while ppu.status & STATUS_VBLANK == 0 {
+            ppu.clock();
+        }
+    }
+
+    #[test]
+    fn status_reads_clear_vblank_and_the_write_toggle() {
+        let mut ppu: PPU = PPU::new();
+        run_to_vblank(&mut ppu);
+
+        // Only the top 3 bits are driven, the rest is the last value written
+        ppu.write(0x2003, 0x1F);
+        assert_eq!(ppu.read(0x2002), 0x80 | 0x1F);
+        assert_eq!(ppu.read(0x2002), 0x1F);
+        // Also through the mirrors
+        run_to_vblank(&mut ppu);
+        assert_eq!(ppu.read(0x3FFA) & 0x80, 0x80);
+        assert_eq!(ppu.read(0x200A) & 0x80, 0x00);
+
+        // A read between the two writes to $2006 starts over from the first
+        ppu.write(0x2006, 0x21);
+        assert!(ppu.w);
+        ppu.read(0x2002);
+        assert!(!ppu.w);
+        ppu.write(0x2006, 0x23);
+        ppu.write(0x2006, 0x45);
+        assert_eq!(loopy(&ppu), (0x2345, 0x2345, 0, false));
+
+        // And for $2005
+        ppu.write(0x2005, 0xFF);
+        ppu.read(0x2002);
+        ppu.write(0x2005, 0x08);
+        assert_eq!(loopy(&ppu), (0x2345, 0x2341, 0, true));
+    }
+
+    #[test]
+    fn scroll_and_address_writes_update_the_loopy_registers() {
+        let mut ppu: PPU = PPU::new();
+
+        // Nametable select goes to t
+        ppu.write(0x2000, 0x03);
+        assert_eq!(loopy(&ppu), (0x0000, 0x0C00, 0, false));
+
+        // First write: coarse X and fine X
+        ppu.write(0x2005, 0x7D);
+        assert_eq!(loopy(&ppu), (0x0000, 0x0C0F, 5, true));
+
+        // Second write: coarse Y and fine Y
+        ppu.write(0x2005, 0x5E);
+        assert_eq!(loopy(&ppu), (0x0000, 0x6D6F, 5, false));
+
+        // PPUADDR's first write sets the high byte of t and clears bit 14,
+        // the second sets the low byte and copies t to v
+        ppu.write(0x2006, 0x3D);
+        assert_eq!(loopy(&ppu), (0x0000, 0x3D6F, 5, true));
+        ppu.write(0x2006, 0xF0);
+        assert_eq!(loopy(&ppu), (0x3DF0, 0x3DF0, 5, false));
+
+        // PPUDATA accesses increment v by 1, or 32 with bit 2 of PPUCTRL
+        ppu.write(0x2007, 0x00);
+        assert_eq!(ppu.v, 0x3DF1);
+        ppu.write(0x2000, 0x04);
+        ppu.read(0x2007);
+        assert_eq!(loopy(&ppu), (0x3E11, 0x31F0, 5, false));
+    }
+}
@@ -0,0 +1,106 @@
+// Small LZ77 compressor for save states. States are mostly long runs (zeroed
+// memory, XOR deltas of unchanged state) and repeats (RGB pixels of the same
+// colour), which back references handle well.
+//
+// The output is a sequence of
+//
+//   literal count (varint), literal bytes, match length - MIN_MATCH (varint),
+//   match distance (varint)
+//
+// where the last sequence stops after its literals. Matches may overlap the
+// bytes they produce, so a distance of 1 repeats a single byte.
+
+static MIN_MATCH: usize = 4;
+
+static HASH_BITS: u32 = 16;
+
+// Position of the last occurrence of the 4 bytes at i, by hash
+fn hash(data: &[u8], i: usize) -> usize {
+    let word: u32 = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    return (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift: u32 = 0;
+    loop {
+        let byte: u8 = *data.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut table: Vec<usize> = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literals: usize = 0; // start of the pending literals
+    let mut i: usize = 0;
+
+    while i + MIN_MATCH <= data.len() {
+        let key: usize = hash(data, i);
+        let candidate: usize = table[key];
+        table[key] = i;
+        if candidate == usize::MAX
+            || data[candidate..candidate + MIN_MATCH] != data[i..i + MIN_MATCH]
+        {
+            i += 1;
+            continue;
+        }
+
+        let mut length: usize = MIN_MATCH;
+        while i + length < data.len() && data[candidate + length] == data[i + length] {
+            length += 1;
+        }
+        write_varint(&mut out, i - literals);
+        out.extend_from_slice(&data[literals..i]);
+        write_varint(&mut out, length - MIN_MATCH);
+        write_varint(&mut out, i - candidate);
+
+        i += length;
+        literals = i;
+    }
+
+    write_varint(&mut out, data.len() - literals);
+    out.extend_from_slice(&data[literals..]);
+    return out;
+}
+
+// None when the data is corrupt or would decompress to more than limit
+// bytes, so a bad length can't run away with the memory
+pub fn decompress(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut offset: usize = 0;
+    loop {
+        let count: usize = read_varint(data, &mut offset)?;
+        if count > limit - out.len() {
+            return None;
+        }
+        out.extend_from_slice(data.get(offset..offset.checked_add(count)?)?);
+        offset += count;
+        if offset == data.len() {
+            return Some(out);
+        }
+
+        let length: usize = read_varint(data, &mut offset)?.checked_add(MIN_MATCH)?;
+        let distance: usize = read_varint(data, &mut offset)?;
+        if distance == 0 || distance > out.len() || length > limit - out.len() {
+            return None;
+        }
+        let start: usize = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod compress;
+pub mod rewind;
+#[allow(clippy::module_inception)]
+pub mod savestate;
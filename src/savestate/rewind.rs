@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use crate::nes::nes::Nes;
+use crate::savestate::compress;
+use crate::savestate::savestate::StateError;
+
+// Frames between keyframes
+pub static DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+
+// Memory kept for snapshots, in bytes
+pub static DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+// A keyframe and the snapshots following it, stored as deltas
+struct Group {
+    keyframe: Vec<u8>,    // compressed save state
+    deltas: Vec<Vec<u8>>, // compressed XOR against the keyframe's state
+    length: usize,        // uncompressed length of every state in the group
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        return self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>();
+    }
+
+    // Decompress a keyframe or delta of the group, which has to come out at
+    // exactly the length of the states it was made from
+    fn expand(&self, data: &[u8]) -> Result<Vec<u8>, StateError> {
+        return match compress::decompress(data, self.length) {
+            Some(state) if state.len() == self.length => Ok(state),
+            _ => Err(StateError::Invalid("rewind snapshot")),
+        };
+    }
+}
+
+// Ring buffer of save states, typically one per frame, to step the console
+// back in time.
+//
+// Every keyframe_interval snapshots a full state is kept as a keyframe and
+// the ones in between as the XOR against it, which is mostly zeros and
+// compresses well. Any snapshot is restored from its keyframe and a single
+// delta. Once the buffer grows past its memory budget the oldest keyframes
+// are dropped along with their deltas.
+pub struct Rewind {
+    pub keyframe_interval: usize,
+    budget: usize,
+    groups: VecDeque<Group>,
+    keyframe: Vec<u8>, // uncompressed keyframe of the newest group
+    size: usize,       // compressed bytes held
+}
+
+impl Rewind {
+    // Keep at most budget bytes of snapshots
+    pub fn new(budget: usize) -> Self {
+        return Self {
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            budget,
+            groups: VecDeque::new(),
+            keyframe: Vec::new(),
+            size: 0,
+        };
+    }
+
+    // Number of snapshots held
+    pub fn len(&self) -> usize {
+        return self.groups.iter().map(|group| 1 + group.deltas.len()).sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.groups.is_empty();
+    }
+
+    // Memory used by the snapshots, in bytes
+    pub fn size(&self) -> usize {
+        return self.size;
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.keyframe.clear();
+        self.size = 0;
+    }
+
+    // Add a snapshot, as returned by Nes::save_state
+    pub fn push(&mut self, state: &[u8]) {
+        let delta: bool = match self.groups.back() {
+            Some(group) => {
+                group.deltas.len() + 1 < self.keyframe_interval
+                    && state.len() == self.keyframe.len()
+            }
+            None => false,
+        };
+
+        if delta {
+            let xor: Vec<u8> = state
+                .iter()
+                .zip(self.keyframe.iter())
+                .map(|(a, b)| a ^ b)
+                .collect();
+            let delta: Vec<u8> = compress::compress(&xor);
+            self.size += delta.len();
+            self.groups.back_mut().unwrap().deltas.push(delta);
+        } else {
+            let keyframe: Vec<u8> = compress::compress(state);
+            self.size += keyframe.len();
+            self.groups.push_back(Group {
+                keyframe,
+                deltas: Vec::new(),
+                length: state.len(),
+            });
+            self.keyframe = state.to_vec();
+        }
+
+        // The newest group stays, whatever its size
+        while self.size > self.budget && self.groups.len() > 1 {
+            let oldest: Group = self.groups.pop_front().unwrap();
+            self.size -= oldest.size();
+        }
+    }
+
+    // The newest snapshot, None when there are none
+    pub fn latest(&self) -> Result<Option<Vec<u8>>, StateError> {
+        let group: &Group = match self.groups.back() {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+        let state: Vec<u8> = match group.deltas.last() {
+            Some(delta) => group
+                .expand(delta)?
+                .iter()
+                .zip(self.keyframe.iter())
+                .map(|(a, b)| a ^ b)
+                .collect(),
+            None => self.keyframe.clone(),
+        };
+        return Ok(Some(state));
+    }
+
+    // Remove and return the newest snapshot. Nothing is removed when it
+    // turns out to be corrupt.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>, StateError> {
+        let state: Vec<u8> = match self.latest()? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let group: &mut Group = self.groups.back_mut().unwrap();
+        if let Some(delta) = group.deltas.pop() {
+            self.size -= delta.len();
+            return Ok(Some(state));
+        }
+
+        // The previous group's keyframe becomes the one deltas are against
+        let keyframe: Vec<u8> = match self.groups.iter().rev().nth(1) {
+            Some(previous) => previous.expand(&previous.keyframe)?,
+            None => Vec::new(),
+        };
+        let group: Group = self.groups.pop_back().unwrap();
+        self.size -= group.size();
+        self.keyframe = keyframe;
+        return Ok(Some(state));
+    }
+
+    // Snapshot the console, once per frame
+    pub fn capture(&mut self, nes: &Nes) {
+        self.push(&nes.save_state());
+    }
+
+    // Go back a frame: drop the newest snapshot, taken of the frame being
+    // shown, and load the one before it. Returns false once there is nothing
+    // older to go back to.
+    pub fn step_back(&mut self, nes: &mut Nes) -> Result<bool, StateError> {
+        if self.len() < 2 {
+            return Ok(false);
+        }
+        self.pop()?;
+        return match self.latest()? {
+            Some(state) => nes.load_state(&state).map(|_| true),
+            None => Ok(false),
+        };
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        return Self::new(DEFAULT_BUDGET);
+    }
+}
@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::fmt;
+
+// Save states, a snapshot of the whole machine as a binary blob.
+//
+// Layout (all numbers little endian):
+//
+//   0   8  magic "NESSTATE"
+//   8   2  format version
+//   10  8  hash of the cartridge ROM the state belongs to, 0 without one
+//   18  .. chunks, each a 4 byte tag, a u32 length and that many bytes
+//
+// Every component saves itself into its own chunk through Snapshot. To keep
+// older builds able to read newer states, changes must be additive: new
+// state goes into new chunks or is appended to the end of a chunk. Readers
+// skip chunks they don't know and ignore trailing bytes in the ones they do.
+// FORMAT_VERSION is only bumped for changes older builds can't read.
+
+static MAGIC: &[u8; 8] = b"NESSTATE";
+pub static FORMAT_VERSION: u16 = 2;
+
+static HEADER_SIZE: usize = 18;
+static CHUNK_HEADER_SIZE: usize = 8;
+
+// Errors raised while loading a save state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    MissingChunk(&'static str),
+    Truncated(&'static str), // chunk ended before all of its state
+    Invalid(&'static str),   // value that doesn't fit the machine
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            StateError::InvalidMagic => write!(f, "Not a save state, missing NESSTATE magic"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "Save state format {} is newer than the supported {}",
+                version, FORMAT_VERSION
+            ),
+            StateError::RomMismatch => write!(f, "Save state belongs to a different ROM"),
+            StateError::MissingChunk(tag) => write!(f, "Save state has no {} chunk", tag.trim()),
+            StateError::Truncated(tag) => write!(f, "Save state {} chunk is truncated", tag.trim()),
+            StateError::Invalid(what) => write!(f, "Save state has an invalid {}", what),
+        };
+    }
+}
+
+impl Error for StateError {}
+
+// State of a component that can be saved and restored. load reads back
+// exactly what save wrote, in the same order.
+pub trait Snapshot {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Builds a save state out of chunks
+pub struct SaveState {
+    data: Vec<u8>,
+}
+
+impl SaveState {
+    pub fn new(rom_hash: u64) -> Self {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data.extend_from_slice(&rom_hash.to_le_bytes());
+        return Self { data };
+    }
+
+    // Append a chunk with the state of a component. Tags are 4 characters.
+    pub fn chunk(&mut self, tag: &'static str, component: &dyn Snapshot) {
+        assert_eq!(tag.len(), 4, "Chunk tags are 4 bytes long");
+        let mut writer: StateWriter = StateWriter { data: Vec::new() };
+        component.save(&mut writer);
+        self.data.extend_from_slice(tag.as_bytes());
+        self.data
+            .extend_from_slice(&(writer.data.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(&writer.data);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.data;
+    }
+}
+
+// A save state being loaded, split into its chunks
+pub struct ParsedState<'a> {
+    pub version: u16,
+    pub rom_hash: u64,
+    chunks: Vec<(&'a [u8], &'a [u8])>, // tag and contents
+}
+
+impl<'a> ParsedState<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, StateError> {
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+        let version: u16 = u16::from_le_bytes([data[8], data[9]]);
+        if version > FORMAT_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut rom_hash: [u8; 8] = [0x00; 8];
+        rom_hash.copy_from_slice(&data[10..HEADER_SIZE]);
+
+        let mut chunks: Vec<(&[u8], &[u8])> = Vec::new();
+        let mut offset: usize = HEADER_SIZE;
+        while offset < data.len() {
+            if data.len() - offset < CHUNK_HEADER_SIZE {
+                return Err(StateError::Invalid("chunk header"));
+            }
+            let tag: &[u8] = &data[offset..offset + 4];
+            let mut length: [u8; 4] = [0x00; 4];
+            length.copy_from_slice(&data[offset + 4..offset + CHUNK_HEADER_SIZE]);
+            let start: usize = offset + CHUNK_HEADER_SIZE;
+            let end: usize = start + u32::from_le_bytes(length) as usize;
+            if end > data.len() {
+                return Err(StateError::Invalid("chunk length"));
+            }
+            chunks.push((tag, &data[start..end]));
+            offset = end;
+        }
+
+        return Ok(Self {
+            version,
+            rom_hash: u64::from_le_bytes(rom_hash),
+            chunks,
+        });
+    }
+
+    // Restore a component from its chunk
+    pub fn restore(
+        &self,
+        tag: &'static str,
+        component: &mut dyn Snapshot,
+    ) -> Result<(), StateError> {
+        let data: &[u8] = self
+            .chunks
+            .iter()
+            .find(|(t, _)| *t == tag.as_bytes())
+            .map(|(_, data)| *data)
+            .ok_or(StateError::MissingChunk(tag))?;
+        let mut reader: StateReader = StateReader {
+            tag,
+            data,
+            offset: 0,
+        };
+        return component.load(&mut reader);
+    }
+}
+
+// Serialises the fields of a component
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    // Fixed size memory, read back with StateReader::bytes
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Variable size memory, prefixed with its length
+    pub fn block(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    // Length prefixed state of a nested component, read back with
+    // StateReader::section so it can grow like a chunk
+    pub fn section(&mut self, save: impl FnOnce(&mut StateWriter)) {
+        let mut section: StateWriter = StateWriter { data: Vec::new() };
+        save(&mut section);
+        self.block(&section.data);
+    }
+}
+
+// Reads back the fields written by StateWriter
+pub struct StateReader<'a> {
+    tag: &'static str,
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.offset < length {
+            return Err(StateError::Truncated(self.tag));
+        }
+        let bytes: &[u8] = &self.data[self.offset..self.offset + length];
+        self.offset += length;
+        return Ok(bytes);
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        return Ok(self.u8()? != 0);
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes: &[u8] = self.take(2)?;
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes: [u8; 4] = [0x00; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_le_bytes(bytes));
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes: [u8; 8] = [0x00; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        return Ok(u64::from_le_bytes(bytes));
+    }
+
+    pub fn f32(&mut self) -> Result<f32, StateError> {
+        return Ok(f32::from_bits(self.u32()?));
+    }
+
+    // Fill fixed size memory
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        return Ok(());
+    }
+
+    // Variable size memory, which must have the size of out (e.g. the RAM
+    // of the cartridge the state is loaded into)
+    pub fn block(&mut self, out: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        if self.u32()? as usize != out.len() {
+            return Err(StateError::Invalid(what));
+        }
+        return self.bytes(out);
+    }
+
+    // Read a section, ignoring whatever load leaves unread in it
+    pub fn section<T>(
+        &mut self,
+        load: impl FnOnce(&mut StateReader) -> Result<T, StateError>,
+    ) -> Result<T, StateError> {
+        let length: usize = self.u32()? as usize;
+        let mut section: StateReader = StateReader {
+            tag: self.tag,
+            data: self.take(length)?,
+            offset: 0,
+        };
+        return load(&mut section);
+    }
+}
+
+// 64 bit FNV-1a, used to identify ROMs and compare machine states
+pub fn hash(data: &[u8]) -> u64 {
+    return data.iter().fold(0xCBF2_9CE4_8422_2325, |hash: u64, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+    });
+}
@@ -0,0 +1,200 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::cpu::asm::{self, Assembly};
+use nes_emulator::cpu::disasm::{self, Decoded};
+use nes_emulator::cpu::instructions::LOOKUP;
+
+fn assemble(source: &str) -> Assembly {
+    return asm::assemble(source).unwrap_or_else(|e| panic!("{}", e));
+}
+
+#[test]
+fn addressing_modes() {
+    let assembly: Assembly = assemble(
+        "
+        .org $C000
+        LDA #$42
+        LDA $10
+        LDA $0010       ; 4 digits force absolute
+        LDA $10,X
+        LDX $10,Y
+        LDA $1234,Y
+        LDA ($20,X)
+        LDA ($20),Y
+        JMP ($FFFC)
+        ASL
+        ASL A
+        STX $80,Y
+        BRK             ; padding byte filled in
+        BRK #$12
+        ",
+    );
+    assert_eq!(
+        assembly.bytes(),
+        vec![
+            0xA9, 0x42, 0xA5, 0x10, 0xAD, 0x10, 0x00, 0xB5, 0x10, 0xB6, 0x10, 0xB9, 0x34, 0x12,
+            0xA1, 0x20, 0xB1, 0x20, 0x6C, 0xFC, 0xFF, 0x0A, 0x0A, 0x96, 0x80, 0x00, 0x00, 0x00,
+            0x12,
+        ]
+    );
+    assert_eq!(assembly.origin(), 0xC000);
+}
+
+#[test]
+fn labels_and_expressions() {
+    let assembly: Assembly = assemble(
+        "
+        PPUSTATUS = $2002
+        COUNT = end - table     ; constants may refer ahead
+                .org $8000
+        reset:  LDX #COUNT
+        @loop:  BIT PPUSTATUS
+                BPL @loop
+                DEX
+                BNE @loop
+        nmi:
+        @loop:  JMP @loop       ; a different @loop
+                LDA #<table
+                LDY #>table
+                LDA table+1, x
+        table:  .byte 1, %10, 'c', \"hi\", -1
+                .word reset, *, (2 + 3) * 4, $F0 | $0F, 1 << 8 >> 4
+        end:
+        ",
+    );
+    let symbol = |name: &str| assembly.symbol(name).unwrap();
+    assert_eq!(symbol("reset"), 0x8000);
+    assert_eq!(symbol("reset@loop"), 0x8002);
+    assert_eq!(symbol("nmi@loop"), 0x800A);
+    assert_eq!(symbol("table"), 0x8014);
+    assert_eq!(symbol("COUNT"), 16);
+    assert_eq!(symbol("PPUSTATUS"), 0x2002);
+
+    let bytes: Vec<u8> = assembly.bytes();
+    assert_eq!(&bytes[0..2], &[0xA2, 16]);
+    assert_eq!(&bytes[2..5], &[0x2C, 0x02, 0x20]);
+    assert_eq!(&bytes[5..7], &[0x10, 0xFB]);
+    assert_eq!(&bytes[8..10], &[0xD0, 0xF8]);
+    assert_eq!(&bytes[10..13], &[0x4C, 0x0A, 0x80]);
+    assert_eq!(&bytes[13..17], &[0xA9, 0x14, 0xA0, 0x80]);
+    assert_eq!(&bytes[17..20], &[0xBD, 0x15, 0x80]);
+    assert_eq!(&bytes[20..26], &[1, 2, b'c', b'h', b'i', 0xFF]);
+    assert_eq!(
+        &bytes[26..36],
+        &[0x00, 0x80, 0x1C, 0x80, 20, 0x00, 0xFF, 0x00, 0x10, 0x00]
+    );
+}
+
+#[test]
+fn forward_references_use_absolute_addressing() {
+    // The zero page label isn't known on the first pass
+    let assembly: Assembly = assemble("LDA data\n.org $0010\ndata: .byte 0");
+    assert_eq!(assembly.segments[0].bytes, vec![0xAD, 0x10, 0x00]);
+    assert_eq!(assembly.segments[1].origin, 0x0010);
+}
+
+#[test]
+fn errors_report_the_line() {
+    let error = |source: &str| asm::assemble(source).err().unwrap();
+    assert_eq!(error("NOP\nLDA undefined").line, 2);
+    assert_eq!(error("FOO #1").message, "Unknown instruction FOO");
+    assert_eq!(error("a: NOP\na: NOP").line, 2);
+    assert_eq!(
+        error("STX $1234,X").message,
+        "Addressing mode not supported by STX"
+    );
+    assert_eq!(error(".org $8000\nBNE $9000").line, 2);
+    assert_eq!(
+        error("LDA #$100").message,
+        "Value 256 doesn't fit in a byte"
+    );
+    assert_eq!(
+        error(".org $FFFF\nNOP\nNOP").message,
+        "Code runs past $FFFF"
+    );
+}
+
+#[test]
+fn round_trips_every_opcode() {
+    for (opcode, instruction) in LOOKUP.iter().enumerate() {
+        let code: Vec<u8> =
+            [opcode as u8, 0x34, 0x12][..instruction.mode.length() as usize].to_vec();
+        let decoded: Vec<Decoded> = disasm::disassemble(&code, 0x8000);
+        let source: String = format!(".org $8000\n{}", decoded[0].text());
+        let assembled: Vec<u8> = assemble(&source).bytes();
+
+        // Alternative encodings come back as the preferred one
+        let preferred: u8 = asm::opcode_for(instruction.name, instruction.mode).unwrap();
+        let mut expected: Vec<u8> = code.clone();
+        expected[0] = preferred;
+        // BRK is listed without its padding byte, which assembles as zero
+        if instruction.name == "BRK" {
+            expected[1] = 0x00;
+        }
+        assert_eq!(assembled, expected, "{}", source);
+        if preferred != opcode as u8 {
+            assert_eq!(LOOKUP[preferred as usize].name, instruction.name);
+        }
+    }
+}
+
+#[test]
+fn round_trips_a_program() {
+    let source: &str = "
+        .org $C000
+        start:  SEI
+                LDX #$FF
+                TXS
+        @wait:  BIT $2002
+                BPL @wait
+                LDA ($00),Y
+                STA $0300,X
+                JSR sub
+                JMP start
+        sub:    INC $10
+                RTS
+                .byte $FF, $00
+    ";
+    let assembly: Assembly = assemble(source);
+    let symbols = assembly.symbol_table();
+    let listing: Vec<String> = disasm::disassemble(&assembly.bytes(), assembly.origin())
+        .iter()
+        .map(|instruction| {
+            let label: String = match symbols.get(&instruction.address) {
+                Some(name) if !name.contains('@') => format!("{}:", name),
+                _ => String::new(),
+            };
+            return format!("{} {}", label, instruction.text());
+        })
+        .collect();
+    let reassembled: Assembly = assemble(&format!(".org $C000\n{}", listing.join("\n")));
+    assert_eq!(reassembled.bytes(), assembly.bytes());
+    assert_eq!(listing[0], "start: SEI");
+}
+
+#[test]
+fn runs_on_the_cpu() {
+    let assembly: Assembly = assemble(
+        "
+                .org $8000
+        reset:  LDX #5
+                LDA #0
+        @add:   CLC
+                ADC #3
+                DEX
+                BNE @add
+                STA $00
+        @done:  JMP @done
+                .org $FFFC
+                .word reset
+        ",
+    );
+    let (mut cpu, memory) = common::cpu_with_flat_memory();
+    assembly.load_into(&mut cpu.bus);
+    cpu.reset();
+    while cpu.registers.pc != assembly.symbol("reset@done").unwrap() {
+        cpu.execute_instruction();
+    }
+    assert_eq!(memory.borrow().data[0x0000], 15);
+}
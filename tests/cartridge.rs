@@ -0,0 +1,232 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nes_emulator::cartridge::cartridge::Cartridge;
+use nes_emulator::cartridge::ines::{INesHeader, Mirroring, RomError, RomFormat};
+use nes_emulator::cpu::asm::{self, Assembly};
+use nes_emulator::{Bus, Nes, CPU};
+
+// iNES header followed by zeroed PRG and CHR data of the declared sizes
+fn image(header: [u8; 16], prg: usize, chr: usize) -> Vec<u8> {
+    let mut image: Vec<u8> = header.to_vec();
+    image.resize(16 + prg + chr, 0x00);
+    return image;
+}
+
+fn load(data: &[u8]) -> Result<Cartridge, RomError> {
+    return Cartridge::from_bytes(data);
+}
+
+#[test]
+fn ines_headers_are_decoded() {
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 2, 1, 0x13, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let cartridge: Cartridge = load(&image(header, 0x8000, 0x2000)).unwrap();
+    assert_eq!(cartridge.header.format, RomFormat::INes);
+    assert_eq!(cartridge.mapper(), 1);
+    assert_eq!(cartridge.header.mirroring, Mirroring::Vertical);
+    assert!(cartridge.header.battery);
+    assert_eq!(cartridge.header.prg_nvram_size, 0x2000);
+    assert_eq!(cartridge.prg_rom.len(), 0x8000);
+    assert_eq!(cartridge.chr.len(), 0x2000);
+    assert!(!cartridge.chr_is_ram);
+}
+
+#[test]
+fn truncated_files_and_bad_magic_are_rejected() {
+    assert!(matches!(
+        load(b"NES\x1A"),
+        Err(RomError::Truncated {
+            expected: 16,
+            actual: 4
+        })
+    ));
+
+    let header: [u8; 16] = [b'N', b'E', b'S', 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut data: Vec<u8> = image(header, 0x8000, 0x2000);
+    data.truncate(data.len() - 1);
+    assert!(matches!(
+        load(&data),
+        Err(RomError::Truncated {
+            expected: 0xA010,
+            actual: 0xA00F
+        })
+    ));
+
+    // The trainer counts towards the file size
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 1, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    assert!(matches!(
+        load(&image(header, 0x4000, 0)),
+        Err(RomError::Truncated {
+            expected: 0x4210,
+            ..
+        })
+    ));
+
+    let mut data: Vec<u8> = image(header, 0x4000, 0);
+    data[3] = 0x1B;
+    assert!(matches!(load(&data), Err(RomError::InvalidMagic)));
+    assert!(matches!(
+        INesHeader::parse(b"UNIF\0\0\0\0\0\0\0\0\0\0\0\0"),
+        Err(RomError::InvalidMagic)
+    ));
+}
+
+#[test]
+fn nes2_exponent_sizes() {
+    // PRG ROM 2^12 * 3 bytes in exponent notation, CHR ROM a single 8KB bank
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 0x31, 1, 0x00, 0x08, 0x00, 0x0F, 0x07, 0x00, 0, 0, 0, 0,
+    ];
+    let parsed: INesHeader = INesHeader::parse(&header).unwrap();
+    assert_eq!(parsed.format, RomFormat::Nes2);
+    assert_eq!(parsed.prg_rom_size, 3 << 12);
+    assert_eq!(parsed.chr_rom_size, 0x2000);
+    assert_eq!(parsed.prg_ram_size, 64 << 7);
+    assert_eq!(parsed.file_size().unwrap(), 16 + (3 << 12) + 0x2000);
+    assert_eq!(
+        load(&image(header, 3 << 12, 0x2000)).unwrap().prg_rom.len(),
+        3 << 12
+    );
+
+    // 2^63 bytes of PRG and of CHR ROM don't fit in a usize together
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 0xFC, 0xFC, 0x00, 0x08, 0x00, 0xFF, 0, 0, 0, 0, 0, 0,
+    ];
+    assert!(matches!(load(&header), Err(RomError::InvalidSize(_))));
+
+    // 2^63 * 7 overflows on its own
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 0xFF, 0x00, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0,
+    ];
+    assert!(matches!(
+        INesHeader::parse(&header),
+        Err(RomError::InvalidSize("PRG ROM"))
+    ));
+}
+
+#[test]
+fn unsupported_mappers_are_rejected() {
+    // Mapper 5 (MMC5) in iNES, mapper 0x100 (a NES 2.0 extended number)
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 1, 1, 0x50, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    assert!(matches!(
+        load(&image(header, 0x4000, 0x2000)),
+        Err(RomError::UnsupportedMapper(5))
+    ));
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 1, 1, 0x00, 0x08, 0x01, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    assert!(matches!(
+        load(&image(header, 0x4000, 0x2000)),
+        Err(RomError::UnsupportedMapper(0x100))
+    ));
+
+    // Junk from old dumping tools in bytes 12-15 masks the upper nibble
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 1, 1, 0x00, 0x40, 0, 0, 0, 0, b'D', b'i', b's', b'k',
+    ];
+    assert_eq!(load(&image(header, 0x4000, 0x2000)).unwrap().mapper(), 0);
+}
+
+#[test]
+fn unmapped_cartridge_space_is_open_bus() {
+    // NES 2.0 board without PRG RAM
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 2, 1, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let cartridge: Cartridge = load(&image(header, 0x8000, 0x2000)).unwrap();
+    let mut bus: Bus = Bus::new();
+    bus.insert_cartridge(Rc::new(RefCell::new(cartridge)));
+
+    // Reads return whatever was last on the bus
+    bus.write(0x4018, 0x5A);
+    assert_eq!(bus.read(0x5000), 0x5A);
+    assert_eq!(bus.read(0x6000), 0x5A);
+    assert_eq!(bus.read(0x7FFF), 0x5A);
+    assert_eq!(bus.peek(0x6000), None);
+
+    // ROM drives every bit
+    assert_eq!(bus.read(0x8000), 0x00);
+    assert_eq!(bus.read(0x6000), 0x00);
+}
+
+// Selects PRG bank 2 at $8000, one bit per store, and leaves $42 in PRG RAM.
+// Runs from the last bank, which MMC1 powers up fixed at $C000.
+static MMC1_PROGRAM: &str = "
+            .org $C000
+    reset:  LDA #2
+            STA $E000
+            LSR A
+            STA $E000
+            LSR A
+            STA $E000
+            STA $E000
+            STA $E000
+            LDA #$42
+            STA $6000
+    done:   JMP done
+
+            .org $FFFC
+            .word reset
+";
+
+// MMC1 board with 4 PRG banks, each filled with its number, and
+// MMC1_PROGRAM in the last one
+fn mmc1_image(assembly: &Assembly) -> Vec<u8> {
+    let header: [u8; 16] = [
+        b'N', b'E', b'S', 0x1A, 4, 0, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut data: Vec<u8> = header.to_vec();
+    for bank in 0..4 {
+        data.extend_from_slice(&[bank; 0x4000]);
+    }
+    let program: Vec<u8> = assembly.bytes();
+    data[16 + 0xC000..16 + 0xC000 + program.len()].copy_from_slice(&program);
+    return data;
+}
+
+#[test]
+fn mmc1_is_loaded_by_a_cpu_of_its_own() {
+    let assembly: Assembly = asm::assemble(MMC1_PROGRAM).unwrap();
+
+    // No console clocking the cartridge, only the CPU and its bus
+    let cartridge: Cartridge = load(&mmc1_image(&assembly)).unwrap();
+    let mut cpu: CPU = CPU::new();
+    cpu.bus.insert_cartridge(Rc::new(RefCell::new(cartridge)));
+    cpu.reset();
+    let done: u16 = assembly.symbol("done").unwrap();
+    while cpu.registers.pc != done && cpu.cpu_cycles < 1000 {
+        cpu.clock();
+    }
+    assert_eq!(cpu.bus.peek(0x8000), Some(2));
+    assert_eq!(cpu.bus.peek(0xBFFF), Some(2));
+}
+
+#[test]
+fn power_on_resets_the_mapper_and_keeps_prg_ram() {
+    let assembly: Assembly = asm::assemble(MMC1_PROGRAM).unwrap();
+    let mut nes: Nes = Nes::new();
+    nes.insert_cartridge(load(&mmc1_image(&assembly)).unwrap());
+    let done: u16 = assembly.symbol("done").unwrap();
+    while nes.cpu.registers.pc != done && nes.cpu.cpu_cycles < 1000 {
+        nes.step_instruction();
+    }
+    assert_eq!(nes.cpu.bus.peek(0x8000), Some(2));
+
+    nes.power_on();
+    assert_eq!(nes.cpu.bus.peek(0x8000), Some(0));
+    assert_eq!(nes.cpu.bus.peek(0xC000), Some(0xA9));
+    assert_eq!(nes.cpu.bus.peek(0x6000), Some(0x42));
+
+    // Unless erased
+    nes.power_on_erased();
+    assert_eq!(nes.cpu.bus.peek(0x6000), Some(0x00));
+}
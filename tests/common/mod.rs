@@ -0,0 +1,184 @@
+// Shared helpers for the integration tests
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nes_emulator::cartridge::cartridge::Cartridge;
+use nes_emulator::cpu::asm::{self, Assembly};
+use nes_emulator::cpu::device::Device;
+use nes_emulator::{Bus, Nes, CPU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Flat 64KB of memory recording every bus access, so tests can see exactly
+// what the CPU did
+pub struct FlatMemory {
+    pub data: Vec<u8>,
+    pub accesses: Vec<(u16, u8, Access)>,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        return Self {
+            data: vec![0x00; 0x10000],
+            accesses: Vec::new(),
+        };
+    }
+}
+
+impl Device for FlatMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        let data: u8 = self.data[address as usize];
+        self.accesses.push((address, data, Access::Read));
+        return data;
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.data[address as usize] = data;
+        self.accesses.push((address, data, Access::Write));
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        return Some(self.data[address as usize]);
+    }
+}
+
+// A CPU whose whole address space is a FlatMemory. The bus still handles
+// $4014 itself, so tests should keep clear of it.
+pub fn cpu_with_flat_memory() -> (CPU, Rc<RefCell<FlatMemory>>) {
+    let memory: Rc<RefCell<FlatMemory>> = Rc::new(RefCell::new(FlatMemory::new()));
+    let mut cpu: CPU = CPU::new();
+    let mut bus: Bus = Bus::new();
+    bus.attach(0x0000, 0xFFFF, Box::new(memory.clone()));
+    cpu.bus = bus;
+    return (cpu, memory);
+}
+
+// iNES image of an NROM board with 8KB of CHR RAM and 32KB of PRG ROM
+// assembled from source, which should start at $8000 and end with the
+// vectors
+pub fn nrom_image(source: &str) -> Vec<u8> {
+    let assembly: Assembly = asm::assemble(source).unwrap();
+    assert_eq!(assembly.origin(), 0x8000);
+    let mut prg: Vec<u8> = assembly.bytes();
+    prg.resize(0x8000, 0x00);
+
+    let mut image: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, 2, 0, 0x01];
+    image.resize(16, 0x00);
+    image.extend_from_slice(&prg);
+    return image;
+}
+
+// Keeps every part of the machine busy: CHR RAM and nametables are filled,
+// rendering and NMIs are on, two APU channels play and the NMI handler reads
+// the controller
+pub static DEMO_PROGRAM: &str = "
+            .org $8000
+    reset:  SEI
+            LDX #$FF
+            TXS
+            LDA #$00
+            STA $2006
+            STA $2006
+            LDX #0
+    @chr:   TXA
+            STA $2007
+            INX
+            BNE @chr
+            LDA #$20
+            STA $2006
+            LDA #$00
+            STA $2006
+    @nt:    TXA
+            STA $2007
+            INX
+            BNE @nt
+            LDA #$0F
+            STA $4015
+            LDA #$BF
+            STA $4000
+            LDA #$40
+            STA $4002
+            LDA #$01
+            STA $4003
+            LDA #$81
+            STA $4008
+            STA $400B
+            LDA #$80
+            STA $2000
+            LDA #$1E
+            STA $2001
+    loop:   INC $10
+            LDA $10
+            ADC $11
+            STA $11
+            TAX
+            LDA $0300,X
+            EOR $11
+            STA $0300,X
+            JMP loop
+
+    nmi:    PHA
+            INC $20
+            LDA #$01
+            STA $4016
+            LDA #$00
+            STA $4016
+            LDA $4016
+            AND #$01
+            CLC
+            ADC $21
+            STA $21
+            PLA
+            RTI
+
+    irq:    RTI
+
+            .org $FFFA
+            .word nmi, reset, irq
+";
+
+// Console with nrom_image(source) inserted
+pub fn nrom_nes(source: &str) -> Nes {
+    let mut nes: Nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&nrom_image(source)).unwrap());
+    return nes;
+}
+
+// Console running DEMO_PROGRAM
+pub fn demo_nes() -> Nes {
+    return nrom_nes(DEMO_PROGRAM);
+}
+
+// A loop calling a subroutine that calls another, for stepping through with
+// the debuggers. Leaves 10 in A and $0300, and 5 in Y.
+pub static SUBROUTINE_PROGRAM: &str = "
+            .org $8000
+    reset:  LDX #5
+    @loop:  JSR add
+            DEX
+            BNE @loop
+            STA $0300
+    done:   JMP done
+
+    add:    CLC
+            ADC #2
+            JSR nested
+            RTS
+    nested: INY
+            RTS
+
+            .org $FFFC
+            .word reset
+";
+
+// Console running SUBROUTINE_PROGRAM, and its assembly for the symbols
+pub fn subroutine_nes() -> (Nes, Assembly) {
+    let assembly: Assembly = asm::assemble(SUBROUTINE_PROGRAM).unwrap();
+    return (nrom_nes(SUBROUTINE_PROGRAM), assembly);
+}
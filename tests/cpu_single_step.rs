@@ -0,0 +1,185 @@
+#![allow(clippy::needless_return)]
+// Runs the single instruction vectors in tests/data/single_step, which use
+// the format of the SingleStepTests/ProcessorTests 6502 suite:
+//
+// { "name": "...",
+//   "initial": { "pc", "s", "a", "x", "y", "p", "ram": [[address, value], ...] },
+//   "final":   { same as initial },
+//   "cycles":  [[address, value, "read" | "write"], ...] }
+//
+// The vectors checked in are written by hand, at least one per addressing
+// mode and dummy access pattern. Files from the full suite can be dropped
+// into the directory as they are.
+// Every cycle is a bus access, so the reads and writes the CPU makes,
+// dummy ones included, must match the expected cycles exactly and in order.
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use common::Access;
+
+static VECTOR_DIRECTORY: &str = "tests/data/single_step";
+
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn from_json(value: &Value) -> Self {
+        let ram: Vec<(u16, u8)> = value["ram"]
+            .as_array()
+            .expect("ram is not an array")
+            .iter()
+            .map(|entry| {
+                (
+                    entry[0].as_u64().unwrap() as u16,
+                    entry[1].as_u64().unwrap() as u8,
+                )
+            })
+            .collect();
+        return Self {
+            pc: value["pc"].as_u64().unwrap() as u16,
+            s: value["s"].as_u64().unwrap() as u8,
+            a: value["a"].as_u64().unwrap() as u8,
+            x: value["x"].as_u64().unwrap() as u8,
+            y: value["y"].as_u64().unwrap() as u8,
+            p: value["p"].as_u64().unwrap() as u8,
+            ram,
+        };
+    }
+}
+
+fn parse_cycles(value: &Value) -> Vec<(u16, u8, Access)> {
+    return value
+        .as_array()
+        .expect("cycles is not an array")
+        .iter()
+        .map(|cycle| {
+            let access: Access = match cycle[2].as_str() {
+                Some("read") => Access::Read,
+                Some("write") => Access::Write,
+                other => panic!("Unknown cycle type {:?}", other),
+            };
+            return (
+                cycle[0].as_u64().unwrap() as u16,
+                cycle[1].as_u64().unwrap() as u8,
+                access,
+            );
+        })
+        .collect();
+}
+
+// Run one vector, returning a description of every mismatch
+fn run_vector(test: &Value) -> Vec<String> {
+    let initial: State = State::from_json(&test["initial"]);
+    let expected: State = State::from_json(&test["final"]);
+    let cycles: Vec<(u16, u8, Access)> = parse_cycles(&test["cycles"]);
+
+    let (mut cpu, memory) = common::cpu_with_flat_memory();
+    cpu.registers.pc = initial.pc;
+    cpu.registers.sp = initial.s;
+    cpu.registers.a = initial.a;
+    cpu.registers.x = initial.x;
+    cpu.registers.y = initial.y;
+    cpu.registers.status = initial.p;
+    for (address, value) in &initial.ram {
+        memory.borrow_mut().data[*address as usize] = *value;
+    }
+
+    let taken: u8 = cpu.execute_instruction();
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut check = |what: &str, got: u16, want: u16| {
+        if got != want {
+            errors.push(format!("{} is ${:02X}, expected ${:02X}", what, got, want));
+        }
+    };
+    check("PC", cpu.registers.pc, expected.pc);
+    check("S", cpu.registers.sp as u16, expected.s as u16);
+    check("A", cpu.registers.a as u16, expected.a as u16);
+    check("X", cpu.registers.x as u16, expected.x as u16);
+    check("Y", cpu.registers.y as u16, expected.y as u16);
+    check("P", cpu.registers.status as u16, expected.p as u16);
+    check("Cycle count", taken as u16, cycles.len() as u16);
+
+    let memory = memory.borrow();
+    for (address, value) in &expected.ram {
+        let got: u8 = memory.data[*address as usize];
+        if got != *value {
+            errors.push(format!(
+                "RAM ${:04X} is ${:02X}, expected ${:02X}",
+                address, got, value
+            ));
+        }
+    }
+
+    for cycle in 0..memory.accesses.len().max(cycles.len()) {
+        let (got, want) = (memory.accesses.get(cycle), cycles.get(cycle));
+        if got != want {
+            errors.push(format!(
+                "Cycle {} was {}, expected {}",
+                cycle + 1,
+                describe(got),
+                describe(want)
+            ));
+        }
+    }
+
+    return errors;
+}
+
+fn describe(access: Option<&(u16, u8, Access)>) -> String {
+    return match access {
+        Some((address, value, Access::Read)) => {
+            format!("read ${:02X} from ${:04X}", value, address)
+        }
+        Some((address, value, Access::Write)) => {
+            format!("write ${:02X} to ${:04X}", value, address)
+        }
+        None => String::from("nothing"),
+    };
+}
+
+fn vector_files() -> Vec<PathBuf> {
+    let directory: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR")).join(VECTOR_DIRECTORY);
+    let mut files: Vec<PathBuf> = fs::read_dir(&directory)
+        .expect("Missing single step test directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    return files;
+}
+
+#[test]
+fn single_step_vectors() {
+    let mut failures: Vec<String> = Vec::new();
+    let mut count: usize = 0;
+
+    for file in vector_files() {
+        let text: String = fs::read_to_string(&file).unwrap();
+        let tests: Value = serde_json::from_str(&text)
+            .unwrap_or_else(|error| panic!("{}: {}", file.display(), error));
+        for test in tests.as_array().expect("Expected an array of tests") {
+            count += 1;
+            for error in run_vector(test) {
+                failures.push(format!("{}: {}", test["name"].as_str().unwrap(), error));
+            }
+        }
+    }
+
+    assert!(count > 0, "No single step vectors found");
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,172 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::cpu::bus::BusAccess;
+use nes_emulator::debugger::debugger::{
+    Breakpoint, Comparison, Condition, Register, StopReason, Watchpoint,
+};
+use nes_emulator::Debugger;
+
+#[test]
+fn breakpoints_stop_before_the_instruction() {
+    let (mut nes, assembly) = common::subroutine_nes();
+    let mut debugger: Debugger = Debugger::new();
+    let add: u16 = assembly.symbol("add").unwrap();
+    let id: usize = debugger.add_breakpoint(Breakpoint::at(add));
+
+    assert_eq!(debugger.run(&mut nes), StopReason::Breakpoint(id));
+    assert_eq!(nes.cpu.registers.pc, add);
+    assert_eq!(nes.cpu.registers.a, 0);
+
+    // Continuing moves off the breakpoint and around the loop again
+    assert_eq!(debugger.run(&mut nes), StopReason::Breakpoint(id));
+    assert_eq!(nes.cpu.registers.a, 2);
+
+    assert!(debugger.remove(id));
+    assert!(!debugger.remove(id));
+    debugger.budget = 1000;
+    assert_eq!(debugger.run(&mut nes), StopReason::Budget);
+    assert_eq!(nes.cpu.registers.pc, assembly.symbol("done").unwrap());
+    assert_eq!(nes.cpu.registers.a, 10);
+}
+
+#[test]
+fn stepping() {
+    let (mut nes, assembly) = common::subroutine_nes();
+    let mut debugger: Debugger = Debugger::new();
+    let call: u16 = assembly.symbol("reset@loop").unwrap();
+
+    // The reset sequence is finished before the first instruction
+    assert_eq!(debugger.step_into(&mut nes), StopReason::Done);
+    assert_eq!(nes.cpu.registers.pc, call);
+
+    // Step over runs the whole subroutine, nested calls included
+    assert_eq!(debugger.step_over(&mut nes), StopReason::Done);
+    assert_eq!(nes.cpu.registers.pc, call + 3);
+    assert_eq!((nes.cpu.registers.a, nes.cpu.registers.y), (2, 1));
+
+    // Step over anything else is a single step
+    assert_eq!(debugger.step_over(&mut nes), StopReason::Done);
+    assert_eq!(nes.cpu.registers.x, 4);
+
+    // Step out of the nested subroutine and then out of add
+    debugger.step_into(&mut nes); // BNE
+    debugger.step_into(&mut nes); // JSR add
+    for _ in 0..3 {
+        debugger.step_into(&mut nes); // CLC, ADC, JSR nested
+    }
+    assert_eq!(nes.cpu.registers.pc, assembly.symbol("nested").unwrap());
+    assert_eq!(debugger.step_out(&mut nes), StopReason::Done);
+    assert_eq!(nes.cpu.registers.pc, assembly.symbol("add").unwrap() + 6);
+    assert_eq!(debugger.step_out(&mut nes), StopReason::Done);
+    assert_eq!(nes.cpu.registers.pc, call + 3);
+
+    // The return address may be pushed across the bottom of the stack page
+    debugger.step_into(&mut nes); // DEX
+    debugger.step_into(&mut nes); // BNE
+    nes.cpu.registers.sp = 0x01;
+    debugger.step_into(&mut nes); // JSR add
+    assert_eq!(nes.cpu.registers.sp, 0xFF);
+    debugger.budget = 1000;
+    assert_eq!(debugger.step_out(&mut nes), StopReason::Done);
+    assert_eq!(
+        (nes.cpu.registers.pc, nes.cpu.registers.sp),
+        (call + 3, 0x01)
+    );
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let (mut nes, assembly) = common::subroutine_nes();
+    let mut debugger: Debugger = Debugger::new();
+    let x_is_2: Condition = Condition {
+        register: Register::X,
+        comparison: Comparison::Equal,
+        value: 2,
+    };
+    let id: usize = debugger.add_breakpoint(Breakpoint::when(x_is_2));
+    assert_eq!(debugger.run(&mut nes), StopReason::Breakpoint(id));
+    assert_eq!(nes.cpu.registers.x, 2);
+    assert_eq!(nes.cpu.registers.a, 6);
+
+    // A condition on an address
+    debugger.remove(id);
+    let loop_start: u16 = assembly.symbol("reset@loop").unwrap();
+    let at_loop: usize = debugger.add_breakpoint(Breakpoint {
+        address: Some(loop_start),
+        condition: Some(Condition {
+            register: Register::Flag(nes_emulator::StatusRegFlags::Z),
+            comparison: Comparison::Equal,
+            value: 0,
+        }),
+    });
+    assert_eq!(debugger.run(&mut nes), StopReason::Breakpoint(at_loop));
+    assert_eq!((nes.cpu.registers.pc, nes.cpu.registers.x), (loop_start, 2));
+    assert_eq!(x_is_2.to_string(), "X == $02");
+}
+
+#[test]
+fn watchpoints() {
+    let (mut nes, assembly) = common::subroutine_nes();
+    let mut debugger: Debugger = Debugger::new();
+    let write: usize = debugger.add_watchpoint(Watchpoint {
+        start: 0x0300,
+        end: 0x03FF,
+        read: false,
+        write: true,
+        execute: false,
+    });
+    assert_eq!(
+        debugger.run(&mut nes),
+        StopReason::Watchpoint(
+            write,
+            Some(BusAccess {
+                address: 0x0300,
+                data: 10,
+                write: true
+            })
+        )
+    );
+    assert_eq!(nes.cpu.registers.pc, assembly.symbol("done").unwrap());
+
+    // Execution of a range, stopping before the instruction
+    let (mut nes, assembly) = common::subroutine_nes();
+    let nested: u16 = assembly.symbol("nested").unwrap();
+    let execute: usize = debugger.add_watchpoint(Watchpoint {
+        start: nested,
+        end: nested + 1,
+        read: false,
+        write: false,
+        execute: true,
+    });
+    assert_eq!(
+        debugger.run(&mut nes),
+        StopReason::Watchpoint(execute, None)
+    );
+    assert_eq!(nes.cpu.registers.pc, nested);
+
+    // Reads, including the opcode fetch
+    let read: usize = debugger.add_watchpoint(Watchpoint {
+        start: assembly.symbol("add").unwrap(),
+        end: assembly.symbol("add").unwrap(),
+        read: true,
+        write: false,
+        execute: false,
+    });
+    debugger.remove(execute);
+    assert!(
+        matches!(debugger.run(&mut nes), StopReason::Watchpoint(id, Some(access)) if id == read && !access.write)
+    );
+
+    // Accesses are no longer recorded once the run is over
+    assert!(nes.cpu.bus.take_accesses().is_empty());
+}
+
+#[test]
+fn run_to_cycle() {
+    let (mut nes, _) = common::subroutine_nes();
+    let mut debugger: Debugger = Debugger::new();
+    assert_eq!(debugger.run_to_cycle(&mut nes, 100), StopReason::Done);
+    assert!(nes.cpu.cpu_cycles >= 100 && nes.cpu.cpu_cycles < 107);
+    assert_eq!(debugger.run_to_cycle(&mut nes, 50), StopReason::Done);
+}
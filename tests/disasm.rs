@@ -0,0 +1,88 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::cpu::disasm::{self, Decoded};
+
+fn texts(decoded: &[Decoded]) -> Vec<String> {
+    return decoded
+        .iter()
+        .map(|instruction| instruction.text())
+        .collect();
+}
+
+#[test]
+fn addressing_mode_syntax() {
+    let code: &[u8] = &[
+        0xA9, 0x42, // LDA #$42
+        0xB1, 0x20, // LDA ($20),Y
+        0xA1, 0x20, // LDA ($20,X)
+        0xB5, 0x10, // LDA $10,X
+        0xB6, 0x10, // LDX $10,Y
+        0xBD, 0x00, 0x03, // LDA $0300,X
+        0xB9, 0x00, 0x03, // LDA $0300,Y
+        0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+        0x0A, // ASL A
+        0xE8, // INX
+        0xD0, 0xFC, // BNE $C013
+        0x8D, 0x00, 0x20, // STA $2000
+        0x07, 0x30, // *SLO $30
+        0x02, // *JAM
+        0x00, 0xFF, // BRK, padding byte not shown
+        0xEA, // NOP
+    ];
+    let decoded: Vec<Decoded> = disasm::disassemble(code, 0xC000);
+    assert_eq!(
+        texts(&decoded),
+        vec![
+            "LDA #$42",
+            "LDA ($20),Y",
+            "LDA ($20,X)",
+            "LDA $10,X",
+            "LDX $10,Y",
+            "LDA $0300,X",
+            "LDA $0300,Y",
+            "JMP ($FFFC)",
+            "ASL A",
+            "INX",
+            "BNE $C013",
+            "STA $2000",
+            "SLO $30",
+            "JAM",
+            "BRK",
+            "NOP",
+        ]
+    );
+    assert_eq!(decoded[10].target(), Some(0xC013));
+    assert_eq!(decoded[12].to_string(), "C01A  07 30    *SLO $30");
+    assert_eq!(decoded[0].to_string(), "C000  A9 42     LDA #$42");
+    assert_eq!(decoded[14].to_string(), "C01D  00 FF     BRK");
+    assert_eq!(decoded[15].address, 0xC01F);
+}
+
+#[test]
+fn register_symbols() {
+    let code: [u8; 6] = [0x8D, 0x00, 0x20, 0xAD, 0x16, 0x40];
+    let symbols = disasm::register_symbols();
+    let decoded: Vec<String> = disasm::disassemble(&code, 0x8000)
+        .iter()
+        .map(|instruction| instruction.text_with_symbols(&symbols))
+        .collect();
+    assert_eq!(decoded, vec!["STA PPUCTRL", "LDA JOY1"]);
+}
+
+#[test]
+fn truncated_instruction_is_data() {
+    let decoded: Vec<Decoded> = disasm::disassemble(&[0xEA, 0x4C, 0x00], 0x8000);
+    assert_eq!(texts(&decoded), vec!["NOP", ".byte $4C, $00"]);
+    assert!(decoded[1].instruction.is_none());
+}
+
+#[test]
+fn disassembles_live_bus() {
+    let (cpu, memory) = common::cpu_with_flat_memory();
+    memory.borrow_mut().data[0x8000..0x8006].copy_from_slice(&[0xA2, 0x00, 0xCA, 0xD0, 0xFD, 0x60]);
+    let decoded: Vec<Decoded> = disasm::disassemble_bus(&cpu.bus, 0x8000, 0x8005);
+    assert_eq!(texts(&decoded), vec!["LDX #$00", "DEX", "BNE $8002", "RTS"]);
+    // Peeking leaves no trace on the devices
+    assert!(memory.borrow().accesses.is_empty());
+}
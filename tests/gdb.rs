@@ -0,0 +1,232 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use nes_emulator::cpu::asm::{self, Assembly};
+use nes_emulator::debugger::gdb::{Connection, GdbStub};
+
+// Registers and $0300 once the client is gone: A, X, Y, PC, SP, P, $0300
+type FinalState = (u8, u8, u8, u16, u8, u8, u8);
+
+// Run the stub on its own thread, the console is built there as it is not Send
+fn spawn_server<C, F>(accept: F) -> JoinHandle<FinalState>
+where
+    C: Connection,
+    F: FnOnce() -> C + Send + 'static,
+{
+    return thread::spawn(move || {
+        let (mut nes, _) = common::subroutine_nes();
+        GdbStub::new().serve(&mut nes, accept()).unwrap();
+        let r = &nes.cpu.registers;
+        return (
+            r.a,
+            r.x,
+            r.y,
+            r.pc,
+            r.sp,
+            r.status,
+            nes.cpu.bus.peek(0x0300).unwrap(),
+        );
+    });
+}
+
+fn spawn_tcp_server() -> (TcpStream, JoinHandle<FinalState>) {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = spawn_server(move || {
+        let stream: TcpStream = listener.accept().unwrap().0;
+        stream.set_nodelay(true).unwrap();
+        return stream;
+    });
+    let stream: TcpStream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    return (stream, server);
+}
+
+// Scripted client side of the protocol
+struct Client<S: Read + Write> {
+    stream: S,
+    ack: bool,
+}
+
+impl<S: Read + Write> Client<S> {
+    fn new(stream: S) -> Self {
+        return Self { stream, ack: true };
+    }
+
+    fn send(&mut self, packet: &str) {
+        let checksum: u8 = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+        if self.ack {
+            assert_eq!(self.byte(), b'+');
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte: [u8; 1] = [0x00];
+        self.stream.read_exact(&mut byte).unwrap();
+        return byte[0];
+    }
+
+    fn receive(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum: String = (0..2).map(|_| self.byte() as char).collect();
+        let expected: u8 = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(checksum, format!("{:02x}", expected));
+        if self.ack {
+            // The stub may already be gone after replying to a detach
+            let _ = self.stream.write_all(b"+");
+        }
+        return String::from_utf8(data).unwrap();
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        return self.receive();
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let (stream, server) = spawn_tcp_server();
+    let mut client = Client::new(stream);
+
+    assert!(client
+        .request("qSupported:swbreak+")
+        .contains("qXfer:features:read+"));
+    let xml: String = client.request("qXfer:features:read:target.xml:0,1000");
+    assert!(xml.starts_with('l') && xml.contains(r#"<reg name="pc" bitsize="16""#));
+    assert_eq!(client.request("?"), "S05");
+
+    // A X Y PC(lo hi) SP P right after reset
+    assert_eq!(client.request("g"), "0000000080fd24");
+    assert_eq!(client.request("P0=7f"), "OK");
+    assert_eq!(client.request("P3=3412"), "OK");
+    assert_eq!(client.request("p0"), "7f");
+    assert_eq!(client.request("p3"), "3412");
+    assert_eq!(client.request("p6"), "E01");
+    assert_eq!(client.request("P1=1234"), "E01");
+
+    assert_eq!(client.request("m8000,3"), "a20520");
+    assert_eq!(client.request("M0300,2:beef"), "OK");
+    assert_eq!(client.request("m02ff,4"), "00beef00");
+    assert_eq!(client.request("M0300,2:be"), "E01");
+
+    // ROM space is refused as a whole, writes there would switch banks
+    assert_eq!(client.request("M8000,1:ea"), "E01");
+    assert_eq!(client.request("M7fff,2:eaea"), "E01");
+    // So are the I/O registers, writes there have side effects
+    assert_eq!(client.request("M2006,1:3f"), "E01");
+    assert_eq!(client.request("M4014,1:02"), "E01");
+    assert_eq!(client.request("M1fff,2:0000"), "E01");
+    assert_eq!(client.request("M401f,1:00"), "E01");
+    assert_eq!(client.request("M4020,1:00"), "OK");
+    assert_eq!(client.request("m7fff,2"), "00a2");
+
+    assert_eq!(client.request("G01020300c0fb81"), "OK");
+    assert_eq!(client.request("g"), "01020300c0fb81");
+    assert_eq!(client.request("D"), "OK");
+
+    assert_eq!(
+        server.join().unwrap(),
+        (0x01, 0x02, 0x03, 0xC000, 0xFB, 0x81, 0xBE)
+    );
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let (stream, server) = spawn_tcp_server();
+    let mut client = Client::new(stream);
+    let assembly: Assembly = asm::assemble(common::SUBROUTINE_PROGRAM).unwrap();
+    let add: u16 = assembly.symbol("add").unwrap();
+    let done: u16 = assembly.symbol("done").unwrap();
+
+    assert_eq!(client.request(&format!("Z0,{:x},1", add)), "OK");
+    assert_eq!(client.request("c"), "T05swbreak:;");
+    assert_eq!(
+        client.request("p3"),
+        format!("{:02x}{:02x}", add & 0xFF, add >> 8)
+    );
+    assert_eq!(client.request("c"), "T05swbreak:;");
+    assert_eq!(client.request("p0"), "02");
+    assert_eq!(client.request(&format!("z0,{:x},1", add)), "OK");
+
+    // Single steps report a trap without a reason
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "02");
+
+    assert_eq!(client.request("Z2,300,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:0300;");
+    assert_eq!(
+        client.request("p3"),
+        format!("{:02x}{:02x}", done & 0xFF, done >> 8)
+    );
+    assert_eq!(client.request("m300,1"), "0a");
+    assert_eq!(client.request("z2,300,1"), "OK");
+
+    // Unsupported packets get an empty reply
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    client.send("k");
+
+    let state: FinalState = server.join().unwrap();
+    assert_eq!((state.0, state.1, state.6), (10, 0, 10));
+}
+
+#[test]
+fn interrupt_while_running() {
+    let (stream, server) = spawn_tcp_server();
+    let mut client = Client::new(stream);
+
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+
+    // The program ends in an endless loop, only an interrupt stops it
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.receive(), "S02");
+    assert_eq!(client.request("m300,1"), "0a");
+    client.send("k");
+
+    server.join().unwrap();
+}
+
+#[test]
+fn bad_checksums_are_rejected() {
+    let (stream, server) = spawn_tcp_server();
+    let mut client = Client::new(stream);
+
+    client.stream.write_all(b"$g#00").unwrap();
+    assert_eq!(client.byte(), b'-');
+    assert_eq!(client.request("g"), "0000000080fd24");
+
+    // Dropping the connection ends the session
+    drop(client);
+    server.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join(format!("nes_gdb_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener: UnixListener = UnixListener::bind(&path).unwrap();
+    let server = spawn_server(move || listener.accept().unwrap().0);
+    let mut client = Client::new(UnixStream::connect(&path).unwrap());
+
+    assert_eq!(client.request("m8000,2"), "a205");
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,163 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::FlatMemory;
+use nes_emulator::{StatusRegFlags, CPU};
+
+static NMI_HANDLER: u16 = 0x9000;
+static RESET_HANDLER: u16 = 0x8000;
+static IRQ_HANDLER: u16 = 0xA000;
+
+static B: u8 = StatusRegFlags::B as u8;
+static U: u8 = StatusRegFlags::U as u8;
+static I: u8 = StatusRegFlags::I as u8;
+
+// CPU after reset, with a distinct handler behind each vector. Memory is
+// all BRK until a program is stored.
+fn setup(program: &[u8]) -> (CPU, Rc<RefCell<FlatMemory>>) {
+    let (mut cpu, memory) = common::cpu_with_flat_memory();
+    {
+        let data: &mut Vec<u8> = &mut memory.borrow_mut().data;
+        data[0xFFFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0xA0]);
+        let start: usize = RESET_HANDLER as usize;
+        data[start..start + program.len()].copy_from_slice(program);
+        // NOPs in the handlers
+        data[NMI_HANDLER as usize] = 0xEA;
+        data[IRQ_HANDLER as usize] = 0xEA;
+    }
+    cpu.reset();
+    step(&mut cpu);
+    return (cpu, memory);
+}
+
+// Clock until the current instruction or interrupt sequence has finished
+fn step(cpu: &mut CPU) -> u8 {
+    let mut cycles: u8 = 0;
+    loop {
+        cpu.clock();
+        cycles += 1;
+        if cpu.complete() {
+            return cycles;
+        }
+    }
+}
+
+// Return address and status pushed by the last interrupt
+fn pushed(cpu: &CPU, memory: &Rc<RefCell<FlatMemory>>) -> (u16, u8) {
+    let data: &Vec<u8> = &memory.borrow().data;
+    let top: usize = 0x0100 + cpu.registers.sp as usize;
+    let address: u16 = data[top + 2] as u16 | (data[top + 3] as u16) << 8;
+    return (address, data[top + 1]);
+}
+
+#[test]
+fn irq_is_masked_by_the_i_flag() {
+    // NOP, CLI, NOP
+    let (mut cpu, memory) = setup(&[0xEA, 0x58, 0xEA]);
+    assert_eq!(cpu.registers.status & I, I);
+
+    cpu.set_irq_line(true);
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, 0x8001);
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, 0x8002);
+
+    // Taken at the next instruction boundary once I is clear
+    assert_eq!(step(&mut cpu), 7);
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(cpu.registers.sp, 0xFA);
+    assert_eq!(pushed(&cpu, &memory), (0x8002, U));
+    assert_eq!(cpu.registers.status & I, I);
+
+    // The line is still asserted but the handler runs with I set
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER + 1);
+}
+
+#[test]
+fn b_flag_is_only_set_when_pushed_by_instructions() {
+    // BRK with its padding byte, then PHP in the IRQ handler
+    let (mut cpu, memory) = setup(&[0x00, 0xFF]);
+    memory.borrow_mut().data[IRQ_HANDLER as usize] = 0x08;
+    assert_eq!(step(&mut cpu), 7);
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (0x8002, B | U | I));
+    step(&mut cpu);
+    assert_eq!(memory.borrow().data[0x01FA], B | U | I);
+
+    // Hardware interrupts push it clear, NMI ignores the I flag
+    cpu.registers.status = 0x00;
+    cpu.set_irq_line(true);
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (IRQ_HANDLER + 1, U));
+
+    cpu.trigger_nmi();
+    assert_eq!(step(&mut cpu), 7);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (IRQ_HANDLER, U | I));
+    // Serviced once per edge
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+}
+
+#[test]
+fn nmi_takes_priority_over_irq() {
+    let (mut cpu, memory) = setup(&[0x58, 0xEA]);
+    step(&mut cpu);
+    cpu.set_irq_line(true);
+    cpu.trigger_nmi();
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (0x8001, U));
+}
+
+#[test]
+fn nmi_hijacks_brk_and_irq_before_the_vector_fetch() {
+    // An NMI during BRK keeps the B flag BRK pushed but jumps to the NMI
+    // handler, and is not serviced again afterwards
+    let (mut cpu, memory) = setup(&[0x00, 0xFF]);
+    cpu.clock();
+    cpu.trigger_nmi();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (0x8002, B | U | I));
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+
+    // Too late in the sequence, the NMI interrupts the BRK handler's first
+    // instruction instead
+    let (mut cpu, memory) = setup(&[0x00, 0xFF]);
+    for _ in 0..5 {
+        cpu.clock();
+    }
+    assert_eq!(cpu.cycles, 2);
+    cpu.trigger_nmi();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (IRQ_HANDLER, U | I));
+
+    // Same for a hardware IRQ, which pushed B clear
+    let (mut cpu, memory) = setup(&[0x58, 0xEA]);
+    step(&mut cpu);
+    cpu.set_irq_line(true);
+    cpu.clock();
+    cpu.trigger_nmi();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu, &memory), (0x8001, U));
+    cpu.set_irq_line(false);
+    step(&mut cpu);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+}
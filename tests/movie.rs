@@ -0,0 +1,188 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::movie::fm2::{Frame, Movie, MovieError, COMMAND_POWER, COMMAND_RESET};
+use nes_emulator::movie::playback::{self, Player, Recorder};
+use nes_emulator::Nes;
+
+// The demo's NMI handler counts frames with A held in $21
+fn input(frame: usize) -> Frame {
+    return Frame {
+        commands: match frame {
+            20 => COMMAND_RESET,
+            45 => COMMAND_POWER,
+            _ => 0x00,
+        },
+        buttons: [(frame % 3 == 0) as u8, (frame as u8).wrapping_mul(37)],
+    };
+}
+
+fn record(nes: &mut Nes, mut recorder: Recorder, frames: usize) -> Movie {
+    for frame in 0..frames {
+        recorder.step(nes, input(frame));
+    }
+    return recorder.finish(nes);
+}
+
+#[test]
+fn movies_from_power_on_replay_exactly() {
+    // Leftovers from an earlier run are erased
+    let mut nes: Nes = common::demo_nes();
+    for _ in 0..10 {
+        nes.run_frame();
+    }
+    let recorder: Recorder = Recorder::from_power_on(&mut nes);
+    let movie: Movie = record(&mut nes, recorder, 70);
+    assert!(nes.cpu.bus.peek(0x0021).unwrap() > 0);
+    assert_eq!(movie.frames.len(), 70);
+    assert_eq!(movie.hashes.keys().copied().collect::<Vec<_>>(), [60, 70]);
+    assert_eq!(movie.savestate, None);
+
+    // Through the text format, on a fresh console
+    let movie: Movie = Movie::parse(&movie.to_string()).unwrap();
+    let mut other: Nes = common::demo_nes();
+    let mut player: Player = Player::start(movie, &mut other).unwrap();
+    player.play(&mut other).unwrap();
+    assert!(player.finished());
+    assert_eq!(player.frame(), 70);
+    assert_eq!(other.save_state(), nes.save_state());
+    assert!(!player.step(&mut other).unwrap());
+}
+
+#[test]
+fn movies_from_a_save_state_replay_exactly() {
+    let mut nes: Nes = common::demo_nes();
+    for _ in 0..5 {
+        nes.run_frame();
+    }
+    for _ in 0..1234 {
+        nes.clock();
+    }
+    let start: Vec<u8> = nes.save_state();
+    let mut recorder: Recorder = Recorder::from_state(&nes);
+    recorder.hash_interval = 7;
+    let movie: Movie = record(&mut nes, recorder, 30);
+    assert_eq!(movie.savestate.as_ref(), Some(&start));
+    assert_eq!(movie.hashes.len(), 5);
+
+    let movie: Movie = Movie::parse(&movie.to_string()).unwrap();
+    let mut other: Nes = common::demo_nes();
+    Player::start(movie, &mut other)
+        .unwrap()
+        .play(&mut other)
+        .unwrap();
+    assert_eq!(other.save_state(), nes.save_state());
+}
+
+#[test]
+fn fm2_movies_are_imported() {
+    let text: &str = "version 3\r\n\
+        emuVersion 22020\r\n\
+        rerecordCount 12\r\n\
+        palFlag 0\r\n\
+        romFilename demo\r\n\
+        romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\r\n\
+        guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\r\n\
+        fourscore 0\r\n\
+        microphone 0\r\n\
+        port0 1\r\n\
+        port1 0\r\n\
+        port2 0\r\n\
+        FDS 0\r\n\
+        NewPPU 0\r\n\
+        comment author Someone\r\n\
+        |1|........|||\r\n\
+        |0|R..U...A|||\r\n\
+        |0|RLDUTSBA|||\r\n\
+        |2|   UT   |||\r\n";
+    let movie: Movie = Movie::parse(text).unwrap();
+    assert_eq!(movie.gamepads, [true, false]);
+    assert_eq!(movie.rom_hash, None);
+    assert!(movie.hashes.is_empty());
+    let frames: Vec<(u8, u8)> = movie
+        .frames
+        .iter()
+        .map(|frame| (frame.commands, frame.buttons[0]))
+        .collect();
+    assert_eq!(frames, [(1, 0x00), (0, 0x91), (0, 0xFF), (2, 0x18)]);
+    assert!(movie
+        .header
+        .contains(&(String::from("comment"), String::from("author Someone"))));
+
+    // Written back in FM2 form, keeping the rest of the header
+    let written: String = movie.to_string();
+    assert!(written.starts_with("version 3\n"));
+    assert!(written.contains("\nguid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n"));
+    assert!(written.contains("\nport1 0\n"));
+    assert!(written.ends_with("\n|0|R..U...A|||\n|0|RLDUTSBA|||\n|2|...UT...|||\n"));
+    assert_eq!(Movie::parse(&written).unwrap(), movie);
+
+    for unsupported in [
+        "binary 1",
+        "fourscore 1",
+        "palFlag 1",
+        "port0 2",
+        "port2 1",
+        "version 2",
+        "|4|........|........||",
+        "|8|........|........||",
+        "|16|........|........||",
+        "|17|........|........||",
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            Movie::parse(unsupported),
+            Err(MovieError::Unsupported(_))
+        ));
+    }
+    for (invalid, line) in [
+        ("port1 0\n|0|........|.......A||", 2),
+        ("|0|.......||", 1),
+        ("\n|x|........|........||", 2),
+        ("|32|........|........||", 1),
+        ("|0|........|........|A|", 1),
+        ("savestate base64:A", 1),
+        ("frameHash 60", 1),
+    ]
+    .iter()
+    {
+        match Movie::parse(invalid) {
+            Err(MovieError::Parse { line: found, .. }) => assert_eq!(found, *line, "{}", invalid),
+            other => panic!("{} parsed as {:?}", invalid, other),
+        }
+    }
+}
+
+#[test]
+fn desyncs_are_detected() {
+    let mut nes: Nes = common::demo_nes();
+    let mut recorder: Recorder = Recorder::from_power_on(&mut nes);
+    recorder.hash_interval = 10;
+    let movie: Movie = record(&mut nes, recorder, 30);
+
+    // An extra press of A changes $21 from the next frame on
+    let mut edited: Movie = movie.clone();
+    edited.frames[14].buttons[0] = 0x01;
+    let mut other: Nes = common::demo_nes();
+    let mut player: Player = Player::start(edited, &mut other).unwrap();
+    match player.play(&mut other) {
+        Err(MovieError::Desync {
+            frame,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(frame, 20);
+            assert_eq!(expected, movie.hashes[&20]);
+            assert_eq!(actual, playback::frame_hash(&other));
+        }
+        other => panic!("Expected a desync, got {:?}", other),
+    }
+
+    // Another ROM
+    let mut other: Nes = common::nrom_nes(&common::DEMO_PROGRAM.replace("INC $10", "INC $12"));
+    assert!(matches!(
+        Player::start(movie, &mut other),
+        Err(MovieError::RomMismatch)
+    ));
+}
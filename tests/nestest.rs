@@ -0,0 +1,87 @@
+#![allow(clippy::needless_return)]
+// Runs kevtris' nestest ROM in automation mode (starting at $C000 with no
+// PPU) and compares the trace against the reference nestest.log line by
+// line. The ROM and the log aren't distributed with the repository, so the
+// test is ignored by default: put them in tests/data and run it with
+// cargo test --test nestest -- --ignored.
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use nes_emulator::cpu::trace::Tracer;
+use nes_emulator::Nes;
+
+static AUTOMATION_START: u16 = 0xC000;
+
+fn data_file(name: &str) -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name);
+}
+
+// Registers and cycle count of a trace line, the PPU position is left out as
+// the reference log was made with a different PPU power up timing
+fn comparable(line: &str) -> (String, String) {
+    let cpu_state: String = match line.find("PPU:") {
+        Some(index) => line[..index].trim_end().to_string(),
+        None => line.trim_end().to_string(),
+    };
+    let cycles: String = match line.find("CYC:") {
+        Some(index) => line[index..].trim_end().to_string(),
+        None => String::new(),
+    };
+    return (cpu_state, cycles);
+}
+
+#[test]
+#[ignore = "needs tests/data/nestest.nes and nestest.log, which are not checked in"]
+fn nestest_matches_reference_log() {
+    let (rom, log) = (data_file("nestest.nes"), data_file("nestest.log"));
+    assert!(
+        rom.exists() && log.exists(),
+        "tests/data/nestest.nes and nestest.log not found"
+    );
+    let expected: Vec<String> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+
+    let lines: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let mut nes: Nes = Nes::new();
+    nes.load_rom(&rom).unwrap();
+    let sink: Rc<RefCell<Vec<String>>> = lines.clone();
+    nes.set_tracer(Some(Tracer::to_callback(move |line: &str| {
+        sink.borrow_mut().push(line.to_string());
+    })));
+    nes.cpu.registers.pc = AUTOMATION_START;
+
+    while lines.borrow().len() <= expected.len() {
+        nes.step_instruction();
+    }
+
+    let lines = lines.borrow();
+    for (number, (got, want)) in lines.iter().zip(expected.iter()).enumerate() {
+        assert_eq!(
+            comparable(got),
+            comparable(want),
+            "nestest.log line {} differs\n got: {}\nwant: {}",
+            number + 1,
+            got,
+            want
+        );
+    }
+
+    // Results of the official and unofficial opcode tests
+    assert_eq!(
+        nes.cpu.bus.peek(0x0002),
+        Some(0x00),
+        "Official opcode tests failed"
+    );
+    assert_eq!(
+        nes.cpu.bus.peek(0x0003),
+        Some(0x00),
+        "Unofficial opcode tests failed"
+    );
+}
@@ -0,0 +1,132 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::savestate::compress;
+use nes_emulator::savestate::rewind::Rewind;
+use nes_emulator::Nes;
+
+#[test]
+fn compression_round_trips() {
+    let mut random: Vec<u8> = Vec::new();
+    let mut seed: u32 = 1;
+    for _ in 0..5000 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        random.push((seed >> 16) as u8);
+    }
+    let pixels: Vec<u8> = [0x12, 0x34, 0x56].repeat(10_000);
+    let mut mixed: Vec<u8> = vec![0x00; 3000];
+    mixed.extend_from_slice(&random[..100]);
+    mixed.extend_from_slice(&pixels[..999]);
+    mixed.extend_from_slice(&random);
+
+    for data in [
+        vec![],
+        vec![7],
+        vec![0x00; 100_000],
+        random,
+        pixels.clone(),
+        mixed,
+    ]
+    .iter()
+    {
+        assert_eq!(
+            compress::decompress(&compress::compress(data), data.len()).as_ref(),
+            Some(data)
+        );
+    }
+    assert!(compress::compress(&vec![0x00; 100_000]).len() < 32);
+    assert!(compress::compress(&pixels).len() < 32);
+
+    assert_eq!(compress::decompress(&[0x05, 0x01], 100), None);
+    assert_eq!(compress::decompress(&[0x01, 0xAA, 0x00, 0x02], 100), None);
+
+    // Output past the limit is refused, before it is produced
+    let zeros: Vec<u8> = compress::compress(&vec![0x00; 100_000]);
+    assert_eq!(compress::decompress(&zeros, 99_999), None);
+    assert_eq!(compress::decompress(&[0x03, 1, 2, 3], 2), None);
+    let huge: [u8; 9] = [0x01, 0xAA, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x01, 0x00];
+    assert_eq!(compress::decompress(&huge, 1 << 20), None);
+}
+
+#[test]
+fn step_back_a_frame_at_a_time() {
+    let mut nes: Nes = common::demo_nes();
+    let mut rewind: Rewind = Rewind::default();
+    rewind.keyframe_interval = 8;
+    let mut states: Vec<Vec<u8>> = Vec::new();
+    for _ in 0..30 {
+        nes.run_frame();
+        rewind.capture(&nes);
+        states.push(nes.save_state());
+    }
+    assert_eq!(rewind.len(), 30);
+
+    // Deltas against the keyframes are much smaller than the states
+    assert!(rewind.size() < states.iter().map(Vec::len).sum::<usize>() / 10);
+
+    for expected in states[..29].iter().rev() {
+        assert!(rewind.step_back(&mut nes).unwrap());
+        assert_eq!(&nes.save_state(), expected);
+    }
+    assert_eq!(rewind.len(), 1);
+    assert!(!rewind.step_back(&mut nes).unwrap());
+    assert_eq!(nes.save_state(), states[0]);
+}
+
+#[test]
+fn recording_continues_after_rewinding() {
+    let mut nes: Nes = common::demo_nes();
+    let mut rewind: Rewind = Rewind::default();
+    rewind.keyframe_interval = 4;
+    for _ in 0..10 {
+        nes.run_frame();
+        rewind.capture(&nes);
+    }
+
+    // Back to the start of a group, then down a different timeline
+    for _ in 0..6 {
+        rewind.step_back(&mut nes).unwrap();
+    }
+    assert_eq!(rewind.len(), 4);
+    let mut states: Vec<Vec<u8>> = vec![nes.save_state()];
+    nes.controller(0).unwrap().set_buttons(0xFF);
+    for _ in 0..6 {
+        nes.run_frame();
+        rewind.capture(&nes);
+        states.push(nes.save_state());
+    }
+
+    assert_eq!(rewind.len(), 10);
+    for expected in states[..6].iter().rev() {
+        rewind.step_back(&mut nes).unwrap();
+        assert_eq!(&nes.save_state(), expected);
+    }
+}
+
+#[test]
+fn memory_is_bounded() {
+    let mut nes: Nes = common::demo_nes();
+    nes.run_frame();
+    let state: Vec<u8> = nes.save_state();
+    let mut probe: Rewind = Rewind::default();
+    probe.push(&state);
+
+    // Room for about three keyframes
+    let budget: usize = probe.size() * 3;
+    let mut rewind: Rewind = Rewind::new(budget);
+    rewind.keyframe_interval = 1;
+    let mut last: Vec<u8> = Vec::new();
+    for _ in 0..20 {
+        nes.run_frame();
+        last = nes.save_state();
+        rewind.push(&last);
+        assert!(rewind.size() <= budget);
+    }
+    assert!(rewind.len() < 20);
+    assert_eq!(rewind.latest(), Ok(Some(last)));
+
+    rewind.clear();
+    assert!(rewind.is_empty());
+    assert_eq!(rewind.size(), 0);
+    assert_eq!(rewind.pop(), Ok(None));
+}
@@ -0,0 +1,103 @@
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use nes_emulator::cartridge::cartridge::Cartridge;
+use nes_emulator::Nes;
+
+// Counts frames in $10 from its NMI handler
+#[rustfmt::skip]
+static PROGRAM: [u8; 11] = [
+    0xA9, 0x80,         // $8000 reset: LDA #$80
+    0x8D, 0x00, 0x20,   // $8002        STA $2000
+    0x4C, 0x05, 0x80,   // $8005 loop:  JMP loop
+    0xE6, 0x10,         // $8008 nmi:   INC $10
+    0x40,               // $800A        RTI
+];
+
+// iNES image of an NROM board with 8KB of CHR RAM and PROGRAM at $8000
+fn image() -> Vec<u8> {
+    let mut prg: Vec<u8> = PROGRAM.to_vec();
+    prg.resize(0x8000, 0x00);
+    prg[0x7FFA..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+    let mut image: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, 2, 0, 0x01];
+    image.resize(16, 0x00);
+    image.extend_from_slice(&prg);
+    return image;
+}
+
+// Empty directory for a run, which is also where the runner puts its logs
+fn work_directory(name: &str) -> PathBuf {
+    let directory: PathBuf =
+        std::env::temp_dir().join(format!("nes_runner_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("count.nes"), image()).unwrap();
+    return directory;
+}
+
+// Exit code of the emulator run with the arguments in the directory
+fn run(directory: &Path, arguments: &[&str]) -> i32 {
+    let status: ExitStatus = Command::new(env!("CARGO_BIN_EXE_nes_emulator"))
+        .args(arguments)
+        .current_dir(directory)
+        .output()
+        .unwrap()
+        .status;
+    return status.code().unwrap();
+}
+
+// Byte of RAM from a --dump-ram file
+fn dumped(path: &Path, address: usize) -> u8 {
+    let dump: String = fs::read_to_string(path).unwrap();
+    let line: &str = dump.lines().nth(address / 16).unwrap();
+    let byte: &str = line.split_whitespace().nth(1 + address % 16).unwrap();
+    return u8::from_str_radix(byte, 16).unwrap();
+}
+
+// Value of $10 after a number of frames on the library's own console
+fn frames_counted(frames: usize) -> u8 {
+    let mut nes: Nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&image()).unwrap());
+    for _ in 0..frames {
+        nes.run_frame();
+    }
+    return nes.cpu.bus.peek(0x0010).unwrap();
+}
+
+#[test]
+fn runs_stop_at_the_frame_limit() {
+    let directory: PathBuf = work_directory("limit");
+    let arguments: [&str; 5] = ["count.nes", "--frames", "4", "--dump-ram", "ram.txt"];
+    assert_eq!(run(&directory, &arguments), 0);
+    let counted: u8 = dumped(&directory.join("ram.txt"), 0x10);
+    assert_eq!(counted, frames_counted(4));
+    assert_ne!(counted, frames_counted(5));
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn exit_codes_report_the_result() {
+    let directory: PathBuf = work_directory("exit");
+    let exit_code = |arguments: &str| -> i32 {
+        return run(&directory, &arguments.split(' ').collect::<Vec<&str>>());
+    };
+
+    // Passed, by reaching the stop condition with the expectations holding
+    assert_eq!(
+        exit_code("count.nes --frames 100 --until-mem $10=$20 --expect-mem $10=$20"),
+        0
+    );
+    // Failed expectation
+    assert_eq!(exit_code("count.nes --frames 4 --expect-mem $10=$20"), 1);
+    // Timed out, the frame limit came first
+    assert_eq!(exit_code("count.nes --frames 4 --until-mem $10=$20"), 2);
+    assert_eq!(exit_code("count.nes --frames 4 --until-pc $1234"), 2);
+    // Errors
+    assert_eq!(exit_code("missing.nes --frames 4"), 3);
+    assert_eq!(exit_code("count.nes --frames four"), 3);
+    fs::remove_dir_all(&directory).unwrap();
+}
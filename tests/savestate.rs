@@ -0,0 +1,156 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use nes_emulator::savestate::savestate::{StateError, FORMAT_VERSION};
+use nes_emulator::{Button, Nes};
+
+// Run a number of frames plus some cycles, so the machine stops mid-frame
+// and usually mid-instruction
+fn run(nes: &mut Nes, frames: u32, cycles: u32) {
+    for _ in 0..frames {
+        nes.run_frame();
+    }
+    for _ in 0..cycles {
+        nes.clock();
+    }
+}
+
+fn samples(nes: &mut Nes) -> Vec<f32> {
+    let mut samples: Vec<f32> = vec![0.0; 44100];
+    let count: usize = nes.audio_samples(&mut samples);
+    samples.truncate(count);
+    return samples;
+}
+
+#[test]
+fn loading_resumes_deterministically() {
+    let mut nes: Nes = common::demo_nes();
+    nes.controller(0).unwrap().set_button(Button::A, true);
+    run(&mut nes, 20, 12_345);
+    let state: Vec<u8> = nes.save_state();
+    samples(&mut nes);
+
+    run(&mut nes, 3, 777);
+    let expected: Vec<u8> = nes.save_state();
+    let expected_samples: Vec<f32> = samples(&mut nes);
+    assert!(nes.cpu.bus.peek(0x0021).unwrap() > 0);
+    assert!(!expected_samples.is_empty());
+
+    // A fresh console with the same cartridge
+    let mut other: Nes = common::demo_nes();
+    other.load_state(&state).unwrap();
+    assert_eq!(other.save_state(), state);
+    run(&mut other, 3, 777);
+    assert_eq!(other.save_state(), expected);
+    assert_eq!(samples(&mut other), expected_samples);
+    assert_eq!(&*other.framebuffer(), &*nes.framebuffer());
+
+    // Going back in time on the original
+    nes.load_state(&state).unwrap();
+    run(&mut nes, 3, 777);
+    assert_eq!(nes.save_state(), expected);
+}
+
+#[test]
+fn invalid_states_are_rejected() {
+    let mut nes: Nes = common::demo_nes();
+    run(&mut nes, 2, 100);
+    let state: Vec<u8> = nes.save_state();
+    run(&mut nes, 1, 0);
+    let current: Vec<u8> = nes.save_state();
+
+    assert_eq!(nes.load_state(b"garbage"), Err(StateError::InvalidMagic));
+
+    let mut newer: Vec<u8> = state.clone();
+    newer[8..10].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        nes.load_state(&newer),
+        Err(StateError::UnsupportedVersion(FORMAT_VERSION + 1))
+    );
+
+    // Another ROM
+    let mut other: Nes = common::nrom_nes(&common::DEMO_PROGRAM.replace("INC $10", "INC $12"));
+    assert_eq!(other.load_state(&state), Err(StateError::RomMismatch));
+    assert_eq!(Nes::new().load_state(&state), Err(StateError::RomMismatch));
+
+    // The BUS chunk is last, shrinking it cuts off the devices
+    let mut truncated: Vec<u8> = state.clone();
+    truncated.truncate(state.len() - 100);
+    let bus: usize = find_chunk(&truncated, b"BUS ");
+    let length: u32 = (truncated.len() - bus - 8) as u32;
+    truncated[bus + 4..bus + 8].copy_from_slice(&length.to_le_bytes());
+    assert_eq!(
+        nes.load_state(&truncated),
+        Err(StateError::Truncated("BUS "))
+    );
+
+    // Failed loads leave the machine alone
+    assert_eq!(nes.save_state(), current);
+}
+
+#[test]
+fn corrupted_states_are_rejected() {
+    let mut nes: Nes = common::demo_nes();
+    run(&mut nes, 2, 0);
+    let state: Vec<u8> = nes.save_state();
+    let apu: usize = apu_section(&state);
+
+    // Values that would send the APU out of its tables, at their offsets in
+    // the APU section
+    let corruptions: [(usize, &[u8], &str); 5] = [
+        (9, &[4], "pulse duty"),
+        (10, &[8], "pulse sequence step"),
+        (45, &[32], "triangle sequence step"),
+        (68, &[0, 0], "noise period"),
+        (75, &[0, 0], "DMC period"),
+    ];
+    for (offset, value, what) in corruptions.iter() {
+        let mut corrupted: Vec<u8> = state.clone();
+        corrupted[apu + offset..apu + offset + value.len()].copy_from_slice(value);
+        assert_eq!(nes.load_state(&corrupted), Err(StateError::Invalid(what)));
+        assert_eq!(nes.save_state(), state);
+    }
+}
+
+#[test]
+fn unknown_chunks_are_skipped() {
+    let mut nes: Nes = common::demo_nes();
+    run(&mut nes, 2, 0);
+    let state: Vec<u8> = nes.save_state();
+
+    // As written by a newer build with more state
+    let mut newer: Vec<u8> = state.clone();
+    newer.extend_from_slice(b"NEW ");
+    newer.extend_from_slice(&3u32.to_le_bytes());
+    newer.extend_from_slice(&[1, 2, 3]);
+
+    let mut other: Nes = common::demo_nes();
+    other.load_state(&newer).unwrap();
+    assert_eq!(other.save_state(), state);
+}
+
+// Offset of a chunk's tag
+fn find_chunk(state: &[u8], tag: &[u8; 4]) -> usize {
+    let mut offset: usize = 18;
+    loop {
+        if &state[offset..offset + 4] == tag {
+            return offset;
+        }
+        let mut length: [u8; 4] = [0x00; 4];
+        length.copy_from_slice(&state[offset + 4..offset + 8]);
+        offset += 8 + u32::from_le_bytes(length) as usize;
+    }
+}
+
+// Offset of the APU's state, in the device sections of the BUS chunk after
+// the RAM, the OAM DMA page, the open bus value and the number of devices.
+// The PPU and the controller ports come before it.
+fn apu_section(state: &[u8]) -> usize {
+    let mut offset: usize = find_chunk(state, b"BUS ") + 8 + 0x800 + 3 + 4;
+    for _ in 0..2 {
+        let mut length: [u8; 4] = [0x00; 4];
+        length.copy_from_slice(&state[offset..offset + 4]);
+        offset += 4 + u32::from_le_bytes(length) as usize;
+    }
+    return offset + 4;
+}
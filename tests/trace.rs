@@ -0,0 +1,86 @@
+#![allow(clippy::needless_return)]
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::FlatMemory;
+use nes_emulator::cpu::trace::{self, Tracer};
+use nes_emulator::CPU;
+
+// CPU at pc with the instruction bytes stored there, registers as at the
+// start of nestest
+fn setup(pc: u16, bytes: &[u8]) -> (CPU, Rc<RefCell<FlatMemory>>) {
+    let (mut cpu, memory) = common::cpu_with_flat_memory();
+    let start: usize = pc as usize;
+    memory.borrow_mut().data[start..start + bytes.len()].copy_from_slice(bytes);
+    cpu.registers.pc = pc;
+    cpu.registers.sp = 0xFD;
+    cpu.registers.status = 0x24;
+    cpu.cpu_cycles = 7;
+    return (cpu, memory);
+}
+
+#[test]
+fn lines_match_nestest_log() {
+    // The first line of nestest.log
+    let (cpu, _memory) = setup(0xC000, &[0x4C, 0xF5, 0xC5]);
+    assert_eq!(
+        trace::format_line(&cpu, Some((0, 21))),
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+    );
+    assert_eq!(
+        trace::format_line(&cpu, None),
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+    );
+
+    // Effective addresses and the values found there are resolved
+    let (mut cpu, memory) = setup(0xD959, &[0xA1, 0x80]);
+    {
+        let data: &mut Vec<u8> = &mut memory.borrow_mut().data;
+        data[0x0082..0x0084].copy_from_slice(&[0x00, 0x02]);
+        data[0x0200] = 0x5A;
+    }
+    cpu.registers.x = 0x02;
+    cpu.registers.y = 0x69;
+    cpu.cpu_cycles = 8838;
+    assert_eq!(
+        trace::format_line(&cpu, Some((77, 131))),
+        "D959  A1 80     LDA ($80,X) @ 82 = 0200 = 5A    A:00 X:02 Y:69 P:24 SP:FD PPU: 77,131 CYC:8838"
+    );
+
+    // Unofficial opcodes are marked with a '*'
+    let (cpu, _memory) = setup(0xE54E, &[0x1C, 0xA9, 0xA9]);
+    assert_eq!(
+        trace::format_line(&cpu, Some((241, 5))),
+        "E54E  1C A9 A9 *NOP $A9A9,X @ A9A9 = 00         A:00 X:00 Y:00 P:24 SP:FD PPU:241,  5 CYC:7"
+    );
+}
+
+#[test]
+fn tracer_sends_a_line_per_instruction() {
+    // STX $0300, NOP, NOP
+    let (mut cpu, _memory) = setup(0xC000, &[0x8E, 0x00, 0x03, 0xEA, 0xEA]);
+    cpu.registers.x = 0x10;
+    let lines: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let sink: Rc<RefCell<Vec<String>>> = lines.clone();
+    cpu.set_tracer(Some(Tracer::to_callback(move |line: &str| {
+        sink.borrow_mut().push(line.to_string());
+    })));
+    for _ in 0..4 + 2 + 2 {
+        cpu.clock();
+    }
+    assert_eq!(
+        *lines.borrow(),
+        [
+            "C000  8E 00 03  STX $0300 = 00                  A:00 X:10 Y:00 P:24 SP:FD CYC:7",
+            "C003  EA        NOP                             A:00 X:10 Y:00 P:24 SP:FD CYC:11",
+            "C004  EA        NOP                             A:00 X:10 Y:00 P:24 SP:FD CYC:13",
+        ]
+    );
+
+    // Nothing is traced while disabled
+    cpu.set_trace_enabled(false);
+    cpu.clock();
+    assert_eq!(lines.borrow().len(), 3);
+}